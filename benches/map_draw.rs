@@ -0,0 +1,89 @@
+//! Draw preparation cost for larger_map.tmx.
+//!
+//! Compares building the sprite batches of every visible chunk each frame
+//! against the revision cache kept by `MapSprites`, while the camera pans
+//! across the map and a tile is written every second.
+//!
+//! Needs a window for the tileset images.
+//! Run with `cargo bench --bench map_draw`.
+extern crate ggez;
+extern crate rpg;
+extern crate tiled;
+
+use ggez::graphics::{self, Rect};
+use ggez::{conf, Context, ContextBuilder};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rpg::map::{Map, MapSprites};
+use rpg::util::load_tile_map;
+
+const FRAMES: usize = 2000;
+
+fn load_map(ctx: &mut Context) -> (tiled::Map, Map) {
+    let map_def = load_tile_map(ctx, "/larger_map.tmx").expect("failed to load larger_map.tmx");
+    let map = Map::new(&map_def, 0);
+    (map_def, map)
+}
+
+// pans right then down, wrapping at the map edge
+fn move_camera(map: &mut Map, frame: usize) {
+    let x = (frame % 1600) as f32;
+    let y = ((frame / 1600) * 64 % 800) as f32;
+    map.set_camera(Rect::new(x, y, 256.0, 224.0));
+}
+
+fn per_frame(elapsed: Duration) -> f64 {
+    let nanos = elapsed.as_secs() as f64 * 1e9 + elapsed.subsec_nanos() as f64;
+    nanos / FRAMES as f64 / 1000.0
+}
+
+fn main() {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources");
+    let ctx = &mut ContextBuilder::new("map_draw", "ggez")
+        .window_mode(conf::WindowMode::default().dimensions(256, 224))
+        .add_resource_path(resources)
+        .build()
+        .expect("failed to create a context");
+    graphics::set_default_filter(ctx, graphics::FilterMode::Nearest);
+
+    let (map_def, mut map) = load_map(ctx);
+    let sprites = MapSprites::new(ctx, &map_def, "/").expect("failed to load tileset images");
+    let images: Vec<_> = (0..map_def.tilesets.len())
+        .map(|i| sprites.tileset_image(i).cloned())
+        .collect();
+
+    // a fresh cache every frame builds every visible chunk
+    let mut built = 0;
+    let start = Instant::now();
+    for frame in 0..FRAMES {
+        move_camera(&mut map, frame);
+        if frame % 60 == 0 {
+            map.write_tile(frame % 128, 10, 0, 1, None, false);
+        }
+        let mut fresh = MapSprites::from_images(&map_def, images.clone());
+        map.setup_sprites(&mut fresh);
+        built += fresh.rebuilds();
+    }
+    println!(
+        "rebuild every frame: {:8.2} us/frame, {} chunk rebuilds",
+        per_frame(start.elapsed()),
+        built
+    );
+
+    let (_, mut map) = load_map(ctx);
+    let mut cached = MapSprites::from_images(&map_def, images);
+    let start = Instant::now();
+    for frame in 0..FRAMES {
+        move_camera(&mut map, frame);
+        if frame % 60 == 0 {
+            map.write_tile(frame % 128, 10, 0, 1, None, false);
+        }
+        map.setup_sprites(&mut cached);
+    }
+    println!(
+        "cached chunks:       {:8.2} us/frame, {} chunk rebuilds",
+        per_frame(start.elapsed()),
+        cached.rebuilds()
+    );
+}
@@ -3,10 +3,10 @@ extern crate rpg;
 extern crate tiled;
 
 use ggez::conf;
-use ggez::event::{self, EventHandler, Keycode, Mod};
+use ggez::event::{self, EventHandler, Keycode, Mod, MouseButton};
 use ggez::graphics;
 use ggez::graphics::spritebatch::SpriteBatch;
-use ggez::graphics::{Point2, Rect};
+use ggez::graphics::{Color, DrawMode, Point2, Rect};
 use ggez::timer;
 use ggez::{Context, ContextBuilder, GameResult};
 
@@ -16,16 +16,21 @@ use std::path;
 use rpg::anim;
 use rpg::entity::Entity;
 use rpg::input::InputState;
-use rpg::map::{uvs_from_tiled, Map};
+use rpg::map::{Map, MapSprites};
+use rpg::screen::{ScaleMode, VirtualScreen};
 use rpg::sprite::Sprite;
 use rpg::state;
+use rpg::timestep::FixedTimestep;
 use rpg::tween;
 use rpg::util::{self, load_tile_map};
 
 const DESIRED_FPS: u32 = 60;
+// logical resolution, scaled up to fit the window
+const SCREEN_WIDTH: u32 = 256;
+const SCREEN_HEIGHT: u32 = 224;
 
 struct SharedState {
-    map_sprite: Sprite,
+    map_sprites: MapSprites,
     player_sprite: Sprite,
     map: Map,
     player: Entity,
@@ -149,24 +154,32 @@ impl<'a> state::State<SharedState> for MoveState {
 }
 
 struct MainState<'a> {
+    screen: VirtualScreen,
+    timestep: FixedTimestep,
+    // tiled coordinates of the last tile clicked, outlined over the map
+    clicked: Option<(i32, i32)>,
     shared_state: SharedState,
     state_machine: state::StateMachine<'a, SharedState>,
 }
 
 impl<'a> MainState<'a> {
     pub fn new(
-        map_sprite: Sprite,
+        screen: VirtualScreen,
+        map_sprites: MapSprites,
         player_sprite: Sprite,
         mut map: Map,
         mut player: Entity,
     ) -> GameResult<MainState<'a>> {
-        let camera = Rect::new(0.0, 0.0, map.pixel_dimensions.x, map.pixel_dimensions.y);
-        map.camera = camera;
+        let camera = Rect::new(0.0, 0.0, SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32);
+        map.set_camera(camera);
         player.teleport(10, 4, &map);
 
         Ok(MainState {
+            screen,
+            timestep: FixedTimestep::new(DESIRED_FPS),
+            clicked: None,
             shared_state: SharedState {
-                map_sprite,
+                map_sprites,
                 player_sprite,
 
                 map,
@@ -181,7 +194,10 @@ impl<'a> MainState<'a> {
 
 impl<'a> EventHandler for MainState<'a> {
     fn update<'b>(&mut self, ctx: &'b mut Context) -> GameResult<()> {
-        while timer::check_update_time(ctx, DESIRED_FPS) {
+        self.timestep.advance_duration(timer::get_delta(ctx));
+        while self.timestep.tick() {
+            // drawing lerps from here to where this update leaves the player
+            self.shared_state.player.store_previous_pos();
             let sm = &mut self.state_machine;
             if sm.is_running() {
                 sm.update(state::StateData::new(&mut self.shared_state));
@@ -212,23 +228,38 @@ impl<'a> EventHandler for MainState<'a> {
         Ok(())
     }
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        graphics::clear(ctx);
+        self.screen.begin(ctx)?;
         for i in 0..self.shared_state.map.layer_count() {
             self.shared_state.map.layer_index = i;
             {
                 let s = self.shared_state
-                    .map_sprite
+                    .map_sprites
                     .with_context(&self.shared_state.map);
                 graphics::draw(ctx, &s, Point2::new(0.0, 0.0), 0.0);
             }
             if i == self.shared_state.player.layer {
                 println!("Rendered player");
-                let s = self.shared_state
-                    .player_sprite
-                    .with_context(&self.shared_state.player);
-                graphics::draw(ctx, &s, Point2::new(0.0, 0.0), 0.0);
+                let player = &self.shared_state.player;
+                // between the last two updates, the sprite adds its own top left back
+                let at = util::snap(player.interpolated_top_left(self.timestep.alpha()));
+                let top_left = player.sprite_top_left();
+                let s = self.shared_state.player_sprite.with_context(player);
+                graphics::draw(ctx, &s, Point2::new(at.x - top_left.x, at.y - top_left.y), 0.0);
             }
         }
+        if let Some((x, y)) = self.clicked {
+            let map = &self.shared_state.map;
+            let (at, offset) = (map.grid().tile_to_pixel(x, y), map.camera_offset());
+            let tile = map.tile_dimensions();
+            graphics::set_color(ctx, Color::new(1.0, 1.0, 0.0, 1.0))?;
+            graphics::rectangle(
+                ctx,
+                DrawMode::Line(1.0),
+                Rect::new(at.x - offset.x, at.y - offset.y, tile.x, tile.y),
+            )?;
+            graphics::set_color(ctx, Color::new(1.0, 1.0, 1.0, 1.0))?;
+        }
+        self.screen.end(ctx)?;
         graphics::present(ctx);
 
         timer::yield_now();
@@ -251,12 +282,25 @@ impl<'a> EventHandler for MainState<'a> {
             .input
             .key_up_event(_ctx, keycode, _keymod, _repeat);
     }
+
+    // mouse positions are in window units, the screen maps them through the bars and scale
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, x: i32, y: i32) {
+        let map = &self.shared_state.map;
+        self.clicked = self
+            .screen
+            .to_world(map, x as f32, y as f32)
+            .map(|p| map.point_to_tile_at(p.x, p.y));
+    }
+
+    fn resize_event(&mut self, _ctx: &mut Context, width: u32, height: u32) {
+        self.screen.resize(width, height);
+    }
 }
 
 fn main() {
     let mut cb = ContextBuilder::new("tilemap", "ggez")
         .window_setup(conf::WindowSetup::default().title("Tilemap!"))
-        .window_mode(conf::WindowMode::default().dimensions(256 * 2, 224 * 2));
+        .window_mode(conf::WindowMode::default().dimensions(SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2));
 
     // We add the CARGO_MANIFEST_DIR/resources to the filesystems paths so
     // we we look in the cargo project for files.
@@ -275,19 +319,18 @@ fn main() {
     let ctx = &mut cb.build().unwrap();
 
     ctx.print_resource_stats();
-    graphics::set_background_color(ctx, (0, 0, 0, 255).into());
+    graphics::set_default_filter(ctx, graphics::FilterMode::Nearest);
+    let screen = VirtualScreen::new(ctx, SCREEN_WIDTH, SCREEN_HEIGHT, ScaleMode::Integer).unwrap();
 
-    let mut image = graphics::Image::new(ctx, "/character/rpg_indoor.png").unwrap();
     let tilemap = load_tile_map(ctx, "/character/small_room.tmx").unwrap();
-    let mut sprite = Sprite::new(image, 0.0, 0.0);
-    sprite.uvs = uvs_from_tiled(&tilemap, 0);
-    let map = Map::new(&tilemap, 0, 0);
+    let map_sprites = MapSprites::new(ctx, &tilemap, "/character").unwrap();
+    let map = Map::new(&tilemap, 0);
 
     let mut p_image = graphics::Image::new(ctx, "/character/walk_cycle.png").unwrap();
     let p_sprite = Sprite::new(p_image, 16.0, 24.0);
     let mut player = Entity::new(Point2::new(16.0, 24.0), 0, 9);
 
-    let mut game = MainState::new(sprite, p_sprite, map, player).unwrap();
+    let mut game = MainState::new(screen, map_sprites, p_sprite, map, player).unwrap();
     let result = event::run(ctx, &mut game);
     if let Err(e) = result {
         println!("Error encountered running game: {}", e);
@@ -5,30 +5,29 @@ extern crate tiled;
 use ggez::conf;
 use ggez::event::{self, EventHandler, Keycode, Mod};
 use ggez::graphics;
-use ggez::graphics::Point2;
+use ggez::graphics::{Point2, Rect};
 use ggez::timer;
 use ggez::{Context, ContextBuilder, GameResult};
 use std::env;
 use std::path;
 
 use rpg::input::InputState;
-use rpg::map::{uvs_from_tiled, Map};
-use rpg::sprite::Sprite;
+use rpg::map::{Map, MapSprites};
 use rpg::util::load_tile_map;
 
 struct MainState {
     map: Map,
-    sprite: Sprite,
+    sprites: MapSprites,
 
     input: InputState,
 }
 
 impl MainState {
-    pub fn new(sprite: Sprite, tilemap: &tiled::Map) -> GameResult<MainState> {
-        let map = Map::new(tilemap, 0, 0);
+    pub fn new(sprites: MapSprites, tilemap: &tiled::Map) -> GameResult<MainState> {
+        let map = Map::new(tilemap, 0);
         Ok(MainState {
             map,
-            sprite,
+            sprites,
             input: InputState::default(),
         })
     }
@@ -41,18 +40,19 @@ impl EventHandler for MainState {
 
         while timer::check_update_time(ctx, DESIRED_FPS) {
             let seconds = 1.0 / (DESIRED_FPS as f32);
+            let camera = self.map.camera();
             let (x, y) = (
-                self.map.camera.x + self.input.xaxis * SPEED * seconds,
-                self.map.camera.y + self.input.yaxis * SPEED * seconds,
+                camera.x + self.input.xaxis * SPEED * seconds,
+                camera.y + self.input.yaxis * SPEED * seconds,
             );
-            self.map.camera.move_to(Point2::new(x, y))
+            self.map.goto(Point2::new(x, y))
         }
         Ok(())
     }
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx);
         {
-            let s = self.sprite.with_context(&self.map);
+            let s = self.sprites.with_context(&self.map);
             graphics::draw(ctx, &s, Point2::new(0.0, 0.0), 0.0);
         }
         // println!("x: {:?}, y: {:?}\n", self.map.map_cam_x, self.map.map_cam_y);
@@ -106,15 +106,12 @@ fn main() {
     ctx.print_resource_stats();
     graphics::set_background_color(ctx, (0, 0, 0, 255).into());
 
-    let mut image = graphics::Image::new(ctx, "/cave16x16.png").unwrap();
     let tilemap = load_tile_map(ctx, "/larger_map.tmx").unwrap();
-    let mut sprite = Sprite::new(image, 0.0, 0.0);
-    sprite.uvs = uvs_from_tiled(&tilemap, 0);
+    let sprites = MapSprites::new(ctx, &tilemap, "/").unwrap();
 
-    let mut game = MainState::new(sprite, &tilemap).unwrap();
+    let mut game = MainState::new(sprites, &tilemap).unwrap();
     let (w, h) = graphics::get_size(ctx);
-    game.map.camera.w = w as f32;
-    game.map.camera.h = h as f32;
+    game.map.set_camera(Rect::new(0.0, 0.0, w as f32, h as f32));
 
     let result = event::run(ctx, &mut game);
     if let Err(e) = result {
@@ -4,7 +4,8 @@ use entity::Entity;
 
 fn teleport(tile_x: usize, tile_y: usize, layer: usize) -> Box<TriggerFn> {
     Box::new(move |trigger, map, entity, _, _ , _| {
-        map.set_tile_pos(entity, tile_x, tile_y, layer)
+        // a blocked destination leaves the entity where it is
+        let _ = map.set_tile_pos(entity, tile_x, tile_y, layer);
     })
 }
 
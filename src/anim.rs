@@ -4,6 +4,8 @@ pub struct Animation {
     frames: Vec<usize>,
     should_loop: bool,
     spf: f32,
+    // seconds per frame by index, spf for frames past the end
+    durations: Vec<f32>,
 
     index: usize,
     time: f32,
@@ -16,16 +18,30 @@ impl Animation {
             frames,
             should_loop,
             spf,
+            durations: Vec::new(),
 
             index: 0,
             time: 0.0,
         }
     }
 
+    /// times each frame by its own duration, frames without one positive use spf
+    pub fn with_durations(mut self, durations: Vec<f32>) -> Self {
+        self.durations = durations;
+        self
+    }
+
+    fn frame_duration(&self) -> f32 {
+        match self.durations.get(self.index) {
+            Some(&duration) if duration > 0.0 => duration,
+            _ => self.spf,
+        }
+    }
+
     pub fn update(&mut self, dt: f32) {
         self.time = self.time + dt;
 
-        if self.time >= self.spf {
+        if self.time >= self.frame_duration() {
             self.index = self.index + 1;
             self.time = 0.0;
 
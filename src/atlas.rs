@@ -0,0 +1,395 @@
+//! Texture atlas packing.
+//!
+//! `AtlasBuilder` packs RGBA images into pages in rows of images sorted by
+//! height. Each image goes on the first page with room in its open row or
+//! under it, a new page is only started when none has. Each image keeps its name and gets a rect on
+//! its page. Edges are extruded into the padding so filtering does not bleed
+//! neighbours in. Pack once at load time or offline and save the pages.
+//!
+//! Entities with an `AtlasSprite` component can then be drawn through
+//! `AtlasBatches`, one sprite batch and draw call per page rather than per
+//! entity. Batches are drawn page by page, so y sorting only holds between
+//! entities on the same page.
+use std::collections::HashMap;
+
+use ggez::graphics::{self, spritebatch::SpriteBatch, Image, Point2, Rect};
+use ggez::{Context, GameError, GameResult};
+
+use map::Map;
+use spritesheet::{SheetFrame, SpriteSheet};
+use util;
+
+#[derive(Debug, Clone)]
+struct Source {
+    name: String,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AtlasBuilder {
+    page_width: u32,
+    page_height: u32,
+    padding: u32,
+    sources: Vec<Source>,
+}
+
+impl AtlasBuilder {
+    /// pages of page_width by page_height pixels
+    pub fn new(page_width: u32, page_height: u32) -> Self {
+        AtlasBuilder {
+            page_width,
+            page_height,
+            padding: 1,
+            sources: Vec::new(),
+        }
+    }
+
+    /// pixels between images, filled with their extruded edges, 1 by default
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// adds width by height RGBA pixels as region name
+    pub fn add(&mut self, name: &str, width: u32, height: u32, pixels: Vec<u8>) -> GameResult<()> {
+        if pixels.len() != (width * height * 4) as usize {
+            return Err(GameError::from(format!("atlas image {} has the wrong number of pixels", name)));
+        }
+        if self.sources.iter().any(|s| s.name == name) {
+            return Err(GameError::from(format!("atlas image {} added twice", name)));
+        }
+        self.sources.push(Source {
+            name: name.to_string(),
+            width,
+            height,
+            pixels,
+        });
+        Ok(())
+    }
+
+    /// adds a loaded image as region name
+    pub fn add_image(&mut self, ctx: &mut Context, name: &str, image: &Image) -> GameResult<()> {
+        let pixels = image.to_rgba8(ctx)?;
+        self.add(name, image.width(), image.height(), pixels)
+    }
+
+    pub fn pack(&self) -> GameResult<Atlas> {
+        let pad = self.padding;
+        let mut order: Vec<&Source> = self.sources.iter().collect();
+        order.sort_by(|a, b| b.height.cmp(&a.height).then(b.width.cmp(&a.width)).then(a.name.cmp(&b.name)));
+
+        let mut atlas = Atlas::default();
+        // cursor and height of the open row on each page
+        let mut rows: Vec<(u32, u32, u32)> = Vec::new();
+        for source in order {
+            let (w, h) = (source.width + pad * 2, source.height + pad * 2);
+            if w > self.page_width || h > self.page_height {
+                return Err(GameError::from(format!(
+                    "atlas image {} does not fit a {}x{} page",
+                    source.name, self.page_width, self.page_height
+                )));
+            }
+            let mut spot = None;
+            for (page, row) in rows.iter_mut().enumerate() {
+                let (x, y, row_height) = *row;
+                if x + w <= self.page_width && y + h <= self.page_height {
+                    *row = (x + w, y, row_height.max(h));
+                    spot = Some((page, x, y));
+                    break;
+                }
+                if y + row_height + h <= self.page_height {
+                    *row = (w, y + row_height, h);
+                    spot = Some((page, 0, y + row_height));
+                    break;
+                }
+            }
+            let (page, x, y) = match spot {
+                Some(spot) => spot,
+                None => {
+                    atlas.pages.push(AtlasPage::new(self.page_width, self.page_height));
+                    rows.push((w, 0, h));
+                    (atlas.pages.len() - 1, 0, 0)
+                }
+            };
+            atlas.pages[page].blit(source, x + pad, y + pad, pad);
+            let rect = Rect::new((x + pad) as f32, (y + pad) as f32, source.width as f32, source.height as f32);
+            atlas.regions.insert(
+                source.name.clone(),
+                AtlasRegion {
+                    page,
+                    rect,
+                    uv: Rect::new(
+                        rect.x / self.page_width as f32,
+                        rect.y / self.page_height as f32,
+                        rect.w / self.page_width as f32,
+                        rect.h / self.page_height as f32,
+                    ),
+                },
+            );
+        }
+        Ok(atlas)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AtlasPage {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA rows, top to bottom
+    pub pixels: Vec<u8>,
+}
+
+impl AtlasPage {
+    fn new(width: u32, height: u32) -> Self {
+        AtlasPage {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    // copies source with its top left at x, y and extrudes its edges pad pixels out
+    fn blit(&mut self, source: &Source, x: u32, y: u32, pad: u32) {
+        let (sw, sh) = (source.width as i64, source.height as i64);
+        if sw == 0 || sh == 0 {
+            return;
+        }
+        let pad = pad as i64;
+        for dy in -pad..sh + pad {
+            for dx in -pad..sw + pad {
+                let (px, py) = (x as i64 + dx, y as i64 + dy);
+                if px < 0 || py < 0 || px >= self.width as i64 || py >= self.height as i64 {
+                    continue;
+                }
+                let sx = dx.max(0).min(sw - 1);
+                let sy = dy.max(0).min(sh - 1);
+                let from = ((sx + sy * sw) * 4) as usize;
+                let to = ((px + py * self.width as i64) * 4) as usize;
+                self.pixels[to..to + 4].copy_from_slice(&source.pixels[from..from + 4]);
+            }
+        }
+    }
+
+    pub fn to_image(&self, ctx: &mut Context) -> GameResult<Image> {
+        Image::from_rgba8(ctx, self.width as u16, self.height as u16, &self.pixels)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    pub page: usize,
+    /// pixels on the page
+    pub rect: Rect,
+    /// rect as fractions of the page, for draw params
+    pub uv: Rect,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Atlas {
+    pub pages: Vec<AtlasPage>,
+    regions: HashMap<String, AtlasRegion>,
+}
+
+impl Atlas {
+    pub fn region(&self, name: &str) -> Option<&AtlasRegion> {
+        self.regions.get(name)
+    }
+
+    pub fn regions(&self) -> &HashMap<String, AtlasRegion> {
+        &self.regions
+    }
+
+    /// uvs relative to the image name, like util::generate_uvs gives, moved onto its page
+    /// lets tilesets and grid sprites be packed whole
+    pub fn map_uvs(&self, name: &str, uvs: &[Rect]) -> Option<Vec<Rect>> {
+        let region = self.region(name)?;
+        Some(
+            uvs.iter()
+                .map(|uv| {
+                    Rect::new(
+                        region.uv.x + uv.x * region.uv.w,
+                        region.uv.y + uv.y * region.uv.h,
+                        uv.w * region.uv.w,
+                        uv.h * region.uv.h,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// regions of page as a sprite sheet of named frames, sorted by name
+    pub fn page_sheet(&self, page: usize) -> SpriteSheet {
+        let mut names: Vec<&String> = self
+            .regions
+            .iter()
+            .filter(|&(_, region)| region.page == page)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        let mut sheet = SpriteSheet::default();
+        if let Some(p) = self.pages.get(page) {
+            sheet.size = Point2::new(p.width as f32, p.height as f32);
+        }
+        for name in names {
+            let rect = self.regions[name].rect;
+            sheet.add_frame(SheetFrame {
+                name: name.clone(),
+                rect,
+                offset: Point2::new(0.0, 0.0),
+                source_size: Point2::new(rect.w, rect.h),
+                duration: None,
+            });
+        }
+        sheet
+    }
+
+    /// uploads every page
+    pub fn to_images(&self, ctx: &mut Context) -> GameResult<Vec<Image>> {
+        self.pages.iter().map(|page| page.to_image(ctx)).collect()
+    }
+}
+
+/// component drawing an entity from atlas regions, one name per frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasSprite {
+    pub frames: Vec<String>,
+}
+
+/// one sprite batch per atlas page
+pub struct AtlasBatches {
+    regions: HashMap<String, AtlasRegion>,
+    batches: Vec<SpriteBatch>,
+}
+
+impl AtlasBatches {
+    /// images are the uploaded pages of atlas, see Atlas::to_images
+    pub fn new(atlas: &Atlas, images: Vec<Image>) -> Self {
+        AtlasBatches {
+            regions: atlas.regions.clone(),
+            batches: images.into_iter().map(SpriteBatch::new).collect(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for batch in self.batches.iter_mut() {
+            batch.clear();
+        }
+    }
+
+    /// queues region name drawn with param, false if there is no such region
+    pub fn add(&mut self, name: &str, param: graphics::DrawParam) -> bool {
+        match self.regions.get(name) {
+            Some(region) if region.page < self.batches.len() => {
+                let mut param = param;
+                param.src = region.uv;
+                self.batches[region.page].add(param);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// queues the entities of layer that have an AtlasSprite, y sorted, in screen space
+    /// alpha interpolates their positions, see FixedTimestep
+    pub fn add_entities(&mut self, map: &Map, layer: usize, alpha: f32) {
+        let offset = map.camera_offset();
+        for e in map.entities_y_sorted(layer, alpha) {
+            let sprite = match map.world().get::<AtlasSprite>(&e.id) {
+                Some(sprite) => sprite,
+                None => continue,
+            };
+            if let Some(name) = sprite.frames.get(e.frame()) {
+                let at = util::snap(e.interpolated_top_left(alpha));
+                let mut param = graphics::DrawParam::default();
+                param.dest = Point2::new(at.x - offset.x, at.y - offset.y);
+                self.add(name, param);
+            }
+        }
+    }
+}
+
+impl graphics::Drawable for AtlasBatches {
+    fn draw_ex(&self, ctx: &mut Context, param: graphics::DrawParam) -> GameResult<()> {
+        for batch in self.batches.iter() {
+            batch.draw_ex(ctx, param)?;
+        }
+        Ok(())
+    }
+    fn set_blend_mode(&mut self, _mode: Option<graphics::BlendMode>) {}
+    fn get_blend_mode(&self) -> Option<graphics::BlendMode> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, value: u8) -> Vec<u8> {
+        vec![value; (width * height * 4) as usize]
+    }
+
+    fn pixel(page: &AtlasPage, x: u32, y: u32) -> u8 {
+        page.pixels[((x + y * page.width) * 4) as usize]
+    }
+
+    #[test]
+    fn packs_pages_and_regions() {
+        let mut builder = AtlasBuilder::new(16, 16);
+        builder.add("tall", 4, 10, solid(4, 10, 1)).unwrap();
+        builder.add("wide", 8, 4, solid(8, 4, 2)).unwrap();
+        builder.add("small", 2, 2, solid(2, 2, 3)).unwrap();
+        builder.add("big", 14, 14, solid(14, 14, 4)).unwrap();
+        assert!(builder.add("small", 2, 2, solid(2, 2, 3)).is_err());
+        assert!(builder.add("broken", 2, 2, solid(1, 1, 0)).is_err());
+
+        let atlas = builder.pack().unwrap();
+        assert_eq!(atlas.pages.len(), 2);
+        let big = *atlas.region("big").unwrap();
+        assert_eq!((big.page, big.rect), (0, Rect::new(1.0, 1.0, 14.0, 14.0)));
+
+        // regions on a page never overlap, padding included
+        let mut on_second: Vec<AtlasRegion> = atlas.regions().values().filter(|r| r.page == 1).cloned().collect();
+        assert_eq!(on_second.len(), 3);
+        on_second.sort_by(|a, b| a.rect.x.partial_cmp(&b.rect.x).unwrap().then(a.rect.y.partial_cmp(&b.rect.y).unwrap()));
+        for (i, a) in on_second.iter().enumerate() {
+            for b in on_second[i + 1..].iter() {
+                let apart = a.rect.right() + 2.0 <= b.rect.left()
+                    || b.rect.right() + 2.0 <= a.rect.left()
+                    || a.rect.bottom() + 2.0 <= b.rect.top()
+                    || b.rect.bottom() + 2.0 <= a.rect.top();
+                assert!(apart);
+            }
+        }
+
+        // pixels are copied and edges extruded into the padding
+        let tall = *atlas.region("tall").unwrap();
+        let page = &atlas.pages[tall.page];
+        assert_eq!(pixel(page, tall.rect.x as u32, tall.rect.y as u32), 1);
+        assert_eq!(pixel(page, tall.rect.x as u32 - 1, tall.rect.y as u32 - 1), 1);
+
+        let uvs = atlas.map_uvs("big", &[Rect::new(0.5, 0.0, 0.5, 0.5)]).unwrap();
+        assert_eq!(uvs[0], Rect::new(8.0 / 16.0, 1.0 / 16.0, 7.0 / 16.0, 7.0 / 16.0));
+
+        let sheet = atlas.page_sheet(1);
+        assert_eq!(sheet.frame("wide").unwrap().rect, atlas.region("wide").unwrap().rect);
+        assert!(sheet.frame("big").is_none());
+
+        // earlier pages are filled before later ones
+        let mut builder = AtlasBuilder::new(16, 16);
+        builder.add("top", 14, 10, solid(14, 10, 1)).unwrap();
+        builder.add("bottom", 14, 8, solid(14, 8, 2)).unwrap();
+        builder.add("gap", 2, 2, solid(2, 2, 3)).unwrap();
+        let atlas = builder.pack().unwrap();
+        assert_eq!(atlas.pages.len(), 2);
+        assert_eq!(atlas.region("bottom").unwrap().page, 1);
+        let gap = *atlas.region("gap").unwrap();
+        assert_eq!((gap.page, gap.rect), (0, Rect::new(1.0, 13.0, 2.0, 2.0)));
+
+        let mut too_small = AtlasBuilder::new(8, 8);
+        too_small.add("big", 8, 8, solid(8, 8, 0)).unwrap();
+        assert!(too_small.pack().is_err());
+    }
+}
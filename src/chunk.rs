@@ -0,0 +1,240 @@
+//! Chunked tile storage.
+//!
+//! Layers are split into square chunks keyed by chunk coordinate so maps can
+//! be sparse, extend into negative coordinates and track changes per chunk.
+//!
+//! `tiled::Layer` can only hold a dense grid, so infinite maps keep one
+//! chunk per row of their layers instead, see `chunk_row`.
+use std::collections::HashMap;
+
+use tiled;
+
+/// width and height of a chunk in tiles
+pub const CHUNK_SIZE: i32 = 16;
+
+/// chunk containing tile coordinate x, rounds towards negative infinity
+pub fn chunk_coord(x: i32) -> i32 {
+    if x < 0 {
+        (x - CHUNK_SIZE + 1) / CHUNK_SIZE
+    } else {
+        x / CHUNK_SIZE
+    }
+}
+
+// x, y, width and height lead every chunk row
+const CHUNK_ROW_HEADER: usize = 4;
+
+/// packs width by height tiles whose top left tile is x, y into a layer row
+pub fn chunk_row(x: i32, y: i32, width: u32, height: u32, tiles: &[u32]) -> Vec<u32> {
+    let mut row = Vec::with_capacity(CHUNK_ROW_HEADER + tiles.len());
+    row.extend_from_slice(&[x as u32, y as u32, width, height]);
+    row.extend_from_slice(tiles);
+    row
+}
+
+/// chunks packed into the rows of a layer as (x, y, width, height, tiles)
+/// rows that are too short for their size are skipped
+pub fn chunk_rows(layer: &tiled::Layer) -> Vec<(i32, i32, u32, u32, &[u32])> {
+    layer
+        .tiles
+        .iter()
+        .filter(|row| row.len() >= CHUNK_ROW_HEADER)
+        .map(|row| {
+            let (width, height) = (row[2], row[3]);
+            (row[0] as i32, row[1] as i32, width, height, &row[CHUNK_ROW_HEADER..])
+        })
+        .filter(|&(_, _, width, height, tiles)| tiles.len() == (width * height) as usize)
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    tiles: Vec<u32>,
+    // map revision of the last change to this chunk
+    revision: u64,
+}
+
+impl Chunk {
+    fn new(revision: u64) -> Self {
+        Chunk {
+            tiles: vec![0; (CHUNK_SIZE * CHUNK_SIZE) as usize],
+            revision,
+        }
+    }
+
+    /// tile at local coordinates inside the chunk
+    pub fn tile(&self, x: i32, y: i32) -> u32 {
+        self.tiles[(x + y * CHUNK_SIZE) as usize]
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub name: String,
+    pub opacity: f32,
+    pub visible: bool,
+    pub properties: tiled::Properties,
+
+    chunks: HashMap<(i32, i32), Chunk>,
+}
+
+impl TileLayer {
+    pub fn new(name: String) -> Self {
+        TileLayer {
+            name,
+            opacity: 1.0,
+            visible: true,
+            properties: HashMap::new(),
+            chunks: HashMap::new(),
+        }
+    }
+
+    // empty layer with the settings of a tiled layer
+    fn settings_of(layer: &tiled::Layer) -> Self {
+        let mut result = TileLayer::new(layer.name.clone());
+        result.opacity = layer.opacity;
+        result.visible = layer.visible;
+        result.properties = layer.properties.clone();
+        result
+    }
+
+    /// copies a dense tiled layer whose top left tile is origin_x, origin_y
+    pub fn from_tiled(layer: &tiled::Layer, origin_x: i32, origin_y: i32) -> Self {
+        let mut result = TileLayer::settings_of(layer);
+        for (y, row) in layer.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if *tile != 0 {
+                    result.set(origin_x + x as i32, origin_y + y as i32, *tile, 0);
+                }
+            }
+        }
+        result
+    }
+
+    /// copies a layer of chunk rows, see chunk_row
+    pub fn from_tiled_chunks(layer: &tiled::Layer) -> Self {
+        let mut result = TileLayer::settings_of(layer);
+        for (left, top, width, _, tiles) in chunk_rows(layer) {
+            for (i, tile) in tiles.iter().enumerate() {
+                if *tile != 0 {
+                    let (x, y) = (i as u32 % width, i as u32 / width);
+                    result.set(left + x as i32, top + y as i32, *tile, 0);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> u32 {
+        let (cx, cy) = (chunk_coord(x), chunk_coord(y));
+        match self.chunks.get(&(cx, cy)) {
+            Some(chunk) => chunk.tile(x - cx * CHUNK_SIZE, y - cy * CHUNK_SIZE),
+            None => 0,
+        }
+    }
+
+    /// sets a tile and stamps its chunk with revision
+    pub fn set(&mut self, x: i32, y: i32, tile: u32, revision: u64) {
+        let (cx, cy) = (chunk_coord(x), chunk_coord(y));
+        if tile == 0 && !self.chunks.contains_key(&(cx, cy)) {
+            return;
+        }
+        let chunk = self
+            .chunks
+            .entry((cx, cy))
+            .or_insert_with(|| Chunk::new(revision));
+        let index = ((x - cx * CHUNK_SIZE) + (y - cy * CHUNK_SIZE) * CHUNK_SIZE) as usize;
+        chunk.tiles[index] = tile;
+        chunk.revision = revision;
+    }
+
+    /// dense copy of the width by height tiles starting at left, top
+    pub fn to_tiled(&self, left: i32, top: i32, width: i32, height: i32) -> tiled::Layer {
+        let tiles = (top..top + height)
+            .map(|y| (left..left + width).map(|x| self.get(x, y)).collect())
+            .collect();
+        tiled::Layer {
+            name: self.name.clone(),
+            opacity: self.opacity,
+            visible: self.visible,
+            tiles,
+            properties: self.properties.clone(),
+        }
+    }
+
+    /// one chunk row per non empty chunk, sorted top to bottom then left to right
+    pub fn to_tiled_chunks(&self) -> tiled::Layer {
+        let mut coords: Vec<(i32, i32)> = self
+            .chunks
+            .iter()
+            .filter(|&(_, chunk)| chunk.tiles.iter().any(|t| *t != 0))
+            .map(|(coord, _)| *coord)
+            .collect();
+        coords.sort_by_key(|&(cx, cy)| (cy, cx));
+        let tiles = coords
+            .into_iter()
+            .map(|(cx, cy)| {
+                let size = CHUNK_SIZE as u32;
+                chunk_row(cx * CHUNK_SIZE, cy * CHUNK_SIZE, size, size, &self.chunks[&(cx, cy)].tiles)
+            })
+            .collect();
+        tiled::Layer {
+            name: self.name.clone(),
+            opacity: self.opacity,
+            visible: self.visible,
+            tiles,
+            properties: self.properties.clone(),
+        }
+    }
+
+    pub fn chunk(&self, cx: i32, cy: i32) -> Option<&Chunk> {
+        self.chunks.get(&(cx, cy))
+    }
+
+    pub fn chunk_coords<'a>(&'a self) -> Box<Iterator<Item = (i32, i32)> + 'a> {
+        Box::new(self.chunks.keys().cloned())
+    }
+
+    /// tile bounds covered by chunks as (left, top, right, bottom), right and bottom exclusive
+    pub fn bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        let mut bounds: Option<(i32, i32, i32, i32)> = None;
+        for &(cx, cy) in self.chunks.keys() {
+            let (l, t) = (cx * CHUNK_SIZE, cy * CHUNK_SIZE);
+            let (r, b) = (l + CHUNK_SIZE, t + CHUNK_SIZE);
+            bounds = Some(match bounds {
+                None => (l, t, r, b),
+                Some((bl, bt, br, bb)) => (bl.min(l), bt.min(t), br.max(r), bb.max(b)),
+            });
+        }
+        bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_coordinates() {
+        assert_eq!(chunk_coord(-1), -1);
+        assert_eq!(chunk_coord(-CHUNK_SIZE), -1);
+        assert_eq!(chunk_coord(-CHUNK_SIZE - 1), -2);
+        assert_eq!(chunk_coord(CHUNK_SIZE - 1), 0);
+
+        let mut layer = TileLayer::new(String::from("ground"));
+        layer.set(-1, -17, 5, 1);
+        layer.set(3, 2, 7, 2);
+        assert_eq!(layer.get(-1, -17), 5);
+        assert_eq!(layer.get(3, 2), 7);
+        assert_eq!(layer.get(0, 0), 0);
+        assert_eq!(layer.chunk(-1, -2).unwrap().revision(), 1);
+        assert_eq!(
+            layer.bounds(),
+            Some((-CHUNK_SIZE, -2 * CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE))
+        );
+    }
+}
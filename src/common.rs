@@ -1,6 +1,19 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Facing {
     Up,
     Right,
     Down,
     Left,
-}
\ No newline at end of file
+}
+
+impl Facing {
+    /// tile offset of one step in this direction
+    pub fn offset(&self) -> (i32, i32) {
+        match *self {
+            Facing::Up => (0, -1),
+            Facing::Right => (1, 0),
+            Facing::Down => (0, 1),
+            Facing::Left => (-1, 0),
+        }
+    }
+}
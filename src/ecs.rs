@@ -0,0 +1,302 @@
+//! Component storage for entities.
+//!
+//! A `World` keeps typed components keyed by the same ids as `entity::Entity`,
+//! so stats, inventories or AI can be attached without changing the entity
+//! struct. `Map` owns a world next to its tile occupancy index, which stays
+//! the source of truth for positions, and drops an entity's components when
+//! the entity is removed. `Systems` run in order over whatever they are
+//! given, a world on its own or a map with its world, see `map::MapSystems`.
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+// type erased storage so the world can hold one per component type
+trait Storage {
+    fn remove_entity(&mut self, id: &str);
+    fn contains(&self, id: &str) -> bool;
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+/// components of one type, iterated in id order
+pub struct ComponentStorage<T> {
+    components: BTreeMap<String, T>,
+}
+
+impl<T> ComponentStorage<T> {
+    fn new() -> Self {
+        ComponentStorage {
+            components: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&T> {
+        self.components.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut T> {
+        self.components.get_mut(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = (&'a String, &'a T)> + 'a> {
+        Box::new(self.components.iter())
+    }
+
+    pub fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item = (&'a String, &'a mut T)> + 'a> {
+        Box::new(self.components.iter_mut())
+    }
+}
+
+impl<T: 'static> Storage for ComponentStorage<T> {
+    fn remove_entity(&mut self, id: &str) {
+        self.components.remove(id);
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.components.contains_key(id)
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct World {
+    entities: BTreeSet<String>,
+    storages: HashMap<TypeId, Box<Storage>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        World::default()
+    }
+
+    /// registers an entity id, false if it already exists
+    pub fn create_entity(&mut self, id: &str) -> bool {
+        self.entities.insert(id.to_string())
+    }
+
+    /// removes an entity and all its components, false if it did not exist
+    pub fn delete_entity(&mut self, id: &str) -> bool {
+        for storage in self.storages.values_mut() {
+            storage.remove_entity(id);
+        }
+        self.entities.remove(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.entities.contains(id)
+    }
+
+    /// entity ids in order
+    pub fn entities<'a>(&'a self) -> Box<Iterator<Item = &'a String> + 'a> {
+        Box::new(self.entities.iter())
+    }
+
+    /// adds or replaces a component, creating the entity if needed
+    /// returns the component it replaced
+    pub fn insert<T: 'static>(&mut self, id: &str, component: T) -> Option<T> {
+        self.entities.insert(id.to_string());
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(ComponentStorage::<T>::new()))
+            .as_any_mut()
+            .downcast_mut::<ComponentStorage<T>>()
+            .unwrap()
+            .components
+            .insert(id.to_string(), component)
+    }
+
+    pub fn remove<T: 'static>(&mut self, id: &str) -> Option<T> {
+        self.storage_mut::<T>()?.components.remove(id)
+    }
+
+    pub fn get<T: 'static>(&self, id: &str) -> Option<&T> {
+        self.storage::<T>()?.get(id)
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, id: &str) -> Option<&mut T> {
+        self.storage_mut::<T>()?.get_mut(id)
+    }
+
+    pub fn has<T: 'static>(&self, id: &str) -> bool {
+        self.get::<T>(id).is_some()
+    }
+
+    /// every component of type T, None if none was ever inserted
+    pub fn storage<T: 'static>(&self) -> Option<&ComponentStorage<T>> {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .and_then(|s| s.as_any().downcast_ref::<ComponentStorage<T>>())
+    }
+
+    pub fn storage_mut<T: 'static>(&mut self) -> Option<&mut ComponentStorage<T>> {
+        self.storages
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|s| s.as_any_mut().downcast_mut::<ComponentStorage<T>>())
+    }
+
+    /// starts a query over the entities having a set of components
+    /// world.query().with::<Health>().with::<Ai>().ids()
+    pub fn query(&self) -> Query {
+        Query {
+            world: self,
+            with: Vec::new(),
+            without: Vec::new(),
+        }
+    }
+}
+
+pub struct Query<'a> {
+    world: &'a World,
+    with: Vec<TypeId>,
+    without: Vec<TypeId>,
+}
+
+impl<'a> Query<'a> {
+    /// only entities having a T
+    pub fn with<T: 'static>(mut self) -> Self {
+        self.with.push(TypeId::of::<T>());
+        self
+    }
+
+    /// only entities without a T
+    pub fn without<T: 'static>(mut self) -> Self {
+        self.without.push(TypeId::of::<T>());
+        self
+    }
+
+    fn has(&self, type_id: &TypeId, id: &str) -> bool {
+        match self.world.storages.get(type_id) {
+            Some(storage) => storage.contains(id),
+            None => false,
+        }
+    }
+
+    /// ids of the matching entities in order
+    pub fn ids(&self) -> Vec<String> {
+        self.world
+            .entities
+            .iter()
+            .filter(|id| self.with.iter().all(|t| self.has(t, id)))
+            .filter(|id| !self.without.iter().any(|t| self.has(t, id)))
+            .cloned()
+            .collect()
+    }
+}
+
+impl fmt::Debug for World {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("World")
+            .field("entities", &self.entities)
+            .field("storages", &self.storages.len())
+            .finish()
+    }
+}
+
+/// logic run each tick over data, a world or something holding one like the map
+pub trait System<T> {
+    fn run(&mut self, data: &mut T);
+}
+
+impl<T, F> System<T> for F
+where
+    F: FnMut(&mut T),
+{
+    fn run(&mut self, data: &mut T) {
+        self(data)
+    }
+}
+
+/// runs systems in the order they were added
+pub struct Systems<'a, T> {
+    systems: Vec<Box<System<T> + 'a>>,
+}
+
+impl<'a, T> Systems<'a, T> {
+    pub fn new() -> Self {
+        Systems {
+            systems: Vec::new(),
+        }
+    }
+
+    pub fn add<S: System<T> + 'a>(&mut self, system: S) -> &mut Self {
+        self.systems.push(Box::new(system));
+        self
+    }
+
+    pub fn run(&mut self, data: &mut T) {
+        for system in self.systems.iter_mut() {
+            system.run(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Health(i32);
+    struct Poisoned;
+    struct Ai;
+
+    #[test]
+    fn components_queries_systems() {
+        let mut world = World::new();
+        world.insert("hero", Health(10));
+        world.insert("slime", Health(3));
+        world.insert("slime", Ai);
+        world.insert("slime", Poisoned);
+        world.insert("bat", Ai);
+
+        assert_eq!(world.get::<Health>("hero"), Some(&Health(10)));
+        assert!(!world.has::<Ai>("hero"));
+        assert_eq!(world.query().with::<Ai>().ids(), vec!["bat", "slime"]);
+        assert_eq!(world.query().with::<Health>().without::<Ai>().ids(), vec!["hero"]);
+
+        // systems over the world and a log of what they did
+        let mut data = (world, Vec::new());
+        {
+            let mut systems = Systems::new();
+            systems
+                .add(|data: &mut (World, Vec<String>)| {
+                    let (ref mut world, ref mut log) = *data;
+                    for id in world.query().with::<Health>().with::<Poisoned>().ids() {
+                        world.get_mut::<Health>(&id).unwrap().0 -= 3;
+                        log.push(format!("poison {}", id));
+                    }
+                })
+                .add(|data: &mut (World, Vec<String>)| {
+                    let (ref mut world, ref mut log) = *data;
+                    let dead: Vec<String> = world
+                        .storage::<Health>()
+                        .unwrap()
+                        .iter()
+                        .filter(|&(_, health)| health.0 <= 0)
+                        .map(|(id, _)| id.clone())
+                        .collect();
+                    for id in dead {
+                        world.delete_entity(&id);
+                        log.push(format!("died {}", id));
+                    }
+                });
+            systems.run(&mut data);
+        }
+        let (world, log) = data;
+
+        assert_eq!(log, vec!["poison slime", "died slime"]);
+        assert!(!world.contains("slime"));
+        assert!(world.get::<Ai>("slime").is_none());
+        assert_eq!(world.query().with::<Ai>().ids(), vec!["bat"]);
+    }
+}
@@ -0,0 +1,561 @@
+//! In game tile map editor.
+//!
+//! `Editor` holds the cursor, palette and undo history and edits a `Map`
+//! directly. `EditorState` drives it from input and can be pushed over
+//! gameplay, popping restores the camera it found. Draw
+//! `Editor::with_context` over the map to show the cursor and palette.
+use std::collections::{HashSet, VecDeque};
+
+use ggez::event::Keycode;
+use ggez::graphics::{self, Color, DrawMode, DrawParam, Drawable, Point2, Rect};
+use ggez::{Context, GameResult};
+
+use input::InputState;
+use map::{self, Map, MapSprites};
+use state::{State, StateData, Trans};
+use util;
+
+// undo steps kept before the oldest are dropped
+const HISTORY_LIMIT: usize = 100;
+
+/// which of a level's three layers is being edited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditLayer {
+    Base,
+    Decoration,
+    Collision,
+}
+
+impl EditLayer {
+    fn offset(&self) -> usize {
+        match *self {
+            EditLayer::Base => 0,
+            EditLayer::Decoration => 1,
+            EditLayer::Collision => 2,
+        }
+    }
+
+    pub fn next(&self) -> EditLayer {
+        match *self {
+            EditLayer::Base => EditLayer::Decoration,
+            EditLayer::Decoration => EditLayer::Collision,
+            EditLayer::Collision => EditLayer::Base,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TileChange {
+    x: i32,
+    y: i32,
+    layer: usize,
+    before: u32,
+    after: u32,
+}
+
+#[derive(Debug)]
+pub struct Editor {
+    /// tiled coordinates of the tile under the cursor
+    pub cursor: (i32, i32),
+    pub level: usize,
+    pub edit_layer: EditLayer,
+
+    // gids that can be painted, in tileset order
+    palette: Vec<u32>,
+    selected: usize,
+
+    undo: Vec<Vec<TileChange>>,
+    redo: Vec<Vec<TileChange>>,
+
+    path: String,
+    dirty: bool,
+    save_requested: bool,
+}
+
+impl Editor {
+    /// editor for map, saving to path
+    pub fn new(map: &Map, path: &str) -> Self {
+        let mut palette = Vec::new();
+        for tileset in map.tilesets().iter() {
+            if tileset.name == map::COLLISION_TILESET || tileset.images.is_empty() {
+                continue;
+            }
+            let count = map::tileset_uvs(tileset).len() as u32;
+            palette.extend(tileset.first_gid..tileset.first_gid + count);
+        }
+        let (left, top, _, _) = map.tile_bounds();
+
+        Editor {
+            cursor: (left, top),
+            level: 0,
+            edit_layer: EditLayer::Base,
+            palette,
+            selected: 0,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            path: path.to_string(),
+            dirty: false,
+            save_requested: false,
+        }
+    }
+
+    pub fn palette(&self) -> &Vec<u32> {
+        &self.palette
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.palette.len() {
+            self.selected = index;
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.palette.is_empty() {
+            self.selected = (self.selected + 1) % self.palette.len();
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        if !self.palette.is_empty() {
+            self.selected = (self.selected + self.palette.len() - 1) % self.palette.len();
+        }
+    }
+
+    /// unsaved changes since the last save
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// moves the cursor, finite maps keep it inside the map
+    pub fn move_cursor(&mut self, map: &Map, dx: i32, dy: i32) {
+        let (mut x, mut y) = (self.cursor.0 + dx, self.cursor.1 + dy);
+        if !map.is_infinite() {
+            let (left, top, right, bottom) = map.tile_bounds();
+            x = util::clamp(x, left, right - 1);
+            y = util::clamp(y, top, bottom - 1);
+        }
+        self.cursor = (x, y);
+    }
+
+    fn layer(&self) -> usize {
+        self.level * 3 + self.edit_layer.offset()
+    }
+
+    // gid painted on the current layer, collision layers always use the blocking tile
+    fn brush(&self, map: &Map) -> Option<u32> {
+        match self.edit_layer {
+            EditLayer::Collision => map.blocking_tile(),
+            _ => self.palette.get(self.selected).cloned(),
+        }
+    }
+
+    /// paints the selected tile under the cursor
+    pub fn paint(&mut self, map: &mut Map) {
+        if let Some(gid) = self.brush(map) {
+            let (x, y) = self.cursor;
+            let layer = self.layer();
+            self.apply(map, vec![(x, y, layer, gid)]);
+        }
+    }
+
+    /// clears the tile under the cursor
+    pub fn erase(&mut self, map: &mut Map) {
+        let (x, y) = self.cursor;
+        let layer = self.layer();
+        self.apply(map, vec![(x, y, layer, 0)]);
+    }
+
+    /// paints every tile connected to the cursor that matches the tile under it
+    pub fn flood_fill(&mut self, map: &mut Map) {
+        let gid = match self.brush(map) {
+            Some(gid) => gid,
+            None => return,
+        };
+        let layer = self.layer();
+        let target = map.tile_at(self.cursor.0, self.cursor.1, layer);
+        if target == gid {
+            return;
+        }
+
+        let (left, top, right, bottom) = map.tile_bounds();
+        let mut writes = Vec::new();
+        let mut seen = HashSet::new();
+        let mut open = VecDeque::new();
+        seen.insert(self.cursor);
+        open.push_back(self.cursor);
+        while let Some((x, y)) = open.pop_front() {
+            writes.push((x, y, layer, gid));
+            for &(nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter() {
+                if nx < left || ny < top || nx >= right || ny >= bottom {
+                    continue;
+                }
+                if map.tile_at(nx, ny, layer) == target && seen.insert((nx, ny)) {
+                    open.push_back((nx, ny));
+                }
+            }
+        }
+        self.apply(map, writes);
+    }
+
+    // writes tiles as a single undo step
+    fn apply(&mut self, map: &mut Map, writes: Vec<(i32, i32, usize, u32)>) {
+        let changes: Vec<TileChange> = writes
+            .into_iter()
+            .map(|(x, y, layer, after)| TileChange {
+                x,
+                y,
+                layer,
+                before: map.tile_at(x, y, layer),
+                after,
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        for change in changes.iter() {
+            map.set_tile_at(change.x, change.y, change.layer, change.after);
+        }
+        self.undo.push(changes);
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+        self.dirty = true;
+    }
+
+    /// reverts the last edit, returns false when there is nothing to undo
+    pub fn undo(&mut self, map: &mut Map) -> bool {
+        match self.undo.pop() {
+            Some(changes) => {
+                for change in changes.iter().rev() {
+                    map.set_tile_at(change.x, change.y, change.layer, change.before);
+                }
+                self.redo.push(changes);
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// reapplies the last undone edit, returns false when there is nothing to redo
+    pub fn redo(&mut self, map: &mut Map) -> bool {
+        match self.redo.pop() {
+            Some(changes) => {
+                for change in changes.iter() {
+                    map.set_tile_at(change.x, change.y, change.layer, change.after);
+                }
+                self.undo.push(changes);
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// world pixel rect of the tile under the cursor
+    pub fn cursor_rect(&self, map: &Map) -> Rect {
+        let grid = map.grid();
+        let top_left = grid.tile_to_pixel(self.cursor.0, self.cursor.1);
+        Rect::new(top_left.x, top_left.y, grid.tile_width, grid.tile_height)
+    }
+
+    /// draw params for the palette laid out in rows from dest, paired with
+    /// the tileset index whose image they should be drawn with
+    pub fn palette_params(
+        &self,
+        map: &Map,
+        uvs: &Vec<Option<Vec<Rect>>>,
+        dest: Point2,
+        columns: usize,
+    ) -> Vec<(usize, DrawParam)> {
+        let columns = columns.max(1);
+        self.palette
+            .iter()
+            .enumerate()
+            .filter_map(|(i, gid)| gid_params(map, uvs, *gid, palette_slot(map, dest, columns, i)))
+            .collect()
+    }
+
+    /// asks the owner of the context to call save
+    pub fn request_save(&mut self) {
+        self.save_requested = true;
+    }
+
+    pub fn save_requested(&self) -> bool {
+        self.save_requested
+    }
+
+    /// writes map to the editor's path, see util::save_tile_map
+    pub fn save(&mut self, ctx: &mut Context, map: &Map) -> GameResult<()> {
+        self.save_requested = false;
+        util::save_tile_map(ctx, &map.to_tiled(), &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// applies the editor key bindings, returns true when the editor should close
+    ///
+    /// arrows move, space paints, x erases, f fills, tab switches layer,
+    /// q and e pick tiles, z undoes, y redoes, s saves and return exits
+    pub fn handle_input(&mut self, map: &mut Map, input: &InputState) -> bool {
+        // the key just pressed, not the whole held axis, so holding one arrow
+        // while tapping another does not move diagonally
+        match input.just_pressed {
+            Some(Keycode::Up) => self.move_cursor(map, 0, -1),
+            Some(Keycode::Down) => self.move_cursor(map, 0, 1),
+            Some(Keycode::Left) => self.move_cursor(map, -1, 0),
+            Some(Keycode::Right) => self.move_cursor(map, 1, 0),
+            _ => (),
+        }
+        match input.just_pressed {
+            Some(Keycode::Space) => self.paint(map),
+            Some(Keycode::X) => self.erase(map),
+            Some(Keycode::F) => self.flood_fill(map),
+            Some(Keycode::Tab) => self.edit_layer = self.edit_layer.next(),
+            Some(Keycode::Q) => self.select_prev(),
+            Some(Keycode::E) => self.select_next(),
+            Some(Keycode::Z) => {
+                self.undo(map);
+            }
+            Some(Keycode::Y) => {
+                self.redo(map);
+            }
+            Some(Keycode::S) => self.request_save(),
+            Some(Keycode::Return) => return true,
+            _ => (),
+        }
+        false
+    }
+
+    /// draws the brush under the cursor and the palette, sprites must be
+    /// for the map being edited
+    pub fn with_context<'a>(&'a self, map: &'a Map, sprites: &'a MapSprites) -> EditorDrawContext<'a> {
+        EditorDrawContext {
+            editor: self,
+            map,
+            sprites,
+        }
+    }
+}
+
+// top left of palette entry i, laid out in rows of columns tiles from dest
+fn palette_slot(map: &Map, dest: Point2, columns: usize, i: usize) -> Point2 {
+    let grid = map.grid();
+    Point2::new(
+        dest.x + (i % columns) as f32 * grid.tile_width,
+        dest.y + (i / columns) as f32 * grid.tile_height,
+    )
+}
+
+// params drawing gid over one grid tile at dest, with the tileset to draw it from
+fn gid_params(map: &Map, uvs: &Vec<Option<Vec<Rect>>>, gid: u32, dest: Point2) -> Option<(usize, DrawParam)> {
+    let tileset = map.tileset_for_gid(gid)?;
+    let local = (gid - map.tilesets()[tileset].first_gid) as usize;
+    let src = match uvs.get(tileset) {
+        Some(&Some(ref uvs)) if local < uvs.len() => uvs[local],
+        _ => return None,
+    };
+    // uvs may be inset, stretch them over exactly one tile
+    let image = map.tilesets()[tileset].images.first()?;
+    let grid = map.grid();
+    Some((
+        tileset,
+        DrawParam {
+            src,
+            dest,
+            scale: Point2::new(
+                grid.tile_width / (src.w * image.width as f32),
+                grid.tile_height / (src.h * image.height as f32),
+            ),
+            ..Default::default()
+        },
+    ))
+}
+
+// alpha of the brush drawn over the tile under the cursor
+const BRUSH_ALPHA: f32 = 0.6;
+
+pub struct EditorDrawContext<'a> {
+    editor: &'a Editor,
+    map: &'a Map,
+    sprites: &'a MapSprites,
+}
+
+impl<'a> EditorDrawContext<'a> {
+    fn draw_tile(&self, ctx: &mut Context, tileset: usize, param: DrawParam) -> GameResult<()> {
+        match self.sprites.tileset_image(tileset) {
+            Some(image) => image.draw_ex(ctx, param),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> Drawable for EditorDrawContext<'a> {
+    fn draw_ex(&self, ctx: &mut Context, param: DrawParam) -> GameResult<()> {
+        let (editor, map) = (self.editor, self.map);
+        let uvs = self.sprites.uvs();
+        let colour = graphics::get_color(ctx);
+
+        // the brush over the tile under the cursor, then the cursor
+        let offset = map.camera_offset();
+        let cursor = editor.cursor_rect(map);
+        let at = Point2::new(
+            param.dest.x + cursor.x - offset.x,
+            param.dest.y + cursor.y - offset.y,
+        );
+        if let Some(gid) = editor.brush(map) {
+            if let Some((tileset, mut tile)) = gid_params(map, uvs, gid, at) {
+                tile.color = Some(Color::new(1.0, 1.0, 1.0, BRUSH_ALPHA));
+                self.draw_tile(ctx, tileset, tile)?;
+            }
+        }
+        graphics::set_color(ctx, Color::new(1.0, 1.0, 1.0, 1.0))?;
+        graphics::rectangle(ctx, DrawMode::Line(1.0), Rect::new(at.x, at.y, cursor.w, cursor.h))?;
+
+        // the palette in rows along the top, the selected tile outlined
+        let columns = (map.camera().w / map.grid().tile_width).max(1.0) as usize;
+        for (tileset, tile) in editor.palette_params(map, uvs, param.dest, columns) {
+            self.draw_tile(ctx, tileset, tile)?;
+        }
+        if !editor.palette.is_empty() {
+            let slot = palette_slot(map, param.dest, columns, editor.selected);
+            let grid = map.grid();
+            graphics::set_color(ctx, Color::new(1.0, 1.0, 0.0, 1.0))?;
+            graphics::rectangle(
+                ctx,
+                DrawMode::Line(1.0),
+                Rect::new(slot.x, slot.y, grid.tile_width, grid.tile_height),
+            )?;
+        }
+        graphics::set_color(ctx, colour)
+    }
+    fn set_blend_mode(&mut self, _mode: Option<graphics::BlendMode>) {}
+    fn get_blend_mode(&self) -> Option<graphics::BlendMode> {
+        None
+    }
+}
+
+/// game data an EditorState can edit
+pub trait EditorData {
+    fn editor_parts(&mut self) -> (&mut Editor, &mut Map, &InputState);
+}
+
+/// state driving an Editor, push it over gameplay to edit the current map
+/// and draw `Editor::with_context` after the map while it is active
+pub struct EditorState {
+    // camera of the state below, restored when the editor closes
+    camera: Option<Rect>,
+}
+
+impl EditorState {
+    pub fn new() -> Self {
+        EditorState { camera: None }
+    }
+}
+
+impl<T: EditorData> State<T> for EditorState {
+    fn on_start(&mut self, data: StateData<T>) {
+        let (editor, map, _) = data.data.editor_parts();
+        self.camera = Some(map.camera());
+        editor.level = map.layer_index;
+    }
+
+    fn on_stop(&mut self, data: StateData<T>) {
+        let (_, map, _) = data.data.editor_parts();
+        if let Some(camera) = self.camera.take() {
+            map.set_camera(camera);
+        }
+    }
+
+    fn state_name(&self) -> &str {
+        "EditorState"
+    }
+
+    fn update(&mut self, data: StateData<T>) -> Trans<T> {
+        let (editor, map, input) = data.data.editor_parts();
+        if editor.handle_input(map, input) {
+            return Trans::Pop;
+        }
+
+        // keep the cursor on screen
+        let cursor = editor.cursor_rect(map);
+        let mut camera = map.camera();
+        if cursor.left() < camera.left() {
+            camera.x = cursor.left();
+        } else if cursor.right() > camera.right() {
+            camera.x = cursor.right() - camera.w;
+        }
+        if cursor.top() < camera.top() {
+            camera.y = cursor.top();
+        } else if cursor.bottom() > camera.bottom() {
+            camera.y = cursor.bottom() - camera.h;
+        }
+        map.set_camera(camera);
+        Trans::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support;
+
+    #[test]
+    fn fill_undo_redo() {
+        let map_def = test_support::test_map(&["112", "121", "211"]);
+        let mut map = Map::new(&map_def, 0);
+        let mut editor = Editor::new(&map, "/map.json");
+        assert_eq!(editor.palette(), &vec![1, 2, 3, 4]);
+
+        // the top left region of 1s is bounded by the diagonal of 2s
+        editor.select(3);
+        editor.flood_fill(&mut map);
+        assert_eq!(map.tile_at(0, 0, 0), 4);
+        assert_eq!(map.tile_at(1, 0, 0), 4);
+        assert_eq!(map.tile_at(0, 1, 0), 4);
+        assert_eq!(map.tile_at(2, 1, 0), 1);
+
+        editor.move_cursor(&map, 5, 5);
+        assert_eq!(editor.cursor, (2, 2));
+        editor.erase(&mut map);
+        assert_eq!(map.tile_at(2, 2, 0), 0);
+
+        assert!(editor.undo(&mut map));
+        assert_eq!(map.tile_at(2, 2, 0), 1);
+        assert!(editor.undo(&mut map));
+        assert_eq!(map.tile_at(0, 0, 0), 1);
+        assert!(!editor.undo(&mut map));
+
+        assert!(editor.redo(&mut map));
+        assert_eq!(map.tile_at(1, 0, 0), 4);
+        assert!(editor.is_dirty());
+    }
+
+    #[test]
+    fn arrows_and_palette() {
+        let map_def = test_support::test_map(&["112", "121", "211"]);
+        let mut map = Map::new(&map_def, 0);
+        let mut editor = Editor::new(&map, "/map.json");
+
+        // tapping right while holding down moves right only
+        let mut input = InputState::default();
+        input.axis = Point2::new(1.0, 1.0);
+        input.just_pressed_axis = Some(input.axis);
+        input.just_pressed = Some(Keycode::Right);
+        editor.handle_input(&mut map, &input);
+        assert_eq!(editor.cursor, (1, 0));
+
+        // palette tiles fill one grid tile each, whatever the uv inset
+        let params = editor.palette_params(&map, &map::map_uvs(&map_def), Point2::new(0.0, 0.0), 3);
+        assert_eq!(params.len(), 4);
+        let (_, last) = params[3];
+        assert_eq!(last.dest, Point2::new(0.0, 16.0));
+        assert!((last.src.w * 32.0 * last.scale.x - 16.0).abs() < 1e-4);
+    }
+}
@@ -7,12 +7,20 @@ use sprite::{Sprite, SpriteComponent};
 pub struct Entity {
     pub(crate) id: String,
 
+    // world position of the anchor, the feet by default
     pos: Point2,
+    // pos at the start of the current fixed update, drawing lerps from it
+    prev_pos: Point2,
     pub(crate) dimensions: Point2,
+    // point of the sprite placed at pos, as a fraction of its dimensions
+    anchor: Point2,
 
+    // top left tile covered
     pub(crate) tile_x: usize,
     pub(crate) tile_y: usize,
     pub(crate) layer: usize,
+    // tiles covered to the right and down, in tiles
+    footprint: (usize, usize),
 
     frame: usize,
 }
@@ -24,11 +32,14 @@ impl Entity {
             id,
 
             pos: zero.clone(),
+            prev_pos: zero.clone(),
             dimensions,
+            anchor: Point2::new(0.5, 1.0),
 
             tile_x: 0,
             tile_y: 0,
             layer: 0,
+            footprint: (1, 1),
 
             frame: 0,
         }
@@ -50,6 +61,55 @@ impl Entity {
         self.tile_y
     }
 
+    /// covers width by height tiles from its tile, set before adding it to a map
+    pub fn with_footprint(mut self, width: usize, height: usize) -> Self {
+        self.footprint = (width.max(1), height.max(1));
+        self
+    }
+
+    pub fn footprint(&self) -> (usize, usize) {
+        self.footprint
+    }
+
+    /// tiles covered if the top left tile were x, y
+    pub fn footprint_at(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let (width, height) = self.footprint;
+        let mut tiles = Vec::with_capacity(width * height);
+        for ty in y..y + height {
+            for tx in x..x + width {
+                tiles.push((tx, ty));
+            }
+        }
+        tiles
+    }
+
+    /// tiles currently covered
+    pub fn covered_tiles(&self) -> Vec<(usize, usize)> {
+        self.footprint_at(self.tile_x, self.tile_y)
+    }
+
+    /// point of the sprite standing on its tile, 0, 0 is the top left and 1, 1 the bottom right
+    pub fn with_anchor(mut self, x: f32, y: f32) -> Self {
+        self.anchor = Point2::new(x, y);
+        self
+    }
+
+    pub fn anchor(&self) -> Point2 {
+        self.anchor
+    }
+
+    /// world position of the top left of the sprite
+    pub fn sprite_top_left(&self) -> Point2 {
+        Point2::new(
+            self.pos.x - self.anchor.x * self.dimensions.x,
+            self.pos.y - self.anchor.y * self.dimensions.y,
+        )
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
     pub fn set_frame(&mut self, frame: usize) {
         self.frame = frame;
     }
@@ -57,15 +117,50 @@ impl Entity {
     pub fn set_pos(&mut self, pos: Point2) {
         self.pos = pos;
     }
+
+    /// moves without interpolating from the old position, for teleports and spawns
+    pub fn warp_pos(&mut self, pos: Point2) {
+        self.pos = pos;
+        self.prev_pos = pos;
+    }
+
+    /// remembers pos as the start of the next interpolation, call before each fixed update
+    pub fn store_previous_pos(&mut self) {
+        self.prev_pos = self.pos;
+    }
+
+    pub fn previous_pos(&self) -> Point2 {
+        self.prev_pos
+    }
+
+    /// position alpha of the way from the previous to the current pos
+    pub fn interpolated_pos(&self, alpha: f32) -> Point2 {
+        Point2::new(
+            self.prev_pos.x + (self.pos.x - self.prev_pos.x) * alpha,
+            self.prev_pos.y + (self.pos.y - self.prev_pos.y) * alpha,
+        )
+    }
+
+    /// world position of the top left of the sprite drawn alpha of the way through a step
+    pub fn interpolated_top_left(&self, alpha: f32) -> Point2 {
+        let pos = self.interpolated_pos(alpha);
+        Point2::new(
+            pos.x - self.anchor.x * self.dimensions.x,
+            pos.y - self.anchor.y * self.dimensions.y,
+        )
+    }
 }
 
 impl SpriteComponent for Entity {
     fn setup_sprite(&self, sprite: &mut Sprite) {
+        if self.anchor != sprite.anchor() {
+            sprite.set_anchor(self.anchor);
+        }
         if self.frame != sprite.frame() {
             sprite.set_frame(self.frame);
         }
     }
     fn draw_sprite_at(&self) -> graphics::Point2 {
-        self.pos.clone()
+        self.sprite_top_left()
     }
 }
@@ -0,0 +1,409 @@
+//! Entity definitions loaded from JSON.
+//!
+//! A definitions file is an object of named definitions:
+//!
+//! ```json
+//! {
+//!     "player": {
+//!         "texture": "walk_cycle.png",
+//!         "frame_width": 16, "frame_height": 24, "start_frame": 9,
+//!         "animations": {
+//!             "walk": { "up": [0, 1, 2, 3], "right": [4, 5, 6, 7],
+//!                       "down": [8, 9, 10, 11], "left": [12, 13, 14, 15] }
+//!         },
+//!         "components": { "health": 10 }
+//!     }
+//! }
+//! ```
+//!
+//! A definition can name a sprite sheet descriptor instead of a frame size,
+//! see `spritesheet`. Its animations then give a `tag`, and `walk` plays the
+//! sheet's `walk_up`, `walk_right`, `walk_down` and `walk_left` tags, or `walk`
+//! for every facing.
+//!
+//! Paths are relative to the definitions file. Default components are
+//! deserialized into the types registered with `register_component` and
+//! added to the map's world by `Map::spawn`, next to a `Sprite` and the
+//! `Animations` of the definition.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+
+use ggez::graphics::{Image, Point2};
+use ggez::{Context, GameError, GameResult};
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+
+use anim::Animation;
+use common::Facing;
+use ecs::World;
+use entity::Entity;
+use sprite::Sprite;
+use spritesheet::SpriteSheet;
+use util;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EntityDef {
+    pub texture: String,
+    // sprite sheet descriptor, frame sizes then come from the sheet
+    #[serde(default)]
+    pub sheet: Option<String>,
+    #[serde(default)]
+    pub frame_width: f32,
+    #[serde(default)]
+    pub frame_height: f32,
+    #[serde(default)]
+    pub start_frame: usize,
+    #[serde(default = "default_footprint")]
+    pub footprint: (usize, usize),
+    #[serde(default)]
+    pub animations: HashMap<String, AnimationDef>,
+    // component name to its value, see EntityDefs::register_component
+    #[serde(default)]
+    pub components: HashMap<String, Value>,
+}
+
+fn default_footprint() -> (usize, usize) {
+    (1, 1)
+}
+
+/// frames of one animation for each facing
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AnimationDef {
+    #[serde(default)]
+    pub up: Vec<usize>,
+    #[serde(default)]
+    pub right: Vec<usize>,
+    #[serde(default)]
+    pub down: Vec<usize>,
+    #[serde(default)]
+    pub left: Vec<usize>,
+    // sheet tag filling the facings left empty
+    #[serde(default)]
+    pub tag: Option<String>,
+    // without left frames, left plays the right frames flipped, see Sprite::set_flip
+    #[serde(default)]
+    pub mirror_left: bool,
+    #[serde(rename = "loop", default)]
+    pub looping: bool,
+    // seconds per frame, 0 uses the Animation default
+    #[serde(default)]
+    pub spf: f32,
+}
+
+impl AnimationDef {
+    /// fills the empty facings from the tags of sheet, see the module docs
+    pub fn resolve(&self, sheet: &SpriteSheet) -> AnimationDef {
+        let mut def = self.clone();
+        let tag = match self.tag {
+            Some(ref tag) => tag,
+            None => return def,
+        };
+        for &(facing, suffix) in [
+            (Facing::Up, "up"),
+            (Facing::Right, "right"),
+            (Facing::Down, "down"),
+            (Facing::Left, "left"),
+        ].iter()
+        {
+            let frames = match facing {
+                Facing::Up => &mut def.up,
+                Facing::Right => &mut def.right,
+                Facing::Down => &mut def.down,
+                Facing::Left => &mut def.left,
+            };
+            if frames.is_empty() && !(facing == Facing::Left && self.mirror_left) {
+                if let Some(found) = sheet.frames(&format!("{}_{}", tag, suffix)).or_else(|| sheet.frames(tag)) {
+                    *frames = found;
+                }
+            }
+        }
+        if def.spf <= 0.0 {
+            let first = def.up.iter().chain(def.right.iter()).chain(def.down.iter()).chain(def.left.iter()).next();
+            if let Some(duration) = first.and_then(|&i| sheet.frames.get(i)).and_then(|f| f.duration) {
+                def.spf = duration;
+            }
+        }
+        def
+    }
+
+    pub fn frames(&self, facing: Facing) -> &Vec<usize> {
+        match facing {
+            Facing::Up => &self.up,
+            Facing::Right => &self.right,
+            Facing::Down => &self.down,
+            Facing::Left if self.is_mirrored(facing) => &self.right,
+            Facing::Left => &self.left,
+        }
+    }
+
+    /// true when facing plays mirrored frames and the sprite should be flipped
+    pub fn is_mirrored(&self, facing: Facing) -> bool {
+        facing == Facing::Left && self.mirror_left && self.left.is_empty()
+    }
+
+    /// None when there are no frames for facing
+    pub fn animation(&self, facing: Facing) -> Option<Animation> {
+        let frames = self.frames(facing);
+        if frames.is_empty() {
+            return None;
+        }
+        Some(Animation::new(frames.clone(), self.looping, self.spf))
+    }
+}
+
+/// component holding the animations of a spawned entity by name
+#[derive(Debug, Clone, Default)]
+pub struct Animations(pub HashMap<String, AnimationDef>);
+
+impl Animations {
+    pub fn animation(&self, name: &str, facing: Facing) -> Option<Animation> {
+        self.0.get(name).and_then(|def| def.animation(facing))
+    }
+
+    pub fn is_mirrored(&self, name: &str, facing: Facing) -> bool {
+        self.0.get(name).map_or(false, |def| def.is_mirrored(facing))
+    }
+}
+
+type ComponentLoader = Box<Fn(&str, &Value, &mut World) -> GameResult<()>>;
+
+fn json_error(err: serde_json::Error) -> GameError {
+    GameError::from(format!("entity definition error: {}", err))
+}
+
+#[derive(Default)]
+pub struct EntityDefs {
+    defs: HashMap<String, EntityDef>,
+    // textures by path as written in the definitions
+    images: HashMap<String, Image>,
+    // sheets by path as written in the definitions
+    sheets: HashMap<String, SpriteSheet>,
+    loaders: HashMap<String, ComponentLoader>,
+}
+
+impl EntityDefs {
+    pub fn new() -> Self {
+        EntityDefs::default()
+    }
+
+    /// parses definitions without loading their textures
+    pub fn parse<R: Read>(reader: R) -> GameResult<Self> {
+        let defs = serde_json::from_reader(reader).map_err(json_error)?;
+        Ok(EntityDefs {
+            defs,
+            ..EntityDefs::default()
+        })
+    }
+
+    /// reads a definitions file and loads its textures
+    pub fn load(ctx: &mut Context, path: &str) -> GameResult<Self> {
+        let file = ctx.filesystem.open(path)?;
+        let mut defs = EntityDefs::parse(file)?;
+        let textures: Vec<String> = defs.defs.values().map(|def| def.texture.clone()).collect();
+        for texture in textures {
+            if !defs.images.contains_key(&texture) {
+                let image = Image::new(ctx, util::resolve_path(path, &texture))?;
+                defs.images.insert(texture, image);
+            }
+        }
+        let sheets: Vec<String> = defs.defs.values().filter_map(|def| def.sheet.clone()).collect();
+        for sheet in sheets {
+            if !defs.sheets.contains_key(&sheet) {
+                let loaded = SpriteSheet::load(ctx, &util::resolve_path(path, &sheet))?;
+                defs.sheets.insert(sheet, loaded);
+            }
+        }
+        Ok(defs)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EntityDef> {
+        self.defs.get(name)
+    }
+
+    pub fn insert(&mut self, name: &str, def: EntityDef) {
+        self.defs.insert(name.to_string(), def);
+    }
+
+    /// texture to use for definitions naming path
+    pub fn set_image(&mut self, path: &str, image: Image) {
+        self.images.insert(path.to_string(), image);
+    }
+
+    /// sprite sheet to use for definitions naming path
+    pub fn set_sheet(&mut self, path: &str, sheet: SpriteSheet) {
+        self.sheets.insert(path.to_string(), sheet);
+    }
+
+    fn sheet(&self, def: &EntityDef) -> Option<&SpriteSheet> {
+        def.sheet.as_ref().and_then(|path| self.sheets.get(path))
+    }
+
+    /// deserializes components called name in definitions into a T
+    pub fn register_component<T: DeserializeOwned + 'static>(&mut self, name: &str) {
+        let loader = |id: &str, value: &Value, world: &mut World| {
+            let component: T = serde_json::from_value(value.clone()).map_err(json_error)?;
+            world.insert(id, component);
+            Ok(())
+        };
+        self.loaders.insert(name.to_string(), Box::new(loader));
+    }
+
+    fn def(&self, name: &str) -> GameResult<&EntityDef> {
+        self.defs
+            .get(name)
+            .ok_or_else(|| GameError::from(format!("unknown entity definition {}", name)))
+    }
+
+    /// entity for definition name, not yet placed
+    pub fn entity(&self, name: &str, id: String) -> GameResult<Entity> {
+        let def = self.def(name)?;
+        let mut size = Point2::new(def.frame_width, def.frame_height);
+        if let Some(frame) = self.sheet(def).and_then(|sheet| sheet.frames.first()) {
+            size = frame.source_size;
+        }
+        let mut e = Entity::new(id, size)
+            .with_footprint(def.footprint.0, def.footprint.1);
+        e.set_frame(def.start_frame);
+        Ok(e)
+    }
+
+    /// adds the sprite, animations and default components of definition name to id
+    pub fn insert_components(&self, name: &str, id: &str, world: &mut World) -> GameResult<()> {
+        let def = self.def(name)?;
+        for (component, value) in def.components.iter() {
+            match self.loaders.get(component) {
+                Some(loader) => loader(id, value, world)?,
+                None => return Err(GameError::from(format!("unknown component {}", component))),
+            }
+        }
+        let sheet = self.sheet(def);
+        if !def.animations.is_empty() {
+            let animations = def
+                .animations
+                .iter()
+                .map(|(name, anim)| (name.clone(), sheet.map_or_else(|| anim.clone(), |s| anim.resolve(s))))
+                .collect();
+            world.insert(id, Animations(animations));
+        }
+        if let Some(image) = self.images.get(&def.texture) {
+            let mut sprite = match sheet {
+                Some(sheet) => Sprite::from_sheet(image.clone(), sheet),
+                None => Sprite::new(image.clone(), def.frame_width, def.frame_height),
+            };
+            sprite.set_frame(def.start_frame);
+            world.insert(id, sprite);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for EntityDefs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EntityDefs")
+            .field("defs", &self.defs.keys().collect::<Vec<_>>())
+            .field("components", &self.loaders.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map::{Map, MapSystems};
+    use test_support;
+
+    const DEFS: &str = r#"{
+        "slime": {
+            "texture": "slime.png", "frame_width": 16, "frame_height": 16, "start_frame": 2,
+            "animations": { "hop": { "down": [2, 3], "right": [4], "mirror_left": true, "loop": true } },
+            "components": { "health": { "hp": 3 } }
+        },
+        "cart": { "texture": "cart.png", "frame_width": 32, "frame_height": 16,
+                  "footprint": [2, 1], "components": { "speed": 2 } },
+        "hero": { "texture": "hero.png", "sheet": "hero.json",
+                  "animations": { "walk": { "tag": "walk", "left": [3] } } }
+    }"#;
+
+    const SHEET: &str = r#"{
+        "frames": [
+            { "filename": "0", "frame": { "x": 0, "y": 0, "w": 16, "h": 24 }, "duration": 200 },
+            { "filename": "1", "frame": { "x": 16, "y": 0, "w": 16, "h": 24 }, "duration": 200 },
+            { "filename": "2", "frame": { "x": 32, "y": 0, "w": 16, "h": 24 }, "duration": 200 },
+            { "filename": "3", "frame": { "x": 48, "y": 0, "w": 16, "h": 24 }, "duration": 200 }
+        ],
+        "meta": { "size": { "w": 64, "h": 24 }, "frameTags": [
+            { "name": "walk_up", "from": 0, "to": 1 },
+            { "name": "walk", "from": 2, "to": 3 }
+        ] }
+    }"#;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Health {
+        hp: i32,
+    }
+
+    #[test]
+    fn spawn_from_definitions() {
+        let mut defs = EntityDefs::parse(DEFS.as_bytes()).unwrap();
+        defs.register_component::<Health>("health");
+
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        map.set_entity_defs(defs);
+
+        let slime = map.spawn("slime", 1, 1, 0).unwrap();
+        let other = map.spawn("slime", 2, 1, 0).unwrap();
+        assert_ne!(slime, other);
+        assert_eq!(map.entity(1, 1, 0).map(|e| e.id.clone()), Some(slime.clone()));
+        assert_eq!(map.world().get::<Health>(&slime), Some(&Health { hp: 3 }));
+
+        let hop = map.world().get::<Animations>(&slime).unwrap();
+        assert!(hop.animation("hop", Facing::Up).is_none());
+        assert_eq!(hop.animation("hop", Facing::Down).unwrap().frame(), 2);
+        assert_eq!(hop.animation("hop", Facing::Left).unwrap().frame(), 4);
+        assert!(hop.is_mirrored("hop", Facing::Left) && !hop.is_mirrored("hop", Facing::Right));
+
+        // taken tiles, unknown definitions and unregistered components fail without leftovers
+        assert!(map.spawn("slime", 1, 1, 0).is_err());
+        assert!(map.spawn("dragon", 0, 0, 0).is_err());
+        assert!(map.spawn("cart", 0, 0, 0).is_err());
+        assert!(map.entity(0, 0, 0).is_none());
+        assert_eq!(map.world().entities().count(), 2);
+
+        // animations by sheet tag, explicit frames win
+        map.entity_defs_mut().set_sheet("hero.json", SpriteSheet::parse(SHEET.as_bytes()).unwrap());
+        let hero = map.spawn("hero", 0, 0, 0).unwrap();
+        assert_eq!(map.entity_by_id(&hero).unwrap().dimensions, Point2::new(16.0, 24.0));
+        let walk = &map.world().get::<Animations>(&hero).unwrap().0["walk"];
+        assert_eq!(walk.up, vec![0, 1]);
+        assert_eq!(walk.down, vec![2, 3]);
+        assert_eq!(walk.left, vec![3]);
+        assert!((walk.spf - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn spawn_from_a_system() {
+        let mut defs = EntityDefs::parse(DEFS.as_bytes()).unwrap();
+        defs.register_component::<Health>("health");
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        map.set_entity_defs(defs);
+        let first = map.spawn("slime", 0, 0, 0).unwrap();
+
+        let mut spawned = Vec::new();
+        {
+            let mut systems = MapSystems::new();
+            systems.add(|map: &mut Map| spawned.push(map.spawn("slime", 1, 1, 0).unwrap()));
+            map.run_systems(&mut systems);
+        }
+
+        // a new id, with its components in the map's world once the systems are done
+        let id = &spawned[0];
+        assert_ne!(id, &first);
+        assert_eq!(map.entity(1, 1, 0).map(|e| &e.id), Some(id));
+        assert_eq!(map.world().get::<Health>(id), Some(&Health { hp: 3 }));
+        assert!(map.world().get::<Animations>(id).is_some());
+        assert_eq!(map.world().query().with::<Health>().ids().len(), 2);
+    }
+}
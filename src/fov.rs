@@ -0,0 +1,178 @@
+//! Field of view and fog of war.
+//!
+//! Visibility uses recursive shadowcasting with collision tiles as opaque.
+//! `FogOfWar` remembers what has been seen so the map renderer can darken
+//! explored tiles that are out of sight and hide unexplored ones.
+use std::collections::{HashMap, HashSet};
+
+use chunk::chunk_coord;
+use map::Map;
+
+/// colour multiplier for explored tiles that are out of sight
+pub const UNSEEN_SHADE: f32 = 0.4;
+
+// transforms from octant space, where rows move up and columns go left, to map space
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1),
+];
+
+/// tiles visible from origin within radius, opaque tiles are visible but block what is behind them
+pub fn compute_fov<F>(origin: (i32, i32), radius: i32, is_opaque: F) -> HashSet<(i32, i32)>
+where
+    F: Fn(i32, i32) -> bool,
+{
+    let mut visible = HashSet::new();
+    visible.insert(origin);
+    for octant in OCTANTS.iter() {
+        cast_light(&mut visible, origin, radius, 1, 1.0, 0.0, *octant, &is_opaque);
+    }
+    visible
+}
+
+fn cast_light<F>(
+    visible: &mut HashSet<(i32, i32)>,
+    origin: (i32, i32),
+    radius: i32,
+    row: i32,
+    mut start: f32,
+    end: f32,
+    octant: (i32, i32, i32, i32),
+    is_opaque: &F,
+) where
+    F: Fn(i32, i32) -> bool,
+{
+    if start < end {
+        return;
+    }
+    let (xx, xy, yx, yy) = octant;
+    let mut new_start = 0.0;
+    for j in row..radius + 1 {
+        let dy = -j;
+        let mut blocked = false;
+        for dx in -j..1 {
+            // slopes of the left and right edges of this cell
+            let l_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+            let r_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+            if start < r_slope {
+                continue;
+            } else if end > l_slope {
+                break;
+            }
+
+            let x = origin.0 + dx * xx + dy * xy;
+            let y = origin.1 + dx * yx + dy * yy;
+            if dx * dx + dy * dy <= radius * radius {
+                visible.insert((x, y));
+            }
+
+            if blocked {
+                if is_opaque(x, y) {
+                    new_start = r_slope;
+                } else {
+                    blocked = false;
+                    start = new_start;
+                }
+            } else if is_opaque(x, y) && j < radius {
+                // scan the lit part past this blocker, then continue beside it
+                blocked = true;
+                cast_light(visible, origin, radius, j + 1, start, l_slope, octant, is_opaque);
+                new_start = r_slope;
+            }
+        }
+        if blocked {
+            break;
+        }
+    }
+}
+
+/// tiles of level visible from tiled coordinates origin
+pub fn field_of_view(map: &Map, level: usize, origin: (i32, i32), radius: i32) -> HashSet<(i32, i32)> {
+    compute_fov(origin, radius, |x, y| map.is_opaque_at(level, x, y))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FogOfWar {
+    visible: HashSet<(i32, i32)>,
+    explored: HashSet<(i32, i32)>,
+    // map revision when the fog last changed in a chunk, so cached batches are rebuilt
+    revisions: HashMap<(i32, i32), u64>,
+}
+
+impl FogOfWar {
+    pub fn new() -> Self {
+        FogOfWar::default()
+    }
+
+    pub fn is_visible(&self, x: i32, y: i32) -> bool {
+        self.visible.contains(&(x, y))
+    }
+
+    pub fn is_explored(&self, x: i32, y: i32) -> bool {
+        self.explored.contains(&(x, y))
+    }
+
+    pub fn explored(&self) -> &HashSet<(i32, i32)> {
+        &self.explored
+    }
+
+    /// replaces the visible tiles, marking every chunk whose fog changed with revision
+    pub fn update(&mut self, visible: HashSet<(i32, i32)>, revision: u64) {
+        for &(x, y) in self.visible.symmetric_difference(&visible) {
+            self.revisions.insert((chunk_coord(x), chunk_coord(y)), revision);
+        }
+        self.explored.extend(visible.iter().cloned());
+        self.visible = visible;
+    }
+
+    /// revision of the last fog change in a chunk
+    pub fn chunk_revision(&self, cx: i32, cy: i32) -> Option<u64> {
+        self.revisions.get(&(cx, cy)).cloned()
+    }
+
+    /// colour multiplier for a tile, None when it should not be drawn
+    pub fn shade(&self, x: i32, y: i32) -> Option<f32> {
+        if self.is_visible(x, y) {
+            Some(1.0)
+        } else if self.is_explored(x, y) {
+            Some(UNSEEN_SHADE)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support;
+
+    #[test]
+    fn walls_cast_shadows() {
+        // a wall across the middle with a gap at the left end
+        let map_def = test_support::test_map(&["11111", "11111", "11###", "11111", "11111"]);
+        let map = Map::new(&map_def, 0);
+
+        let visible = field_of_view(&map, 0, (3, 4), 8);
+        // the wall itself is seen, what is straight behind it is not
+        assert!(visible.contains(&(3, 2)));
+        assert!(!visible.contains(&(3, 1)));
+        assert!(!visible.contains(&(4, 0)));
+        // the gap on the left lets light through
+        assert!(visible.contains(&(0, 1)));
+
+        let mut fog = FogOfWar::new();
+        fog.update(visible, 1);
+        fog.update(field_of_view(&map, 0, (0, 0), 1), 2);
+        assert_eq!(fog.shade(0, 0), Some(1.0));
+        assert_eq!(fog.shade(3, 4), Some(UNSEEN_SHADE));
+        assert_eq!(fog.shade(4, 0), None);
+        assert_eq!(fog.chunk_revision(0, 0), Some(2));
+    }
+}
@@ -39,6 +39,7 @@ impl ggez::event::EventHandler for InputState {
         if _repeat {
             return;
         }
+        self.just_pressed = Some(keycode);
         let current = self.axis;
         match keycode {
             Keycode::Up => {
@@ -1,20 +1,44 @@
 #[macro_use]
 extern crate serde_derive;
 extern crate ggez;
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
 extern crate tiled;
+extern crate xml;
 
 #[macro_use]
 extern crate derivative;
 
 pub mod anim;
 pub mod actions;
+pub mod atlas;
 pub mod character;
+pub mod chunk;
 pub mod common;
+pub mod ecs;
+pub mod editor;
 pub mod entity;
+pub mod entity_def;
+pub mod fov;
 pub mod input;
 pub mod map;
+pub mod minimap;
+pub mod orientation;
+pub mod physics;
+pub mod procgen;
+pub mod region;
+pub mod screen;
+pub mod sight;
 pub mod sprite;
+pub mod spritesheet;
 pub mod state;
+#[cfg(test)]
+mod test_support;
+pub mod tiled_json;
+pub mod tiled_tmx;
+pub mod tiled_writer;
+pub mod timestep;
 pub mod trigger;
 pub mod tween;
 pub mod util;
\ No newline at end of file
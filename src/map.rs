@@ -1,12 +1,22 @@
 use ggez::graphics;
 use ggez::graphics::spritebatch::SpriteBatch;
 use ggez::graphics::{Point2, Rect};
-use ggez::{Context, GameResult};
+use ggez::{Context, GameError, GameResult};
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::error;
+use std::fmt;
 
+use chunk::{self, TileLayer, CHUNK_SIZE};
+use ecs::{Systems, World};
+use entity_def::EntityDefs;
+use fov::{self, FogOfWar};
+use orientation::{Orientation, TileGrid};
+use region::{Region, RegionEvent};
 use sprite::{Sprite, SpriteComponent};
 use tiled;
+use tiled_json;
 use util;
 use entity;
 
@@ -19,45 +29,112 @@ pub struct Map {
     pos: Point2,
     camera: Rect,
 
-    layers: Vec<tiled::Layer>,
+    layers: Vec<TileLayer>,
     tilesets: Vec<tiled::Tileset>,
+    // kept as loaded so the map can be saved again
+    object_groups: Vec<tiled::ObjectGroup>,
+    image_layers: Vec<tiled::ImageLayer>,
+    properties: tiled::Properties,
+    background_colour: Option<tiled::Colour>,
+    version: String,
+
+    // tiled coordinate of the top left tile, non zero for infinite maps
+    origin: (i32, i32),
+    infinite: bool,
+    // bumped on every tile write so caches can tell what changed
+    revision: u64,
 
     // layer index to use
-    layer_index: usize,
-    // tileset to use
-    tile_set: usize,
+    pub layer_index: usize,
 
     dimensions: Point2,
     tile_dimensions: Point2,
     pixel_dimensions: Point2,
+    grid: TileGrid,
 
     // gid of tileset with blocking layer
     blocking_tile: Option<u32>,
+    // what has been seen, None draws every tile
+    fog: Option<FogOfWar>,
 
+    // entities by layer, keyed by the index of their top left tile
     entities: Vec<HashMap<usize, entity::Entity>>,
+    // id of the entity covering each occupied tile index, by layer
+    occupancy: Vec<HashMap<usize, String>>,
     entityid_to_index: HashMap<String, IndexPair>,
+    // components of the entities, keyed by the same ids
+    world: World,
+    // what spawn builds entities from
+    entity_defs: EntityDefs,
+    spawned: usize,
+
+    regions: Vec<Region>,
+    // ids of the regions each entity is in
+    entity_regions: HashMap<String, Vec<u32>>,
+    region_events: Vec<RegionEvent>,
 }
 
+// tiled stores flip flags in the top bits of a gid
+pub(crate) const GID_MASK: u32 = 0x1FFF_FFFF;
+
+// tileset used to paint blocking tiles, never drawn
+pub(crate) const COLLISION_TILESET: &str = "collision_graphic";
+
+// texels trimmed from each side of drawn tiles so they never sample their neighbours
+pub const UV_INSET: f32 = 0.5;
+
+// chunks kept cached around the camera, in chunks
+const CACHE_MARGIN: i32 = 1;
+
 impl Map {
-    pub fn new(map_def: &tiled::Map, layer_index: usize, tile_set: usize) -> Self {
-        let zero = Point2::new(0.0, 0.0);
-        let layers = map_def.layers.clone();
+    pub fn new(map_def: &tiled::Map, layer_index: usize) -> Self {
+        let infinite = match map_def.properties.get(tiled_json::INFINITE_PROPERTY) {
+            Some(&tiled::PropertyValue::BoolValue(infinite)) => infinite,
+            _ => false,
+        };
+        let origin = if infinite {
+            let origin = |name| match map_def.properties.get(name) {
+                Some(&tiled::PropertyValue::IntValue(v)) => v,
+                _ => 0,
+            };
+            (
+                origin(tiled_json::ORIGIN_X_PROPERTY),
+                origin(tiled_json::ORIGIN_Y_PROPERTY),
+            )
+        } else {
+            (0, 0)
+        };
+        let layers = map_def
+            .layers
+            .iter()
+            .map(|layer| {
+                if infinite {
+                    TileLayer::from_tiled_chunks(layer)
+                } else {
+                    TileLayer::from_tiled(layer, 0, 0)
+                }
+            })
+            .collect();
         let tilesets = map_def.tilesets.clone();
         let mut blocking_tile = None;
 
         let dimensions = Point2::new(map_def.width as f32, map_def.height as f32);
         let tile_dimensions = Point2::new(
-                map_def.tilesets[tile_set].tile_width as f32,
-                map_def.tilesets[tile_set].tile_height as f32,
+                map_def.tile_width as f32,
+                map_def.tile_height as f32,
             );
+        let grid = TileGrid::new(
+            Orientation::from_tiled(map_def),
+            tile_dimensions.x,
+            tile_dimensions.y,
+            map_def.height as i32,
+        );
+        let pos = grid.tile_to_pixel(origin.0, origin.1);
 
-        let pixel_dimensions = Point2::new(
-                (map_def.tilesets[tile_set].tile_width * map_def.width) as f32,
-                (map_def.tilesets[tile_set].tile_height * map_def.height) as f32,
-            );
+        let pixel_dimensions = grid.map_pixel_size(map_def.width as i32, map_def.height as i32);
 
         for tileset in tilesets.iter() {
-            if tileset.name == "collision_graphic" {
+            if tileset.name == COLLISION_TILESET {
                 blocking_tile = Some(tileset.first_gid);
                 break;
             }
@@ -67,21 +144,38 @@ impl Map {
         let entities = Vec::new();
 
         Map {
-            pos: zero.clone(),
+            pos,
             camera: Rect::new(0.0, 0.0, 1.0, 1.0),
 
             layers,
             tilesets,
+            object_groups: map_def.object_groups.clone(),
+            image_layers: map_def.image_layers.clone(),
+            properties: map_def.properties.clone(),
+            background_colour: map_def.background_colour,
+            version: map_def.version.clone(),
+            origin,
+            infinite,
+            revision: 0,
             layer_index,
-            tile_set,
             blocking_tile,
-            
+            fog: None,
+
             dimensions,
             tile_dimensions,
             pixel_dimensions,
+            grid,
 
             entities,
+            occupancy: Vec::new(),
             entityid_to_index: HashMap::new(),
+            world: World::new(),
+            entity_defs: EntityDefs::new(),
+            spawned: 0,
+
+            regions: Region::from_groups(&map_def.object_groups),
+            entity_regions: HashMap::new(),
+            region_events: Vec::new(),
         }
     }
 
@@ -90,15 +184,215 @@ impl Map {
     }
 
     pub fn get_tile(&self, x: usize, y: usize, layer: usize) -> usize {
-        self.layers[layer].tiles[y][x] as usize
+        self.tile_at(self.origin.0 + x as i32, self.origin.1 + y as i32, layer) as usize
+    }
+
+    /// tile at tiled coordinates, which can be negative on infinite maps
+    pub fn tile_at(&self, x: i32, y: i32, layer: usize) -> u32 {
+        self.layers[layer].get(x, y)
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        self.infinite
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// index of the tileset owning gid, if any
+    pub fn tileset_for_gid(&self, gid: u32) -> Option<usize> {
+        let gid = gid & GID_MASK;
+        if gid == 0 {
+            return None;
+        }
+        // owning tileset has the largest first_gid not past gid
+        self.tilesets
+            .iter()
+            .enumerate()
+            .filter(|&(_, tileset)| tileset.first_gid <= gid)
+            .max_by_key(|&(_, tileset)| tileset.first_gid)
+            .map(|(i, _)| i)
+    }
+
+    pub fn tilesets(&self) -> &Vec<tiled::Tileset> {
+        &self.tilesets
+    }
+
+    pub fn object_groups(&self) -> &Vec<tiled::ObjectGroup> {
+        &self.object_groups
+    }
+
+    pub fn object_groups_mut(&mut self) -> &mut Vec<tiled::ObjectGroup> {
+        &mut self.object_groups
+    }
+
+    pub fn regions(&self) -> &Vec<Region> {
+        &self.regions
+    }
+
+    /// regions containing the world pixel x, y
+    pub fn regions_at(&self, x: f32, y: f32) -> Vec<&Region> {
+        self.regions.iter().filter(|r| r.contains(x, y)).collect()
+    }
+
+    /// region events since the last call, in the order they happened
+    pub fn drain_region_events(&mut self) -> Vec<RegionEvent> {
+        ::std::mem::replace(&mut self.region_events, Vec::new())
+    }
+
+    // queues leave and enter events for an entity now at point, None when it left the map
+    fn update_entity_regions(&mut self, id: &str, point: Option<Point2>) {
+        let now: Vec<u32> = match point {
+            Some(p) => self.regions_at(p.x, p.y).iter().map(|r| r.id).collect(),
+            None => Vec::new(),
+        };
+        let before = self.entity_regions.remove(id).unwrap_or_default();
+        for region in before.iter().filter(|r| !now.contains(r)) {
+            self.region_events.push(RegionEvent::Leave {
+                entity: id.to_string(),
+                region: *region,
+            });
+        }
+        for region in now.iter().filter(|r| !before.contains(r)) {
+            self.region_events.push(RegionEvent::Enter {
+                entity: id.to_string(),
+                region: *region,
+            });
+        }
+        if !now.is_empty() {
+            self.entity_regions.insert(id.to_string(), now);
+        }
+    }
+
+    pub fn image_layers(&self) -> &Vec<tiled::ImageLayer> {
+        &self.image_layers
+    }
+
+    /// screen space params for an image layer
+    /// a parallax below 1 scrolls slower than the tiles, above 1 faster
+    pub fn image_layer_params(&self, index: usize) -> graphics::DrawParam {
+        let layer = &self.image_layers[index];
+        let parallax = image_layer_parallax(layer);
+        let mut params = graphics::DrawParam::default();
+        params.dest = util::snap(Point2::new(
+            layer.offset_x - self.camera.left() * parallax.x,
+            layer.offset_y - self.camera.top() * parallax.y,
+        ));
+        if layer.opacity < 1.0 {
+            params.color = Some(graphics::Color::new(1.0, 1.0, 1.0, layer.opacity));
+        }
+        params
+    }
+
+    /// rebuilds a tiled map from the current tiles, objects and triggers
+    /// so edited or generated maps can be saved, see util::save_tile_map
+    pub fn to_tiled(&self) -> tiled::Map {
+        let mut properties = self.properties.clone();
+
+        let (width, height) = (self.dimensions.x as i32, self.dimensions.y as i32);
+        let mut bounds = (self.origin.0, self.origin.1, self.origin.0 + width, self.origin.1 + height);
+        if self.infinite {
+            // writes can grow an infinite map past what was loaded
+            for layer_bounds in self.layers.iter().filter_map(|layer| layer.bounds()) {
+                bounds = (
+                    bounds.0.min(layer_bounds.0),
+                    bounds.1.min(layer_bounds.1),
+                    bounds.2.max(layer_bounds.2),
+                    bounds.3.max(layer_bounds.3),
+                );
+            }
+            properties.insert(
+                tiled_json::INFINITE_PROPERTY.to_string(),
+                tiled::PropertyValue::BoolValue(true),
+            );
+            properties.insert(
+                tiled_json::ORIGIN_X_PROPERTY.to_string(),
+                tiled::PropertyValue::IntValue(bounds.0),
+            );
+            properties.insert(
+                tiled_json::ORIGIN_Y_PROPERTY.to_string(),
+                tiled::PropertyValue::IntValue(bounds.1),
+            );
+        }
+        let (left, top, right, bottom) = bounds;
+        let orientation = self.grid.orientation.to_tiled(&mut properties);
+
+        tiled::Map {
+            version: self.version.clone(),
+            orientation,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+            tile_width: self.tile_dimensions.x as u32,
+            tile_height: self.tile_dimensions.y as u32,
+            tilesets: self.tilesets.clone(),
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| {
+                    if self.infinite {
+                        layer.to_tiled_chunks()
+                    } else {
+                        layer.to_tiled(left, top, right - left, bottom - top)
+                    }
+                })
+                .collect(),
+            image_layers: self.image_layers.clone(),
+            object_groups: self.object_groups.clone(),
+            properties,
+            background_colour: self.background_colour,
+        }
+    }
+
+    /// width and height of a tile in pixels
+    pub fn tile_dimensions(&self) -> Point2 {
+        self.tile_dimensions
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.grid.orientation
+    }
+
+    pub fn grid(&self) -> &TileGrid {
+        &self.grid
+    }
+
+    /// world pixel centre of the tile at map coordinates x, y
+    pub fn tile_centre(&self, x: usize, y: usize) -> graphics::Point2 {
+        self.grid
+            .tile_center(self.origin.0 + x as i32, self.origin.1 + y as i32)
     }
 
     pub fn get_tile_foot(&self, x: usize, y: usize) -> graphics::Point2 {
-        let x = self.pos.x + (self.tile_dimensions.x * x as f32) + self.tile_dimensions.x / 2.0;
-        let y = self.pos.y + (self.tile_dimensions.y * y as f32) + self.tile_dimensions.y;
+        let (x, y) = (self.origin.0 + x as i32, self.origin.1 + y as i32);
+        let top_left = self.grid.tile_to_pixel(x, y);
+        let x = top_left.x + self.tile_dimensions.x / 2.0;
+        let y = top_left.y + self.tile_dimensions.y;
         Point2::new(x, y)
     }
 
+    /// bottom centre of a width by height footprint with its top left tile at x, y
+    /// where an entity anchored at its feet stands
+    pub fn footprint_foot(&self, footprint: (usize, usize), x: usize, y: usize) -> graphics::Point2 {
+        let bottom = y + footprint.1.max(1) - 1;
+        let left = self.get_tile_foot(x, bottom);
+        let right = self.get_tile_foot(x + footprint.0.max(1) - 1, bottom);
+        Point2::new((left.x + right.x) / 2.0, (left.y + right.y) / 2.0)
+    }
+
+    pub fn camera(&self) -> Rect {
+        self.camera
+    }
+
+    /// top left of the camera rounded to whole pixels, what world positions are drawn relative to
+    pub fn camera_offset(&self) -> Point2 {
+        util::snap(Point2::new(self.camera.left(), self.camera.top()))
+    }
+
+    pub fn set_camera(&mut self, camera: Rect) {
+        self.camera = camera;
+    }
+
     // Sets the camera position to point
     pub fn goto(&mut self, pos: graphics::Point2) {
         self.camera.x = pos.x;
@@ -107,9 +401,10 @@ impl Map {
 
     // Sets the camera position to tile
     pub fn goto_tile(&mut self, x: usize, y: usize) {
-        let x = (self.tile_dimensions.x * x as f32) + (0.5 * self.tile_dimensions.x);
-        let y = (self.tile_dimensions.y * y as f32) + (0.5 * self.tile_dimensions.y);
-        self.goto(Point2::new(x, y));
+        let center = self
+            .grid
+            .tile_center(self.origin.0 + x as i32, self.origin.1 + y as i32);
+        self.goto(center);
     }
 
     pub fn is_blocked(&self, layer: usize, tile_x: usize, tile_y: usize) -> bool {
@@ -122,6 +417,44 @@ impl Map {
         }
     }
 
+    /// true when the collision layer of level has a collision tile at tiled coordinates x, y
+    pub fn is_blocked_at(&self, level: usize, x: i32, y: i32) -> bool {
+        match self.blocking_tile {
+            None => false,
+            Some(blocking_tile) => self.tile_at(x, y, level * 3 + 2) == blocking_tile,
+        }
+    }
+
+    /// true when the collision layer of level blocks sight at tiled coordinates x, y
+    pub fn is_opaque_at(&self, level: usize, x: i32, y: i32) -> bool {
+        self.is_blocked_at(level, x, y)
+    }
+
+    /// starts tracking explored tiles, unexplored tiles are no longer drawn
+    pub fn enable_fog(&mut self) {
+        if self.fog.is_none() {
+            self.fog = Some(FogOfWar::new());
+        }
+    }
+
+    pub fn fog(&self) -> Option<&FogOfWar> {
+        self.fog.as_ref()
+    }
+
+    /// recomputes what e can see within radius tiles, does nothing unless fog is enabled
+    pub fn update_fov(&mut self, e: &entity::Entity, radius: i32) {
+        if self.fog.is_none() {
+            return;
+        }
+        let origin = (self.origin.0 + e.tile_x as i32, self.origin.1 + e.tile_y as i32);
+        let visible = fov::field_of_view(self, e.layer, origin, radius);
+        self.revision += 1;
+        let revision = self.revision;
+        if let Some(ref mut fog) = self.fog {
+            fog.update(visible, revision);
+        }
+    }
+
     pub fn layer_count(&self) -> usize {
         assert!(
             self.layers.len() % 3 == 0,
@@ -131,76 +464,310 @@ impl Map {
     }
 
     /// converts world pixel coordinates to tile in map
+    /// points outside the map resolve to the nearest edge tile
     pub fn point_to_tile(&self, x: f32, y: f32) -> (usize, usize) {
-        let x = util::clamp(x, self.pos.x, self.pos.y + self.pixel_dimensions.x - 1.0);
-        let y = util::clamp(y, self.pos.y, self.pos.y + self.pixel_dimensions.y - 1.0);
+        let (tile_x, tile_y) = self.grid.pixel_to_tile(x, y);
 
-        let tile_x = ((x - self.pos.x) / self.tile_dimensions.x).floor();
-        let tile_y = ((y - self.pos.y) / self.tile_dimensions.y).floor();
+        let tile_x = util::clamp(tile_x - self.origin.0, 0, self.dimensions.x as i32 - 1);
+        let tile_y = util::clamp(tile_y - self.origin.1, 0, self.dimensions.y as i32 - 1);
 
         (tile_x as usize, tile_y as usize)
     }
 
+    /// converts world pixel coordinates to tiled coordinates without clamping
+    pub fn point_to_tile_at(&self, x: f32, y: f32) -> (i32, i32) {
+        self.grid.pixel_to_tile(x, y)
+    }
+
     // overrides the layer tile
     pub fn write_tile(&mut self, x: usize, y: usize, layer: usize, tile: u32, detail: Option<u32>, collision: bool) {
+        let (x, y) = (self.origin.0 + x as i32, self.origin.1 + y as i32);
+        self.write_tile_at(x, y, layer, tile, detail, collision);
+    }
+
+    /// write_tile using tiled coordinates
+    pub fn write_tile_at(&mut self, x: i32, y: i32, layer: usize, tile: u32, detail: Option<u32>, collision: bool) {
+        self.revision += 1;
+        let revision = self.revision;
         let layer = layer * 3;
-        self.layers[layer].tiles[y][x] = tile;
+        self.layers[layer].set(x, y, tile, revision);
         if let Some(d) = detail {
-            self.layers[layer + 1].tiles[y][x] = d;
+            self.layers[layer + 1].set(x, y, d, revision);
         }
         if collision {
             if let Some(blocking_tile) = self.blocking_tile {
-                self.layers[layer + 2].tiles[y][x] = blocking_tile;
+                self.layers[layer + 2].set(x, y, blocking_tile, revision);
             }
         }
 
     }
 
+    /// sets a single tiled layer, unlike write_tile layer indexes self.layers
+    /// directly so decoration and collision can be cleared
+    pub fn set_tile_at(&mut self, x: i32, y: i32, layer: usize, tile: u32) {
+        self.revision += 1;
+        let revision = self.revision;
+        self.layers[layer].set(x, y, tile, revision);
+    }
+
+    /// gid painted on collision layers, if the map has a collision tileset
+    pub fn blocking_tile(&self) -> Option<u32> {
+        self.blocking_tile
+    }
+
+    /// tiled coordinates covered by the map as (left, top, right, bottom), right and bottom exclusive
+    pub fn tile_bounds(&self) -> (i32, i32, i32, i32) {
+        (
+            self.origin.0,
+            self.origin.1,
+            self.origin.0 + self.dimensions.x as i32,
+            self.origin.1 + self.dimensions.y as i32,
+        )
+    }
+
+    /// draw params for the tile at tile_x, tile_y
+    /// uvs are those of tileset, see map_uvs, and tile_id is local to it
     pub fn tile_draw_params(
         &self,
         uvs: &Vec<Rect>,
+        tileset: usize,
         tile_x: usize,
         tile_y: usize,
-        tile: usize,
+        tile_id: usize,
     ) -> graphics::DrawParam {
-        let x: f32 = self.pos.x + self.tile_dimensions.x * tile_x as f32;
-        let y: f32 = self.pos.y + self.tile_dimensions.y * tile_y as f32;
+        let (x, y) = (self.origin.0 + tile_x as i32, self.origin.1 + tile_y as i32);
+        let mut params = self.tile_world_params(uvs, tileset, x, y, tile_id);
+        let offset = self.camera_offset();
+        params.dest.x -= offset.x;
+        params.dest.y -= offset.y;
+        params
+    }
 
-        // subtract 1 because tiled indexes by 1
-        let uv = uvs[tile - 1];
+    /// draw params in world space for the tile at tiled coordinates x, y
+    pub fn tile_world_params(
+        &self,
+        uvs: &Vec<Rect>,
+        tileset: usize,
+        x: i32,
+        y: i32,
+        tile_id: usize,
+    ) -> graphics::DrawParam {
+        let uv = uvs[tile_id];
         // println!("wh: {} {}", uv.left() * self.map_pixel_width, uv.right() * self.map_pixel_width);
 
         let mut params = graphics::DrawParam::default();
         params.src = uv;
-        params.dest = Point2::new(-self.camera.left() + x, -self.camera.top() + y);
-        // TODO: Figure out reason for this hack
-        // have to scale otherwise it looks like tearing
-        params.scale = Point2::new(1.1, 1.1);
+        params.dest = self.grid.tile_to_pixel(x, y);
+        // map_uvs are inset, stretch them back over exactly one tile
+        if let Some(tileset) = self.tilesets.get(tileset) {
+            if let Some(image) = tileset.images.first() {
+                params.scale = Point2::new(
+                    tileset.tile_width as f32 / (uv.w * image.width as f32),
+                    tileset.tile_height as f32 / (uv.h * image.height as f32),
+                );
+            }
+        }
         params
     }
 
-    /// entity functions
+    // world space params for gid and the tileset batch it belongs in
+    fn gid_draw_params(
+        &self,
+        uvs: &Vec<Option<Vec<Rect>>>,
+        x: i32,
+        y: i32,
+        gid: u32,
+    ) -> Option<(usize, graphics::DrawParam)> {
+        let ts = self.tileset_for_gid(gid)?;
+        let tileset = &self.tilesets[ts];
+        let uvs = match uvs[ts] {
+            Some(ref uvs) => uvs,
+            None => return None,
+        };
+        let tile_id = ((gid & GID_MASK) - tileset.first_gid) as usize;
+        if tile_id >= uvs.len() {
+            return None;
+        }
+        let mut params = self.tile_world_params(uvs, ts, x, y, tile_id);
+        // tiled aligns tiles larger than the grid to the bottom left of the cell
+        params.dest.y += self.tile_dimensions.y - tileset.tile_height as f32;
+        if let Some(ref fog) = self.fog {
+            let shade = fog.shade(x, y)?;
+            if shade < 1.0 {
+                params.color = Some(graphics::Color::new(shade, shade, shade, 1.0));
+            }
+        }
+        Some((ts, params))
+    }
 
-    pub fn entity(&self, x: usize, y:usize, layer:usize) -> Option<&entity::Entity> {
-        if layer >= self.entities.len() {
-            return None
+    /// world space draw params for the base and decoration tiles of a chunk
+    /// in the current layer as (sublayer, tileset, params), sublayer 0 is the
+    /// base and 1 the decoration, all base tiles come first
+    /// uvs are indexed like the map tilesets, see map_uvs
+    pub fn chunk_draw_params(
+        &self,
+        uvs: &Vec<Option<Vec<Rect>>>,
+        cx: i32,
+        cy: i32,
+    ) -> Vec<(usize, usize, graphics::DrawParam)> {
+        let mut params = Vec::new();
+        let layer_index = self.layer_index * 3;
+        let (left, top) = (cx * CHUNK_SIZE, cy * CHUNK_SIZE);
+        let order = self
+            .grid
+            .draw_order(left, top, left + CHUNK_SIZE - 1, top + CHUNK_SIZE - 1);
+        for (sublayer, layer) in self.layers[layer_index..layer_index + 2].iter().enumerate() {
+            let chunk = match layer.chunk(cx, cy) {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+            for &(x, y) in order.iter() {
+                if let Some((ts, p)) = self.gid_draw_params(uvs, x, y, chunk.tile(x - left, y - top)) {
+                    params.push((sublayer, ts, p));
+                }
+            }
         }
-        let map = &self.entities[layer];
-        match map.get(&self.coord_to_index(x, y)) {
-            Some(entity) => Some(&entity),
-            None => None
+        params
+    }
+
+    /// brings the cached batches of the visible chunks up to date
+    /// a chunk is only rebuilt when its tiles were written since it was cached
+    /// or when it scrolls back into view after being evicted
+    pub fn setup_sprites(&self, sprites: &mut MapSprites) {
+        let visible = self.visible_chunks();
+        for &(cx, cy) in visible.iter() {
+            let key = (self.layer_index, cx, cy);
+            let revision = self.chunk_revision(self.layer_index, cx, cy).unwrap_or(0);
+            if !sprites.is_cached(key, revision) {
+                let cached = self.build_chunk(sprites, cx, cy, revision);
+                sprites.chunks.insert(key, cached);
+                sprites.rebuilds += 1;
+            }
         }
+
+        // drop chunks that are well outside the camera
+        let (left, top, right, bottom) = self.camera_chunk_bounds();
+        sprites.chunks.retain(|&(_, cx, cy), _| {
+            cx >= left - CACHE_MARGIN
+                && cx <= right + CACHE_MARGIN
+                && cy >= top - CACHE_MARGIN
+                && cy <= bottom + CACHE_MARGIN
+        });
+        sprites.visible = visible;
     }
 
-    pub fn entity_mut<'a>(&'a mut self, x: usize, y:usize, layer:usize) -> Option<&'a mut entity::Entity> {
-        if layer >= self.entities.len() {
-            return None
+    // latest change to the drawn sections of a chunk, None if they are empty
+    // fog changes count so darkened tiles are redrawn
+    pub fn chunk_revision(&self, level: usize, cx: i32, cy: i32) -> Option<u64> {
+        let layer_index = level * 3;
+        let revision = self.layers[layer_index..layer_index + 2]
+            .iter()
+            .filter_map(|layer| layer.chunk(cx, cy))
+            .map(|chunk| chunk.revision())
+            .max()?;
+        let fog = self.fog.as_ref().and_then(|fog| fog.chunk_revision(cx, cy));
+        Some(revision.max(fog.unwrap_or(0)))
+    }
+
+    /// latest tile write to any of the three layers of level in a chunk, None if they are empty
+    pub fn tiles_revision(&self, level: usize, cx: i32, cy: i32) -> Option<u64> {
+        let layer_index = level * 3;
+        self.layers[layer_index..layer_index + 3]
+            .iter()
+            .filter_map(|layer| layer.chunk(cx, cy))
+            .map(|chunk| chunk.revision())
+            .max()
+    }
+
+    // chunks overlapping the camera as left, top, right, bottom inclusive
+    fn camera_chunk_bounds(&self) -> (i32, i32, i32, i32) {
+        // on non orthogonal maps the camera covers a skewed range of tiles
+        let corners = [
+            self.point_to_tile_at(self.camera.left(), self.camera.top()),
+            self.point_to_tile_at(self.camera.right(), self.camera.top()),
+            self.point_to_tile_at(self.camera.left(), self.camera.bottom()),
+            self.point_to_tile_at(self.camera.right(), self.camera.bottom()),
+        ];
+        // staggered rows can poke out of the corner tiles by one
+        let margin = match self.grid.orientation {
+            Orientation::Orthogonal => 0,
+            _ => 1,
+        };
+        let left = corners.iter().map(|c| c.0).min().unwrap() - margin;
+        let top = corners.iter().map(|c| c.1).min().unwrap() - margin;
+        let right = corners.iter().map(|c| c.0).max().unwrap() + margin;
+        let bottom = corners.iter().map(|c| c.1).max().unwrap() + margin;
+        (
+            chunk::chunk_coord(left),
+            chunk::chunk_coord(top),
+            chunk::chunk_coord(right),
+            chunk::chunk_coord(bottom),
+        )
+    }
+
+    /// non empty chunks of the current layer overlapping the camera
+    pub fn visible_chunks(&self) -> Vec<(i32, i32)> {
+        let (left, top, right, bottom) = self.camera_chunk_bounds();
+
+        let mut chunks = Vec::new();
+        for cy in top..=bottom {
+            for cx in left..=right {
+                if self.chunk_revision(self.layer_index, cx, cy).is_some() {
+                    chunks.push((cx, cy));
+                }
+            }
         }
-        let index = self.coord_to_index(x, y);
-        match self.entities[layer].get_mut(&index) {
-            Some(e) => Some(e),
-            None => None
+        chunks
+    }
+
+    fn build_chunk(&self, sprites: &MapSprites, cx: i32, cy: i32, revision: u64) -> ChunkBatch {
+        let new_batches = || -> Vec<Option<SpriteBatch>> {
+            sprites
+                .images
+                .iter()
+                .map(|image| image.as_ref().map(|image| SpriteBatch::new(image.clone())))
+                .collect()
+        };
+        let mut batches = [new_batches(), new_batches()];
+
+        for (sublayer, ts, params) in self.chunk_draw_params(&sprites.uvs, cx, cy) {
+            if let Some(ref mut batch) = batches[sublayer][ts] {
+                batch.add(params);
+            }
         }
+        ChunkBatch { revision, batches }
+    }
+
+    /// entity functions
+
+    // id of the entity covering a tile
+    fn occupant(&self, x: usize, y: usize, layer: usize) -> Option<&String> {
+        if layer >= self.occupancy.len() || !self.in_bounds(x, y) {
+            return None
+        }
+        self.occupancy[layer].get(&self.coord_to_index(x, y))
+    }
+
+    /// entity covering the tile, which need not be its top left tile
+    pub fn entity(&self, x: usize, y:usize, layer:usize) -> Option<&entity::Entity> {
+        let id = self.occupant(x, y, layer)?;
+        self.entity_by_id(id)
+    }
+
+    pub fn entity_mut<'a>(&'a mut self, x: usize, y:usize, layer:usize) -> Option<&'a mut entity::Entity> {
+        let id = self.occupant(x, y, layer)?.clone();
+        self.entity_by_id_mut(&id)
+    }
+
+    pub fn entity_by_id(&self, id: &str) -> Option<&entity::Entity> {
+        let &IndexPair(layer, index) = self.entityid_to_index.get(id)?;
+        self.entities[layer].get(&index)
+    }
+
+    /// the entity with id, its tile can only be changed with the move functions
+    pub fn entity_by_id_mut(&mut self, id: &str) -> Option<&mut entity::Entity> {
+        let &IndexPair(layer, index) = self.entityid_to_index.get(id)?;
+        self.entities[layer].get_mut(&index)
     }
 
     pub fn entities_of_layer(&self, layer: usize) -> Option<Vec<&entity::Entity>> {
@@ -212,88 +779,514 @@ impl Map {
         Some(entities)
     }
 
-    pub fn add_entity(&mut self, e : entity::Entity) -> &mut entity::Entity {
+    /// entities of layer in drawing order, back to front by the y of their anchor
+    /// where they are drawn alpha of the way through a step, 1.0 sorts by pos
+    pub fn entities_y_sorted(&self, layer: usize, alpha: f32) -> Vec<&entity::Entity> {
+        let mut entities = self.entities_of_layer(layer).unwrap_or_default();
+        entities.sort_by(|a, b| {
+            let (pa, pb) = (a.interpolated_pos(alpha), b.interpolated_pos(alpha));
+            pa.y.partial_cmp(&pb.y)
+                .unwrap_or(Ordering::Equal)
+                .then(pa.x.partial_cmp(&pb.x).unwrap_or(Ordering::Equal))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        entities
+    }
+
+    /// starts a fixed update for every entity, see Entity::store_previous_pos
+    pub fn store_previous_positions(&mut self) {
+        for e in self.entities_mut() {
+            e.store_previous_pos();
+        }
+    }
+
+    /// draws the Sprite components of layer's entities y sorted, after updating their frames
+    /// alpha is how far rendering is between the last two fixed updates, see FixedTimestep
+    pub fn entity_layer<'a>(&'a mut self, layer: usize, alpha: f32) -> EntityLayerDrawContext<'a> {
+        if let Some(entities) = self.entities.get(layer) {
+            for e in entities.values() {
+                if let Some(sprite) = self.world.get_mut::<Sprite>(&e.id) {
+                    e.setup_sprite(sprite);
+                }
+            }
+        }
+        EntityLayerDrawContext { map: self, layer, alpha }
+    }
+
+    /// every entity on the map, layer by layer
+    pub fn entities<'a>(&'a self) -> Box<Iterator<Item = &'a entity::Entity> + 'a> {
+        Box::new(self.entities.iter().flat_map(|layer| layer.values()))
+    }
+
+    pub fn entities_mut<'a>(&'a mut self) -> Box<Iterator<Item = &'a mut entity::Entity> + 'a> {
+        Box::new(self.entities.iter_mut().flat_map(|layer| layer.values_mut()))
+    }
+
+    fn in_bounds(&self, x: usize, y: usize) -> bool {
+        (x as f32) < self.dimensions.x && (y as f32) < self.dimensions.y
+    }
+
+    // error if any tile of e's footprint with x, y top left is off the map, blocked or taken
+    fn check_free(&self, e: &entity::Entity, x: usize, y: usize, layer: usize) -> Result<(), MoveError> {
+        for (x, y) in e.footprint_at(x, y) {
+            if !self.in_bounds(x, y) {
+                return Err(MoveError::OutOfBounds { x, y });
+            }
+            if self.is_area_blocked(layer, x, y, (1, 1)) {
+                return Err(MoveError::Blocked { x, y, layer });
+            }
+            match self.occupant(x, y, layer) {
+                Some(other) if *other != e.id => {
+                    return Err(MoveError::Occupied {
+                        x,
+                        y,
+                        layer,
+                        by: other.clone(),
+                    })
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// true when a collision tile of level lies under a width by height footprint at x, y
+    pub fn is_area_blocked(&self, level: usize, x: usize, y: usize, footprint: (usize, usize)) -> bool {
+        let (left, top) = (self.origin.0 + x as i32, self.origin.1 + y as i32);
+        (top..top + footprint.1 as i32)
+            .any(|ty| (left..left + footprint.0 as i32).any(|tx| self.is_blocked_at(level, tx, ty)))
+    }
+
+    /// places e at its tile, fails if a tile it covers is taken, blocked, off the map or the id is in use
+    pub fn add_entity(&mut self, e : entity::Entity) -> Result<&mut entity::Entity, MoveError> {
+        if self.entityid_to_index.contains_key(&e.id) {
+            return Err(MoveError::DuplicateId(e.id.clone()));
+        }
+        self.check_free(&e, e.tile_x(), e.tile_y(), e.layer())?;
         while e.layer() >= self.entities.len() {
             self.entities.push(HashMap::new());
+            self.occupancy.push(HashMap::new());
+        }
+        for (x, y) in e.covered_tiles() {
+            let index = self.coord_to_index(x, y);
+            self.occupancy[e.layer()].insert(index, e.id.clone());
         }
 
         let index = self.coord_to_index(e.tile_x(), e.tile_y());
         self.entityid_to_index.insert(e.id.clone(), IndexPair(e.layer, index));
-        
-        let layer = &mut self.entities[e.layer()];
+        self.world.create_entity(&e.id);
+        let centre = self.tile_centre(e.tile_x(), e.tile_y());
+        self.update_entity_regions(&e.id, Some(centre));
 
-        assert!(layer.get(&index).is_none());
+        let layer = &mut self.entities[e.layer()];
         layer.insert(index, e);
-        layer.get_mut(&index).unwrap()
+        Ok(layer.get_mut(&index).unwrap())
+    }
 
+    pub fn remove_entity(&mut self, e: &entity::Entity) -> Option<entity::Entity> {
+        self.remove_entity_by_id(&e.id)
     }
 
-    pub fn remove_entity(&mut self, e: &entity::Entity) -> entity::Entity {
-        let index = self.coord_to_index(e.tile_x(), e.tile_y());
-        self.entityid_to_index.remove(&e.id).unwrap();
+    pub fn remove_entity_by_id(&mut self, id: &str) -> Option<entity::Entity> {
+        let e = self.take_entity(id)?;
+        self.update_entity_regions(id, None);
+        self.world.delete_entity(id);
+        Some(e)
+    }
 
-        let layer = &mut self.entities[e.layer()];
+    /// components of the entities on the map, every entity on the map is
+    /// registered in it and its components are dropped when it is removed
+    pub fn world(&self) -> &World {
+        &self.world
+    }
 
-        layer.remove(&index).unwrap()
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
     }
 
-    pub fn set_tile_pos(&mut self, e : &entity::Entity, tile_x: usize, tile_y: usize, layer: usize) {
-        let mut e = self.remove_entity(e);
-        assert!(self.entity(tile_x, tile_y, layer).is_none(), "tile must be free before enity moves to it");
+    pub fn set_entity_defs(&mut self, defs: EntityDefs) {
+        self.entity_defs = defs;
+    }
 
+    pub fn entity_defs(&self) -> &EntityDefs {
+        &self.entity_defs
+    }
+
+    pub fn entity_defs_mut(&mut self) -> &mut EntityDefs {
+        &mut self.entity_defs
+    }
+
+    /// places a new entity built from definition def_name with its top left tile at tile_x, tile_y
+    /// returns its generated id, nothing is added on error
+    pub fn spawn(&mut self, def_name: &str, tile_x: usize, tile_y: usize, layer: usize) -> GameResult<String> {
+        let id = loop {
+            self.spawned += 1;
+            let id = format!("{}_{}", def_name, self.spawned);
+            if !self.entityid_to_index.contains_key(&id) && !self.world.contains(&id) {
+                break id;
+            }
+        };
+        let mut e = self.entity_defs.entity(def_name, id.clone())?;
         e.tile_x = tile_x;
         e.tile_y = tile_y;
         e.layer = layer;
+        let pos = self.footprint_foot(e.footprint(), tile_x, tile_y);
+        self.add_entity(e)?.warp_pos(pos);
+
+        if let Err(err) = self.entity_defs.insert_components(def_name, &id, &mut self.world) {
+            self.remove_entity_by_id(&id);
+            return Err(err);
+        }
+        Ok(id)
+    }
 
-        let pos = self.get_tile_foot(tile_x, tile_y);
-        let dimensions = self.dimensions.clone();
-        let e = self.add_entity(e);
-        e.set_pos(Point2::new(pos.x, pos.y - dimensions.y));
+    /// runs systems in order, each gets the map and its world through it
+    pub fn run_systems(&mut self, systems: &mut MapSystems) {
+        systems.run(self);
+    }
+
+    // removes without leaving regions, for moves
+    fn take_entity(&mut self, id: &str) -> Option<entity::Entity> {
+        let IndexPair(layer, index) = self.entityid_to_index.remove(id)?;
+        let e = self.entities[layer].remove(&index)?;
+        for (x, y) in e.covered_tiles() {
+            let index = self.coord_to_index(x, y);
+            self.occupancy[layer].remove(&index);
+        }
+        Some(e)
+    }
+
+    pub fn set_tile_pos(&mut self, e : &entity::Entity, tile_x: usize, tile_y: usize, layer: usize) -> Result<(), MoveError> {
+        self.move_entity(&e.id, tile_x, tile_y, layer)
+    }
+
+    /// moves the entity with id so its top left tile is tile_x, tile_y
+    /// every tile of its footprint must be free and unblocked, on error it stays where it was
+    pub fn move_entity(&mut self, id: &str, tile_x: usize, tile_y: usize, layer: usize) -> Result<(), MoveError> {
+        match self.entity_by_id(id) {
+            Some(e) => self.check_free(e, tile_x, tile_y, layer)?,
+            None => return Err(MoveError::UnknownEntity(id.to_string())),
+        }
+        let mut e = self.take_entity(id).unwrap();
+
+        e.tile_x = tile_x;
+        e.tile_y = tile_y;
+        e.layer = layer;
+
+        let pos = self.footprint_foot(e.footprint(), tile_x, tile_y);
+        self.add_entity(e)?.set_pos(pos);
+        Ok(())
     }
 
 }
 
-impl SpriteComponent for Map {
-    fn setup_sprite(&self, sprite: &mut Sprite) {
-        // layers are made of 3 sections
-        // want the index to point to a given section
-        let layer_index = self.layer_index * 3;
+/// systems over a map, run by Map::run_systems in the order they were added
+///
+/// the map is the only handle a system gets, components are reached with
+/// map.world_mut() so spawning, removing and moving entities from a system
+/// see the same world as everything else
+pub type MapSystems<'a> = Systems<'a, Map>;
+
+/// reasons an entity can not be placed or moved
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoveError {
+    UnknownEntity(String),
+    DuplicateId(String),
+    OutOfBounds { x: usize, y: usize },
+    /// a collision tile lies under the footprint
+    Blocked { x: usize, y: usize, layer: usize },
+    Occupied { x: usize, y: usize, layer: usize, by: String },
+}
 
-        let (tile_left, tile_top) = self.point_to_tile(self.camera.left(), self.camera.top());
-        let (tile_right, tile_bottom) =
-            self.point_to_tile(self.camera.right(), self.camera.bottom());
-
-        sprite.sprite_batch.clear();
-        for j in tile_top..=(tile_bottom) {
-            for i in tile_left..=(tile_right) {
-                // Get actual tile layer
-                let tile = self.get_tile(i, j, layer_index);
-                if tile > 0 {
-                    sprite
-                        .sprite_batch
-                        .add(self.tile_draw_params(&sprite.uvs, i, j, tile));
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MoveError::UnknownEntity(ref id) => write!(f, "no entity with id {}", id),
+            MoveError::DuplicateId(ref id) => write!(f, "an entity with id {} already exists", id),
+            MoveError::OutOfBounds { x, y } => write!(f, "tile {}, {} is outside the map", x, y),
+            MoveError::Blocked { x, y, layer } => write!(f, "tile {}, {} on layer {} is blocked", x, y, layer),
+            MoveError::Occupied { x, y, layer, ref by } => {
+                write!(f, "tile {}, {} on layer {} is occupied by {}", x, y, layer, by)
+            }
+        }
+    }
+}
+
+impl error::Error for MoveError {
+    fn description(&self) -> &str {
+        "entity can not move there"
+    }
+}
+
+impl From<MoveError> for GameError {
+    fn from(e: MoveError) -> GameError {
+        GameError::from(e.to_string())
+    }
+}
+
+/// Cached sprite batches for the chunks of a map, one batch per tileset image
+/// for the base and decoration tiles of each chunk. The collision tileset is
+/// never drawn and has no batch.
+pub struct MapSprites {
+    images: Vec<Option<graphics::Image>>,
+    uvs: Vec<Option<Vec<Rect>>>,
+    // one per map image layer, None for layers without an image
+    layer_images: Vec<Option<graphics::Image>>,
+
+    // batches keyed by layer and chunk
+    chunks: HashMap<(usize, i32, i32), ChunkBatch>,
+    // chunks to draw this frame
+    visible: Vec<(i32, i32)>,
+    rebuilds: usize,
+}
+
+// batches for one chunk, valid while the chunk revision matches
+struct ChunkBatch {
+    revision: u64,
+    // base then decoration, one batch per tileset in each
+    batches: [Vec<Option<SpriteBatch>>; 2],
+}
+
+impl MapSprites {
+    /// loads every tileset image, image sources are relative to dir
+    pub fn new(ctx: &mut Context, map_def: &tiled::Map, dir: &str) -> GameResult<Self> {
+        let mut images = Vec::new();
+        for tileset in map_def.tilesets.iter() {
+            if tileset.name == COLLISION_TILESET {
+                images.push(None);
+                continue;
+            }
+            let source = match tileset.images.first() {
+                Some(image) => &image.source,
+                None => {
+                    return Err(GameError::from(format!(
+                        "tileset {} has no image",
+                        tileset.name
+                    )))
+                }
+            };
+            let path = format!("{}/{}", dir.trim_right_matches('/'), source);
+            images.push(Some(graphics::Image::new(ctx, path)?));
+        }
+        let mut layer_images = Vec::new();
+        for layer in map_def.image_layers.iter() {
+            layer_images.push(match layer.image {
+                Some(ref image) => {
+                    let path = format!("{}/{}", dir.trim_right_matches('/'), image.source);
+                    Some(graphics::Image::new(ctx, path)?)
                 }
+                None => None,
+            });
+        }
+        let mut sprites = MapSprites::from_images(map_def, images);
+        sprites.layer_images = layer_images;
+        Ok(sprites)
+    }
 
-                // Get decoration layer tiles
-                let tile = self.get_tile(i, j, layer_index + 1);
-                if tile > 0 {
-                    sprite
-                        .sprite_batch
-                        .add(self.tile_draw_params(&sprite.uvs, i, j, tile));
+    /// images must be in the same order as the map tilesets
+    pub fn from_images(map_def: &tiled::Map, images: Vec<Option<graphics::Image>>) -> Self {
+        assert!(
+            images.len() == map_def.tilesets.len(),
+            "need one image per tileset"
+        );
+        let uvs = map_uvs(map_def)
+            .into_iter()
+            .zip(images.iter())
+            .map(|(uvs, image)| image.as_ref().and(uvs))
+            .collect();
+        MapSprites {
+            images,
+            uvs,
+            layer_images: map_def.image_layers.iter().map(|_| None).collect(),
+            chunks: HashMap::new(),
+            visible: Vec::new(),
+            rebuilds: 0,
+        }
+    }
+
+    /// uvs of each tileset, None for tilesets without an image
+    pub fn uvs(&self) -> &Vec<Option<Vec<Rect>>> {
+        &self.uvs
+    }
+
+    /// image of the tileset at index, None for the collision tileset
+    pub fn tileset_image(&self, index: usize) -> Option<&graphics::Image> {
+        self.images.get(index).and_then(|image| image.as_ref())
+    }
+
+    /// number of chunk batches built so far
+    pub fn rebuilds(&self) -> usize {
+        self.rebuilds
+    }
+
+    fn is_cached(&self, key: (usize, i32, i32), revision: u64) -> bool {
+        match self.chunks.get(&key) {
+            Some(cached) => cached.revision == revision,
+            None => false,
+        }
+    }
+
+    pub fn with_context<'a>(&'a mut self, map: &'a Map) -> MapDrawContext<'a> {
+        map.setup_sprites(self);
+        MapDrawContext { sprites: self, map }
+    }
+
+    /// image layers to draw before the tile levels
+    pub fn background<'a>(&'a self, map: &'a Map) -> ImageLayerDrawContext<'a> {
+        ImageLayerDrawContext {
+            sprites: self,
+            map,
+            foreground: false,
+        }
+    }
+
+    /// image layers to draw after the tile levels
+    pub fn foreground<'a>(&'a self, map: &'a Map) -> ImageLayerDrawContext<'a> {
+        ImageLayerDrawContext {
+            sprites: self,
+            map,
+            foreground: true,
+        }
+    }
+}
+
+pub struct ImageLayerDrawContext<'a> {
+    sprites: &'a MapSprites,
+    map: &'a Map,
+    foreground: bool,
+}
+
+impl<'a> graphics::Drawable for ImageLayerDrawContext<'a> {
+    fn draw_ex(&self, ctx: &mut Context, param: graphics::DrawParam) -> GameResult<()> {
+        for (i, layer) in self.map.image_layers.iter().enumerate() {
+            if !layer.visible || image_layer_is_foreground(layer) != self.foreground {
+                continue;
+            }
+            let image = match self.sprites.layer_images.get(i) {
+                Some(&Some(ref image)) => image,
+                _ => continue,
+            };
+            let mut params = self.map.image_layer_params(i);
+            params.dest.x += param.dest.x;
+            params.dest.y += param.dest.y;
+            image.draw_ex(ctx, params)?;
+        }
+        Ok(())
+    }
+    fn set_blend_mode(&mut self, _mode: Option<graphics::BlendMode>) {}
+    fn get_blend_mode(&self) -> Option<graphics::BlendMode> {
+        None
+    }
+}
+
+pub struct EntityLayerDrawContext<'a> {
+    map: &'a Map,
+    layer: usize,
+    alpha: f32,
+}
+
+impl<'a> graphics::Drawable for EntityLayerDrawContext<'a> {
+    fn draw_ex(&self, ctx: &mut Context, param: graphics::DrawParam) -> GameResult<()> {
+        for e in self.map.entities_y_sorted(self.layer, self.alpha) {
+            if let Some(sprite) = self.map.world.get::<Sprite>(&e.id) {
+                let at = util::snap(e.interpolated_top_left(self.alpha));
+                let offset = self.map.camera_offset();
+                let mut param = param;
+                param.dest.x += at.x - offset.x;
+                param.dest.y += at.y - offset.y;
+                sprite.draw_ex(ctx, param)?;
+            }
+        }
+        Ok(())
+    }
+    fn set_blend_mode(&mut self, _mode: Option<graphics::BlendMode>) {}
+    fn get_blend_mode(&self) -> Option<graphics::BlendMode> {
+        None
+    }
+}
+
+pub struct MapDrawContext<'a> {
+    sprites: &'a mut MapSprites,
+    map: &'a Map,
+}
+
+impl<'a> graphics::Drawable for MapDrawContext<'a> {
+    fn draw_ex(&self, ctx: &mut Context, param: graphics::DrawParam) -> GameResult<()> {
+        // chunk batches are in world space
+        let mut param = param;
+        let offset = self.map.camera_offset();
+        param.dest.x -= offset.x;
+        param.dest.y -= offset.y;
+        // every base batch goes before any decoration batch, whatever tileset they use
+        for sublayer in 0..2 {
+            for &(cx, cy) in self.sprites.visible.iter() {
+                if let Some(cached) = self.sprites.chunks.get(&(self.map.layer_index, cx, cy)) {
+                    for batch in cached.batches[sublayer].iter().flat_map(|b| b.as_ref()) {
+                        batch.draw_ex(ctx, param)?;
+                    }
                 }
             }
         }
+        Ok(())
     }
+    fn set_blend_mode(&mut self, mode: Option<graphics::BlendMode>) {}
+    fn get_blend_mode(&self) -> Option<graphics::BlendMode> {
+        None
+    }
+}
 
-    /// Where to draw the sprite map at
-    fn draw_sprite_at(&self) -> graphics::Point2 {
-        self.pos.clone()
+fn float_property(properties: &tiled::Properties, name: &str) -> Option<f32> {
+    match properties.get(name) {
+        Some(&tiled::PropertyValue::FloatValue(v)) => Some(v),
+        Some(&tiled::PropertyValue::IntValue(v)) => Some(v as f32),
+        _ => None,
     }
 }
 
+/// scroll rate of an image layer relative to the camera
+pub fn image_layer_parallax(layer: &tiled::ImageLayer) -> Point2 {
+    Point2::new(
+        float_property(&layer.properties, tiled_json::PARALLAX_X_PROPERTY).unwrap_or(1.0),
+        float_property(&layer.properties, tiled_json::PARALLAX_Y_PROPERTY).unwrap_or(1.0),
+    )
+}
+
+/// true for image layers drawn over the tile levels
+pub fn image_layer_is_foreground(layer: &tiled::ImageLayer) -> bool {
+    match layer.properties.get(tiled_json::FOREGROUND_PROPERTY) {
+        Some(&tiled::PropertyValue::BoolValue(foreground)) => foreground,
+        _ => false,
+    }
+}
+
+/// uvs for every tileset of a map, None for the collision tileset
+/// inset by UV_INSET texels, gid draw params scale them back to the tile size
+pub fn map_uvs(map_def: &tiled::Map) -> Vec<Option<Vec<Rect>>> {
+    map_def
+        .tilesets
+        .iter()
+        .map(|tileset| {
+            if tileset.name == COLLISION_TILESET || tileset.images.is_empty() {
+                None
+            } else {
+                let image = &tileset.images[0];
+                Some(util::inset_uvs(
+                    &tileset_uvs(tileset),
+                    image.width as f32,
+                    image.height as f32,
+                    UV_INSET,
+                ))
+            }
+        })
+        .collect()
+}
+
 // uvs from tiled generates uvs for a map tileset
 pub fn uvs_from_tiled(map: &tiled::Map, tileset_id: usize) -> Vec<Rect> {
-    let tileset = &map.tilesets[tileset_id];
+    tileset_uvs(&map.tilesets[tileset_id])
+}
+
+pub fn tileset_uvs(tileset: &tiled::Tileset) -> Vec<Rect> {
     let i_width = tileset.images[0].width as f32;
     let i_height = tileset.images[0].height as f32;
     let t_width = tileset.tile_width as f32;
@@ -301,3 +1294,302 @@ pub fn uvs_from_tiled(map: &tiled::Map, tileset_id: usize) -> Vec<Rect> {
 
     return util::generate_uvs(i_width, i_height, t_width, t_height);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support;
+
+    fn entity(id: &str, x: usize, y: usize) -> entity::Entity {
+        let mut e = entity::Entity::new(id.to_string(), Point2::new(16.0, 16.0));
+        e.tile_x = x;
+        e.tile_y = y;
+        e
+    }
+
+    #[test]
+    fn entity_moves() {
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        map.add_entity(entity("hero", 0, 0)).unwrap();
+        map.add_entity(entity("npc", 1, 0)).unwrap();
+
+        assert_eq!(
+            map.add_entity(entity("hero", 2, 0)).err(),
+            Some(MoveError::DuplicateId(String::from("hero")))
+        );
+        assert_eq!(
+            map.move_entity("hero", 1, 0, 0),
+            Err(MoveError::Occupied {
+                x: 1,
+                y: 0,
+                layer: 0,
+                by: String::from("npc"),
+            })
+        );
+        assert_eq!(map.move_entity("hero", 3, 0, 0), Err(MoveError::OutOfBounds { x: 3, y: 0 }));
+        assert_eq!(
+            map.move_entity("ghost", 2, 0, 0),
+            Err(MoveError::UnknownEntity(String::from("ghost")))
+        );
+        assert_eq!(map.entity_by_id("hero").unwrap().tile_x(), 0);
+
+        map.move_entity("hero", 2, 1, 0).unwrap();
+        assert_eq!(map.entity_by_id("hero").unwrap().tile_y(), 1);
+        assert!(map.entity(0, 0, 0).is_none());
+        assert!(map.entity_by_id_mut("hero").is_some());
+        assert_eq!(map.entities().count(), 2);
+
+        assert!(map.remove_entity_by_id("npc").is_some());
+        assert!(map.remove_entity_by_id("npc").is_none());
+        assert_eq!(map.entities().count(), 1);
+    }
+
+    #[test]
+    fn footprints() {
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        map.add_entity(entity("boat", 0, 0).with_footprint(2, 2)).unwrap();
+
+        assert_eq!(map.entity(1, 1, 0).map(|e| e.id.as_str()), Some("boat"));
+        assert_eq!(
+            map.add_entity(entity("hero", 1, 0)).err(),
+            Some(MoveError::Occupied {
+                x: 1,
+                y: 0,
+                layer: 0,
+                by: String::from("boat"),
+            })
+        );
+        // the boat's right column would leave the map
+        assert_eq!(map.move_entity("boat", 2, 0, 0), Err(MoveError::OutOfBounds { x: 3, y: 0 }));
+
+        // moving onto tiles it already covers is fine
+        map.move_entity("boat", 1, 0, 0).unwrap();
+        assert!(map.entity(0, 0, 0).is_none());
+        assert_eq!(map.entity(2, 1, 0).map(|e| e.id.as_str()), Some("boat"));
+        map.add_entity(entity("hero", 0, 1)).unwrap();
+    }
+
+    #[test]
+    fn collision_under_footprints() {
+        let map_def = test_support::test_map(&["11#", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        map.add_entity(entity("cart", 0, 0).with_footprint(2, 1)).unwrap();
+        assert!(map.is_area_blocked(0, 1, 0, (2, 1)));
+        assert!(!map.is_area_blocked(0, 0, 0, (2, 2)));
+
+        // the cart's first tile is free, the wall is under its second
+        assert_eq!(
+            map.move_entity("cart", 1, 0, 0),
+            Err(MoveError::Blocked { x: 2, y: 0, layer: 0 })
+        );
+        assert_eq!(map.entity_by_id("cart").unwrap().tile_x(), 0);
+        map.move_entity("cart", 1, 1, 0).unwrap();
+        assert_eq!(
+            map.add_entity(entity("hero", 2, 0)).err(),
+            Some(MoveError::Blocked { x: 2, y: 0, layer: 0 })
+        );
+    }
+
+    #[test]
+    fn anchors_and_y_sorting() {
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        let tree = entity::Entity::new(String::from("tree"), Point2::new(32.0, 48.0)).with_footprint(2, 1);
+        map.add_entity(tree).unwrap();
+        map.add_entity(entity("hero", 2, 0)).unwrap();
+        map.move_entity("tree", 0, 1, 0).unwrap();
+        map.move_entity("hero", 2, 0, 0).unwrap();
+
+        // the tree stands on the bottom centre of its two tiles and rises above them
+        let tree = map.entity_by_id("tree").unwrap();
+        assert_eq!(tree.pos(), Point2::new(16.0, 32.0));
+        assert_eq!(tree.sprite_top_left(), Point2::new(0.0, -16.0));
+        let hero = map.entity_by_id("hero").unwrap();
+        assert_eq!(hero.sprite_top_left(), Point2::new(32.0, 0.0));
+
+        let order = |map: &Map, alpha: f32| -> Vec<String> {
+            map.entities_y_sorted(0, alpha).iter().map(|e| e.id.clone()).collect()
+        };
+        assert_eq!(order(&map, 1.0), vec!["hero", "tree"]);
+        map.store_previous_positions();
+        map.move_entity("hero", 2, 1, 0).unwrap();
+        // halfway down the hero is still drawn behind the tree
+        assert_eq!(order(&map, 0.5), vec!["hero", "tree"]);
+        assert_eq!(order(&map, 1.0), vec!["tree", "hero"]);
+    }
+
+    #[test]
+    fn pixel_perfect_tiles() {
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        let uvs = map_uvs(&map_def);
+        let inset = uvs[0].as_ref().unwrap()[0];
+        assert_eq!(inset, Rect::new(1.0 / 64.0, 1.0 / 64.0, 15.0 / 32.0, 15.0 / 32.0));
+
+        // the inset rect is stretched to cover exactly one tile, no more
+        let (_, params) = map.gid_draw_params(&uvs, 1, 0, 1).unwrap();
+        assert_eq!(params.dest, Point2::new(16.0, 0.0));
+        assert!((params.src.w * 32.0 * params.scale.x - 16.0).abs() < 1e-4);
+        assert!((params.src.h * 32.0 * params.scale.y - 16.0).abs() < 1e-4);
+
+        map.set_camera(Rect::new(3.4, 7.6, 48.0, 32.0));
+        assert_eq!(map.camera_offset(), Point2::new(3.0, 8.0));
+        let tile = map.tile_draw_params(&uvs[0].clone().unwrap(), 0, 1, 1, 0);
+        assert_eq!(tile.dest, Point2::new(13.0, 8.0));
+        assert!((tile.src.w * 32.0 * tile.scale.x - 16.0).abs() < 1e-4);
+        assert!((tile.src.h * 32.0 * tile.scale.y - 16.0).abs() < 1e-4);
+    }
+
+    const TWO_TILESETS: &str = r#"{
+        "type": "map", "version": 1.2, "orientation": "orthogonal",
+        "width": 2, "height": 1, "tilewidth": 16, "tileheight": 16, "infinite": false,
+        "tilesets": [
+            { "firstgid": 1, "name": "ground", "tilewidth": 16, "tileheight": 16,
+              "spacing": 0, "margin": 0, "image": "ground.png", "imagewidth": 32, "imageheight": 16 },
+            { "firstgid": 3, "name": "props", "tilewidth": 16, "tileheight": 16,
+              "spacing": 0, "margin": 0, "image": "props.png", "imagewidth": 32, "imageheight": 16 }
+        ],
+        "layers": [
+            { "type": "tilelayer", "name": "base", "width": 2, "height": 1, "data": [3, 1] },
+            { "type": "tilelayer", "name": "decoration", "width": 2, "height": 1, "data": [2, 0] },
+            { "type": "tilelayer", "name": "collision", "width": 2, "height": 1, "data": [0, 0] }
+        ]
+    }"#;
+
+    #[test]
+    fn tilesets_by_gid() {
+        let map_def = tiled_json::parse(TWO_TILESETS.as_bytes(), |_| unreachable!()).unwrap();
+        let map = Map::new(&map_def, 0);
+        assert_eq!(map.tileset_for_gid(0), None);
+        assert_eq!(map.tileset_for_gid(1), Some(0));
+        assert_eq!(map.tileset_for_gid(2), Some(0));
+        assert_eq!(map.tileset_for_gid(3), Some(1));
+        // flip flags are ignored and gids past the last tileset still belong to it
+        assert_eq!(map.tileset_for_gid(0x8000_0002), Some(0));
+        assert_eq!(map.tileset_for_gid(40), Some(1));
+
+        // a decoration from the first tileset still draws over a base tile from the second
+        let uvs = map_uvs(&map_def);
+        let order: Vec<(usize, usize, Point2)> = map
+            .chunk_draw_params(&uvs, 0, 0)
+            .into_iter()
+            .map(|(sublayer, ts, params)| (sublayer, ts, params.dest))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (0, 1, Point2::new(0.0, 0.0)),
+                (0, 0, Point2::new(16.0, 0.0)),
+                (1, 0, Point2::new(0.0, 0.0)),
+            ]
+        );
+    }
+
+    // two chunks far apart, one left of and above the origin
+    const INFINITE: &str = r#"{
+        "type": "map", "version": 1.2, "orientation": "orthogonal",
+        "width": 16, "height": 16, "tilewidth": 16, "tileheight": 16, "infinite": true,
+        "tilesets": [{ "firstgid": 1, "name": "tiles", "tilewidth": 16, "tileheight": 16,
+            "spacing": 0, "margin": 0, "image": "tiles.png", "imagewidth": 16, "imageheight": 16 }],
+        "layers": [
+            { "type": "tilelayer", "name": "base", "chunks": [
+                { "x": -32, "y": -16, "width": 2, "height": 2, "data": [1, 0, 0, 1] },
+                { "x": 4096, "y": 2048, "width": 2, "height": 1, "data": [0, 1] }
+            ] }
+        ]
+    }"#;
+
+    #[test]
+    fn infinite_maps_stay_sparse() {
+        let map_def = tiled_json::parse(INFINITE.as_bytes(), |_| unreachable!()).unwrap();
+        // one row per chunk rather than the whole area between them
+        assert_eq!(map_def.layers[0].tiles.len(), 2);
+        assert_eq!((map_def.width, map_def.height), (4130, 2065));
+
+        let map = Map::new(&map_def, 0);
+        assert!(map.is_infinite());
+        assert_eq!(map.tile_bounds(), (-32, -16, 4098, 2049));
+        assert_eq!(map.tile_at(-32, -16, 0), 1);
+        assert_eq!(map.tile_at(-31, -16, 0), 0);
+        assert_eq!(map.tile_at(-31, -15, 0), 1);
+        assert_eq!(map.tile_at(4097, 2048, 0), 1);
+        assert_eq!(map.get_tile(0, 0, 0), 1);
+        let chunks: Vec<(i32, i32)> = {
+            let mut chunks: Vec<(i32, i32)> = map.layers[0].chunk_coords().collect();
+            chunks.sort();
+            chunks
+        };
+        assert_eq!(chunks, vec![(-2, -1), (256, 128)]);
+
+        // saving keeps the chunks apart too
+        let saved = map.to_tiled();
+        assert_eq!(saved.layers[0].tiles.len(), 2);
+        let reloaded = Map::new(&saved, 0);
+        assert_eq!(reloaded.tile_bounds().0, -32);
+        assert_eq!(reloaded.tile_at(-31, -15, 0), 1);
+        assert_eq!(reloaded.tile_at(4097, 2048, 0), 1);
+    }
+
+    #[test]
+    fn components_follow_entities() {
+        struct Health(i32);
+
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        map.add_entity(entity("hero", 0, 0)).unwrap();
+        map.add_entity(entity("slime", 1, 0)).unwrap();
+        map.world_mut().insert("hero", Health(10));
+        map.world_mut().insert("slime", Health(0));
+
+        // entities are in the world from the moment they are placed
+        assert!(map.world().contains("hero"));
+
+        let mut systems = MapSystems::new();
+        systems
+            .add(|map: &mut Map| {
+                for id in map.world().query().with::<Health>().ids() {
+                    if map.world().get::<Health>(&id).unwrap().0 <= 0 {
+                        map.remove_entity_by_id(&id);
+                    }
+                }
+            })
+            .add(|map: &mut Map| {
+                map.add_entity(entity("bat", 2, 1)).unwrap();
+                map.world_mut().insert("bat", Health(2));
+            });
+        map.run_systems(&mut systems);
+        // what a system spawns is in the world the next system and the caller see
+        assert_eq!(map.world().get::<Health>("bat").map(|h| h.0), Some(2));
+        map.remove_entity_by_id("bat");
+
+        assert!(map.entity_by_id("slime").is_none());
+        assert!(!map.world().contains("slime"));
+        assert_eq!(map.world().get::<Health>("hero").map(|h| h.0), Some(10));
+        map.remove_entity_by_id("hero");
+        assert!(map.world().query().with::<Health>().ids().is_empty());
+    }
+
+    #[test]
+    fn writes_rebuild_one_chunk() {
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        map.write_tile_at(20, 0, 0, 1, None, false);
+        map.set_camera(Rect::new(0.0, 0.0, 512.0, 256.0));
+        let images = map_def.tilesets.iter().map(|_| None).collect();
+        let mut sprites = MapSprites::from_images(&map_def, images);
+
+        map.setup_sprites(&mut sprites);
+        assert_eq!(sprites.rebuilds(), 2);
+        map.setup_sprites(&mut sprites);
+        assert_eq!(sprites.rebuilds(), 2);
+
+        let before = (map.chunk_revision(0, 0, 0), map.chunk_revision(0, 1, 0));
+        map.write_tile(1, 0, 0, 1, None, false);
+        assert!(map.chunk_revision(0, 0, 0) > before.0);
+        assert_eq!(map.chunk_revision(0, 1, 0), before.1);
+        map.setup_sprites(&mut sprites);
+        assert_eq!(sprites.rebuilds(), 3);
+    }
+}
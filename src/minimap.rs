@@ -0,0 +1,315 @@
+//! Minimap rendering.
+//!
+//! A `Minimap` is an RGBA buffer with one pixel per tile of a map level.
+//! Tiles are coloured with the average colour of their tileset image, or a
+//! `colour` tile property, collision is tinted and a marker shows the player.
+//! Only chunks whose tiles changed are redrawn on `update`.
+use std::collections::HashMap;
+
+use ggez::graphics::Image;
+use ggez::{Context, GameResult};
+use tiled;
+
+use chunk::{chunk_coord, CHUNK_SIZE};
+use map::{self, Map, MapSprites};
+
+/// tile property overriding the averaged colour, a colour or "#rrggbb" string
+pub const COLOUR_PROPERTY: &str = "colour";
+
+pub type Rgba = [u8; 4];
+
+#[derive(Debug, Clone)]
+pub struct Minimap {
+    level: usize,
+    // tiled coordinates of the top left pixel
+    left: i32,
+    top: i32,
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+
+    colours: HashMap<u32, Rgba>,
+    pub collision_colour: Rgba,
+    pub marker_colour: Rgba,
+    marker: Option<(i32, i32)>,
+
+    // tile revision each chunk was drawn at
+    revisions: HashMap<(i32, i32), u64>,
+    redraws: usize,
+}
+
+impl Minimap {
+    /// minimap of one level, covering the tiles loaded with the map
+    pub fn new(map: &Map, level: usize) -> Self {
+        let (left, top, right, bottom) = map.tile_bounds();
+        let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+        let mut minimap = Minimap {
+            level,
+            left,
+            top,
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+            colours: HashMap::new(),
+            collision_colour: [200, 40, 40, 255],
+            marker_colour: [255, 255, 255, 255],
+            marker: None,
+            revisions: HashMap::new(),
+            redraws: 0,
+        };
+        for tileset in map.tilesets().iter() {
+            minimap.add_property_colours(tileset);
+        }
+        minimap
+    }
+
+    /// averages the tiles of every tileset image loaded in sprites
+    pub fn with_sprites(ctx: &mut Context, map: &Map, sprites: &MapSprites, level: usize) -> GameResult<Self> {
+        let mut minimap = Minimap::new(map, level);
+        for (i, tileset) in map.tilesets().iter().enumerate() {
+            if let Some(image) = sprites.tileset_image(i) {
+                let pixels = image.to_rgba8(ctx)?;
+                minimap.add_tileset_pixels(tileset, &pixels, image.width() as usize, image.height() as usize);
+            }
+        }
+        Ok(minimap)
+    }
+
+    /// colours the tiles of tileset with their average over the RGBA pixels of its image
+    /// tile properties keep precedence
+    pub fn add_tileset_pixels(&mut self, tileset: &tiled::Tileset, pixels: &[u8], width: usize, height: usize) {
+        if tileset.images.is_empty() || width == 0 || height == 0 {
+            return;
+        }
+        for (id, uv) in map::tileset_uvs(tileset).iter().enumerate() {
+            let x0 = (uv.x * width as f32) as usize;
+            let y0 = (uv.y * height as f32) as usize;
+            let x1 = ((uv.x + uv.w) * width as f32) as usize;
+            let y1 = ((uv.y + uv.h) * height as f32) as usize;
+            let colour = average(pixels, width, x0, y0, x1.min(width), y1.min(height));
+            self.colours.entry(tileset.first_gid + id as u32).or_insert(colour);
+        }
+        self.revisions.clear();
+    }
+
+    fn add_property_colours(&mut self, tileset: &tiled::Tileset) {
+        for tile in tileset.tiles.iter() {
+            let colour = match tile.properties.get(COLOUR_PROPERTY) {
+                Some(&tiled::PropertyValue::ColorValue(argb)) => Some([
+                    (argb >> 16) as u8,
+                    (argb >> 8) as u8,
+                    argb as u8,
+                    (argb >> 24) as u8,
+                ]),
+                Some(&tiled::PropertyValue::StringValue(ref s)) => parse_rgb(s),
+                _ => None,
+            };
+            if let Some(colour) = colour {
+                self.colours.insert(tileset.first_gid + tile.id, colour);
+            }
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// RGBA rows, top to bottom
+    pub fn pixels(&self) -> &Vec<u8> {
+        &self.pixels
+    }
+
+    /// colour of the pixel for map coordinates x, y
+    pub fn pixel(&self, x: usize, y: usize) -> Rgba {
+        let i = (x + y * self.width) * 4;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    /// number of chunks drawn so far
+    pub fn redraws(&self) -> usize {
+        self.redraws
+    }
+
+    /// marks the player at map coordinates x, y, None hides the marker
+    pub fn set_marker(&mut self, map: &Map, tile: Option<(usize, usize)>) {
+        let old = self.marker.take();
+        self.marker = tile.map(|(x, y)| (self.left + x as i32, self.top + y as i32));
+        if let Some((x, y)) = old {
+            self.draw_tile(map, x, y);
+        }
+        if let Some((x, y)) = self.marker {
+            self.draw_tile(map, x, y);
+        }
+    }
+
+    /// redraws the chunks whose tiles changed since the last update
+    pub fn update(&mut self, map: &Map) {
+        let right = self.left + self.width as i32 - 1;
+        let bottom = self.top + self.height as i32 - 1;
+        for cy in chunk_coord(self.top)..chunk_coord(bottom) + 1 {
+            for cx in chunk_coord(self.left)..chunk_coord(right) + 1 {
+                let revision = map.tiles_revision(self.level, cx, cy).unwrap_or(0);
+                if self.revisions.get(&(cx, cy)) == Some(&revision) {
+                    continue;
+                }
+                for y in cy * CHUNK_SIZE..(cy + 1) * CHUNK_SIZE {
+                    for x in cx * CHUNK_SIZE..(cx + 1) * CHUNK_SIZE {
+                        self.draw_tile(map, x, y);
+                    }
+                }
+                self.revisions.insert((cx, cy), revision);
+                self.redraws += 1;
+            }
+        }
+    }
+
+    fn draw_tile(&mut self, map: &Map, x: i32, y: i32) {
+        let (px, py) = (x - self.left, y - self.top);
+        if px < 0 || py < 0 || px >= self.width as i32 || py >= self.height as i32 {
+            return;
+        }
+        let layer = self.level * 3;
+        let colour = if self.marker == Some((x, y)) {
+            self.marker_colour
+        } else {
+            // decoration covers the base tile unless it is see through
+            let base = self.tile_colour(map.tile_at(x, y, layer));
+            let decoration = self.tile_colour(map.tile_at(x, y, layer + 1));
+            let colour = blend(base, decoration);
+            if map.is_opaque_at(self.level, x, y) {
+                blend(colour, [
+                    self.collision_colour[0],
+                    self.collision_colour[1],
+                    self.collision_colour[2],
+                    self.collision_colour[3] / 2,
+                ])
+            } else {
+                colour
+            }
+        };
+        let i = (px as usize + py as usize * self.width) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&colour);
+    }
+
+    fn tile_colour(&self, gid: u32) -> Rgba {
+        self.colours
+            .get(&(gid & map::GID_MASK))
+            .cloned()
+            .unwrap_or([0, 0, 0, 0])
+    }
+
+    /// uploads the buffer as an image, draw it with FilterMode::Nearest to keep tiles sharp
+    pub fn to_image(&self, ctx: &mut Context) -> GameResult<Image> {
+        Image::from_rgba8(ctx, self.width as u16, self.height as u16, &self.pixels)
+    }
+}
+
+// alpha weighted average of the pixels in x0..x1, y0..y1
+fn average(pixels: &[u8], width: usize, x0: usize, y0: usize, x1: usize, y1: usize) -> Rgba {
+    let (mut r, mut g, mut b, mut a, mut count) = (0u64, 0u64, 0u64, 0u64, 0u64);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let i = (x + y * width) * 4;
+            if i + 3 >= pixels.len() {
+                continue;
+            }
+            let alpha = pixels[i + 3] as u64;
+            r += pixels[i] as u64 * alpha;
+            g += pixels[i + 1] as u64 * alpha;
+            b += pixels[i + 2] as u64 * alpha;
+            a += alpha;
+            count += 1;
+        }
+    }
+    if a == 0 {
+        return [0, 0, 0, 0];
+    }
+    [(r / a) as u8, (g / a) as u8, (b / a) as u8, (a / count) as u8]
+}
+
+// src drawn over dst
+fn blend(dst: Rgba, src: Rgba) -> Rgba {
+    let alpha = src[3] as u32;
+    let mix = |d: u8, s: u8| ((s as u32 * alpha + d as u32 * (255 - alpha)) / 255) as u8;
+    [
+        mix(dst[0], src[0]),
+        mix(dst[1], src[1]),
+        mix(dst[2], src[2]),
+        dst[3].max(src[3]),
+    ]
+}
+
+fn parse_rgb(s: &str) -> Option<Rgba> {
+    let hex = s.trim_left_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiled_json;
+
+    // two 1x2 tiles, red on top of blue for tile 1 and green for tile 2
+    // which has its colour set by property, tile 3 is the collision tile
+    const MAP: &str = r##"{
+        "type": "map", "version": 1.2, "orientation": "orthogonal",
+        "width": 3, "height": 2, "tilewidth": 1, "tileheight": 2, "infinite": false,
+        "tilesets": [
+            { "firstgid": 1, "name": "tiles", "tilewidth": 1, "tileheight": 2,
+              "spacing": 0, "margin": 0, "image": "tiles.png", "imagewidth": 2, "imageheight": 2,
+              "tiles": [{ "id": 1, "properties": [
+                { "name": "colour", "type": "string", "value": "#102030" }] }] },
+            { "firstgid": 3, "name": "collision_graphic", "tilewidth": 1, "tileheight": 2,
+              "spacing": 0, "margin": 0, "image": "collision.png", "imagewidth": 1, "imageheight": 2 }
+        ],
+        "layers": [
+            { "type": "tilelayer", "name": "base", "width": 3, "height": 2, "opacity": 1,
+              "visible": true, "data": [1,2,1, 2,1,2] },
+            { "type": "tilelayer", "name": "decoration", "width": 3, "height": 2, "opacity": 1,
+              "visible": true, "data": [0,0,0, 0,0,0] },
+            { "type": "tilelayer", "name": "collision", "width": 3, "height": 2, "opacity": 1,
+              "visible": true, "data": [0,0,3, 0,0,0] }
+        ]
+    }"##;
+
+    #[test]
+    fn colours_and_updates() {
+        let map_def = tiled_json::parse(MAP.as_bytes(), |_| unreachable!()).unwrap();
+        let mut map = Map::new(&map_def, 0);
+        let mut minimap = Minimap::new(&map, 0);
+        let pixels = [
+            255, 0, 0, 255, 0, 255, 0, 255, //
+            0, 0, 255, 255, 0, 255, 0, 255,
+        ];
+        minimap.add_tileset_pixels(&map.tilesets()[0], &pixels, 2, 2);
+        minimap.update(&map);
+
+        assert_eq!((minimap.width(), minimap.height()), (3, 2));
+        assert_eq!(minimap.pixel(0, 0), [127, 0, 127, 255]);
+        assert_eq!(minimap.pixel(1, 0), [16, 32, 48, 255]);
+        // collision tints the tile
+        assert_eq!(minimap.pixel(2, 0), [163, 19, 83, 255]);
+        assert_eq!(minimap.redraws(), 1);
+
+        minimap.update(&map);
+        assert_eq!(minimap.redraws(), 1);
+
+        map.write_tile(0, 0, 0, 2, None, false);
+        minimap.update(&map);
+        assert_eq!(minimap.redraws(), 2);
+        assert_eq!(minimap.pixel(0, 0), [16, 32, 48, 255]);
+
+        minimap.set_marker(&map, Some((1, 1)));
+        assert_eq!(minimap.pixel(1, 1), minimap.marker_colour);
+        minimap.set_marker(&map, None);
+        assert_eq!(minimap.pixel(1, 1), [127, 0, 127, 255]);
+    }
+}
@@ -0,0 +1,541 @@
+//! Tile to pixel conversions for the map orientations tiled supports.
+//!
+//! The math follows the renderers in tiled itself so maps line up with what
+//! designers see in the editor. Pixel positions are relative to the top left
+//! of the map with tile 0, 0 as the first tile.
+use ggez::graphics::Point2;
+
+use tiled;
+use tiled_json;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StaggerAxis {
+    X,
+    Y,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StaggerIndex {
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Orthogonal,
+    Isometric,
+    Staggered {
+        axis: StaggerAxis,
+        index: StaggerIndex,
+    },
+    Hexagonal {
+        axis: StaggerAxis,
+        index: StaggerIndex,
+        side_length: f32,
+    },
+}
+
+impl Orientation {
+    /// orientation of a tiled map, stagger settings are read from the map
+    /// properties and default to tiled's defaults
+    /// `tiled::parse` drops them from TMX maps, load those with `tiled_tmx::parse`
+    pub fn from_tiled(map_def: &tiled::Map) -> Self {
+        let property = |name: &str| match map_def.properties.get(name) {
+            Some(&tiled::PropertyValue::StringValue(ref s)) => Some(s.clone()),
+            Some(&tiled::PropertyValue::IntValue(v)) => Some(v.to_string()),
+            _ => None,
+        };
+        let axis = match property(tiled_json::STAGGER_AXIS_PROPERTY).as_ref().map(|s| s.as_str()) {
+            Some("x") => StaggerAxis::X,
+            _ => StaggerAxis::Y,
+        };
+        let index = match property(tiled_json::STAGGER_INDEX_PROPERTY).as_ref().map(|s| s.as_str()) {
+            Some("even") => StaggerIndex::Even,
+            _ => StaggerIndex::Odd,
+        };
+
+        match map_def.orientation {
+            tiled::Orientation::Orthogonal => Orientation::Orthogonal,
+            tiled::Orientation::Isometric => Orientation::Isometric,
+            tiled::Orientation::Staggered => Orientation::Staggered { axis, index },
+            tiled::Orientation::Hexagonal => Orientation::Hexagonal {
+                axis,
+                index,
+                side_length: property(tiled_json::HEX_SIDE_LENGTH_PROPERTY)
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0.0),
+            },
+        }
+    }
+
+    /// tiled orientation, stagger settings are written to properties
+    pub fn to_tiled(&self, properties: &mut tiled::Properties) -> tiled::Orientation {
+        let mut stagger = |axis: StaggerAxis, index: StaggerIndex| {
+            let axis = match axis {
+                StaggerAxis::X => "x",
+                StaggerAxis::Y => "y",
+            };
+            let index = match index {
+                StaggerIndex::Odd => "odd",
+                StaggerIndex::Even => "even",
+            };
+            properties.insert(
+                tiled_json::STAGGER_AXIS_PROPERTY.to_string(),
+                tiled::PropertyValue::StringValue(axis.to_string()),
+            );
+            properties.insert(
+                tiled_json::STAGGER_INDEX_PROPERTY.to_string(),
+                tiled::PropertyValue::StringValue(index.to_string()),
+            );
+        };
+        match *self {
+            Orientation::Orthogonal => tiled::Orientation::Orthogonal,
+            Orientation::Isometric => tiled::Orientation::Isometric,
+            Orientation::Staggered { axis, index } => {
+                stagger(axis, index);
+                tiled::Orientation::Staggered
+            }
+            Orientation::Hexagonal {
+                axis,
+                index,
+                side_length,
+            } => {
+                stagger(axis, index);
+                properties.insert(
+                    tiled_json::HEX_SIDE_LENGTH_PROPERTY.to_string(),
+                    tiled::PropertyValue::IntValue(side_length as i32),
+                );
+                tiled::Orientation::Hexagonal
+            }
+        }
+    }
+}
+
+/// Geometry of a map grid
+#[derive(Debug, Clone, Copy)]
+pub struct TileGrid {
+    pub orientation: Orientation,
+    pub tile_width: f32,
+    pub tile_height: f32,
+    // isometric maps are shifted right by half their height in tiles
+    pub map_height: i32,
+}
+
+// derived sizes shared by staggered and hexagonal maps
+struct StaggerParams {
+    stagger_x: bool,
+    stagger_even: bool,
+    side_length_x: f32,
+    side_length_y: f32,
+    side_offset_x: f32,
+    side_offset_y: f32,
+    column_width: f32,
+    row_height: f32,
+}
+
+impl TileGrid {
+    pub fn new(orientation: Orientation, tile_width: f32, tile_height: f32, map_height: i32) -> Self {
+        TileGrid {
+            orientation,
+            tile_width,
+            tile_height,
+            map_height,
+        }
+    }
+
+    fn stagger_params(&self) -> StaggerParams {
+        let (axis, index, side_length) = match self.orientation {
+            Orientation::Staggered { axis, index } => (axis, index, 0.0),
+            Orientation::Hexagonal {
+                axis,
+                index,
+                side_length,
+            } => (axis, index, side_length),
+            _ => (StaggerAxis::Y, StaggerIndex::Odd, 0.0),
+        };
+        let stagger_x = axis == StaggerAxis::X;
+        let side_length_x = if stagger_x { side_length } else { 0.0 };
+        let side_length_y = if stagger_x { 0.0 } else { side_length };
+        let side_offset_x = (self.tile_width - side_length_x) / 2.0;
+        let side_offset_y = (self.tile_height - side_length_y) / 2.0;
+        StaggerParams {
+            stagger_x,
+            stagger_even: index == StaggerIndex::Even,
+            side_length_x,
+            side_length_y,
+            side_offset_x,
+            side_offset_y,
+            column_width: side_offset_x + side_length_x,
+            row_height: side_offset_y + side_length_y,
+        }
+    }
+
+    /// top left of the bounding box of the tile at x, y
+    pub fn tile_to_pixel(&self, x: i32, y: i32) -> Point2 {
+        let (tw, th) = (self.tile_width, self.tile_height);
+        match self.orientation {
+            Orientation::Orthogonal => Point2::new(x as f32 * tw, y as f32 * th),
+            Orientation::Isometric => {
+                let origin_x = self.map_height as f32 * tw / 2.0;
+                Point2::new(
+                    origin_x + (x - y) as f32 * tw / 2.0 - tw / 2.0,
+                    (x + y) as f32 * th / 2.0,
+                )
+            }
+            Orientation::Staggered { .. } | Orientation::Hexagonal { .. } => {
+                let p = self.stagger_params();
+                if p.stagger_x {
+                    let mut pixel_y = y as f32 * (th + p.side_length_y);
+                    if staggered(x, p.stagger_even) {
+                        pixel_y += p.row_height;
+                    }
+                    Point2::new(x as f32 * p.column_width, pixel_y)
+                } else {
+                    let mut pixel_x = x as f32 * (tw + p.side_length_x);
+                    if staggered(y, p.stagger_even) {
+                        pixel_x += p.column_width;
+                    }
+                    Point2::new(pixel_x, y as f32 * p.row_height)
+                }
+            }
+        }
+    }
+
+    /// centre of the tile at x, y
+    pub fn tile_center(&self, x: i32, y: i32) -> Point2 {
+        let p = self.tile_to_pixel(x, y);
+        Point2::new(p.x + self.tile_width / 2.0, p.y + self.tile_height / 2.0)
+    }
+
+    /// tile containing the pixel
+    pub fn pixel_to_tile(&self, px: f32, py: f32) -> (i32, i32) {
+        let (tw, th) = (self.tile_width, self.tile_height);
+        match self.orientation {
+            Orientation::Orthogonal => ((px / tw).floor() as i32, (py / th).floor() as i32),
+            Orientation::Isometric => {
+                let x = px - self.map_height as f32 * tw / 2.0;
+                let tx = py / th + x / tw;
+                let ty = py / th - x / tw;
+                (tx.floor() as i32, ty.floor() as i32)
+            }
+            Orientation::Staggered { .. } => self.staggered_pixel_to_tile(px, py),
+            Orientation::Hexagonal { .. } => self.hexagonal_pixel_to_tile(px, py),
+        }
+    }
+
+    fn staggered_pixel_to_tile(&self, px: f32, py: f32) -> (i32, i32) {
+        let p = self.stagger_params();
+        let (tw, th) = (self.tile_width, self.tile_height);
+        let (mut x, mut y) = (px, py);
+        if p.stagger_x {
+            if p.stagger_even {
+                x -= p.side_offset_x;
+            }
+        } else if p.stagger_even {
+            y -= p.side_offset_y;
+        }
+
+        // diamond shaped tile whose bounding box contains the point
+        let (mut rx, mut ry) = ((x / tw).floor() as i32, (y / th).floor() as i32);
+        let rel_x = x - rx as f32 * tw;
+        let rel_y = y - ry as f32 * th;
+        if p.stagger_x {
+            rx *= 2;
+            if p.stagger_even {
+                rx += 1;
+            }
+        } else {
+            ry *= 2;
+            if p.stagger_even {
+                ry += 1;
+            }
+        }
+
+        // corners of the bounding box belong to the neighbouring tiles
+        if p.stagger_x {
+            let x_pos = rel_y * (tw / th);
+            if p.side_offset_x - x_pos > rel_x {
+                return self.neighbour(rx, ry, -1, -1);
+            }
+            if -p.side_offset_x + x_pos > rel_x {
+                return self.neighbour(rx, ry, -1, 1);
+            }
+            if p.side_offset_x + x_pos < rel_x {
+                return self.neighbour(rx, ry, 1, -1);
+            }
+            if p.side_offset_x * 3.0 - x_pos < rel_x {
+                return self.neighbour(rx, ry, 1, 1);
+            }
+        } else {
+            let y_pos = rel_x * (th / tw);
+            if p.side_offset_y - y_pos > rel_y {
+                return self.neighbour(rx, ry, -1, -1);
+            }
+            if -p.side_offset_y + y_pos > rel_y {
+                return self.neighbour(rx, ry, 1, -1);
+            }
+            if p.side_offset_y + y_pos < rel_y {
+                return self.neighbour(rx, ry, -1, 1);
+            }
+            if p.side_offset_y * 3.0 - y_pos < rel_y {
+                return self.neighbour(rx, ry, 1, 1);
+            }
+        }
+        (rx, ry)
+    }
+
+    // diagonal neighbour of a staggered tile, dx and dy are -1 or 1
+    fn neighbour(&self, x: i32, y: i32, dx: i32, dy: i32) -> (i32, i32) {
+        let p = self.stagger_params();
+        if p.stagger_x {
+            // columns shift vertically, moving sideways keeps or changes the row
+            let shifted = staggered(x, p.stagger_even);
+            let ny = match (dy, shifted) {
+                (-1, true) => y,
+                (-1, false) => y - 1,
+                (_, true) => y + 1,
+                (_, false) => y,
+            };
+            (x + dx, ny)
+        } else {
+            let shifted = staggered(y, p.stagger_even);
+            let nx = match (dx, shifted) {
+                (-1, true) => x,
+                (-1, false) => x - 1,
+                (_, true) => x + 1,
+                (_, false) => x,
+            };
+            (nx, y + dy)
+        }
+    }
+
+    fn hexagonal_pixel_to_tile(&self, px: f32, py: f32) -> (i32, i32) {
+        let p = self.stagger_params();
+        let (tw, th) = (self.tile_width, self.tile_height);
+        let (mut x, mut y) = (px, py);
+        if p.stagger_x {
+            x -= if p.stagger_even { tw } else { p.side_offset_x };
+        } else {
+            y -= if p.stagger_even { th } else { p.side_offset_y };
+        }
+
+        // grid aligned tile, then the nearest of the hexagons around it
+        let (mut rx, mut ry) = (
+            (x / (p.column_width * 2.0)).floor() as i32,
+            (y / (p.row_height * 2.0)).floor() as i32,
+        );
+        let rel_x = x - rx as f32 * (p.column_width * 2.0);
+        let rel_y = y - ry as f32 * (p.row_height * 2.0);
+
+        if p.stagger_x {
+            rx = rx * 2 + if p.stagger_even { 1 } else { 0 };
+        } else {
+            ry = ry * 2 + if p.stagger_even { 1 } else { 0 };
+        }
+
+        let (centers, offsets) = if p.stagger_x {
+            let left = p.side_length_x / 2.0;
+            let center_x = left + p.column_width;
+            let center_y = th / 2.0;
+            (
+                [
+                    (left, center_y),
+                    (center_x, center_y - p.row_height),
+                    (center_x, center_y + p.row_height),
+                    (center_x + p.column_width, center_y),
+                ],
+                [(0, 0), (1, -1), (1, 0), (2, 0)],
+            )
+        } else {
+            let top = p.side_length_y / 2.0;
+            let center_x = tw / 2.0;
+            let center_y = top + p.row_height;
+            (
+                [
+                    (center_x, top),
+                    (center_x - p.column_width, center_y),
+                    (center_x + p.column_width, center_y),
+                    (center_x, center_y + p.row_height),
+                ],
+                [(0, 0), (-1, 1), (0, 1), (0, 2)],
+            )
+        };
+
+        let mut nearest = 0;
+        let mut min_dist = ::std::f32::MAX;
+        for (i, &(cx, cy)) in centers.iter().enumerate() {
+            let dist = (cx - rel_x) * (cx - rel_x) + (cy - rel_y) * (cy - rel_y);
+            if dist < min_dist {
+                min_dist = dist;
+                nearest = i;
+            }
+        }
+        (rx + offsets[nearest].0, ry + offsets[nearest].1)
+    }
+
+    /// size in pixels of a map of width by height tiles
+    pub fn map_pixel_size(&self, width: i32, height: i32) -> Point2 {
+        let (tw, th) = (self.tile_width, self.tile_height);
+        let (w, h) = (width as f32, height as f32);
+        match self.orientation {
+            Orientation::Orthogonal => Point2::new(w * tw, h * th),
+            Orientation::Isometric => Point2::new((w + h) * tw / 2.0, (w + h) * th / 2.0),
+            Orientation::Staggered { .. } | Orientation::Hexagonal { .. } => {
+                let p = self.stagger_params();
+                if p.stagger_x {
+                    let mut size_h = h * (th + p.side_length_y) + p.side_offset_y;
+                    if width <= 1 {
+                        size_h = h * (th + p.side_length_y);
+                    }
+                    Point2::new(w * p.column_width + p.side_offset_x, size_h)
+                } else {
+                    let mut size_w = w * (tw + p.side_length_x) + p.side_offset_x;
+                    if height <= 1 {
+                        size_w = w * (tw + p.side_length_x);
+                    }
+                    Point2::new(size_w, h * p.row_height + p.side_offset_y)
+                }
+            }
+        }
+    }
+
+    /// tiles in the inclusive range in the order they should be drawn so
+    /// tiles further down the screen overlap the ones behind them
+    pub fn draw_order(&self, left: i32, top: i32, right: i32, bottom: i32) -> Vec<(i32, i32)> {
+        let mut tiles = Vec::new();
+        match self.orientation {
+            Orientation::Staggered { axis: StaggerAxis::X, index }
+            | Orientation::Hexagonal {
+                axis: StaggerAxis::X,
+                index,
+                ..
+            } => {
+                // in each row the shifted columns sit lower, draw them last
+                let even = index == StaggerIndex::Even;
+                for y in top..=bottom {
+                    for shifted in [false, true].iter() {
+                        for x in left..=right {
+                            if staggered(x, even) == *shifted {
+                                tiles.push((x, y));
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {
+                for y in top..=bottom {
+                    for x in left..=right {
+                        tiles.push((x, y));
+                    }
+                }
+            }
+        }
+        tiles
+    }
+}
+
+// whether the row or column at index is shifted
+fn staggered(index: i32, stagger_even: bool) -> bool {
+    ((index & 1) == 1) != stagger_even
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // corners of the tile shape relative to its centre
+    fn outline(grid: &TileGrid) -> Vec<(f32, f32)> {
+        let (w, h) = (grid.tile_width / 2.0, grid.tile_height / 2.0);
+        match grid.orientation {
+            Orientation::Orthogonal => vec![(-w, -h), (w, -h), (w, h), (-w, h)],
+            Orientation::Isometric | Orientation::Staggered { .. } => {
+                vec![(0.0, -h), (w, 0.0), (0.0, h), (-w, 0.0)]
+            }
+            Orientation::Hexagonal {
+                axis: StaggerAxis::X,
+                side_length,
+                ..
+            } => {
+                let s = side_length / 2.0;
+                vec![(-w, 0.0), (-s, -h), (s, -h), (w, 0.0), (s, h), (-s, h)]
+            }
+            Orientation::Hexagonal { side_length, .. } => {
+                let s = side_length / 2.0;
+                vec![(0.0, -h), (w, -s), (w, s), (0.0, h), (-w, s), (-w, -s)]
+            }
+        }
+    }
+
+    // the centre, points just inside every corner and every edge midpoint
+    fn round_trip(orientation: Orientation, tw: f32, th: f32) {
+        let grid = TileGrid::new(orientation, tw, th, 10);
+        let corners = outline(&grid);
+        let mut offsets = vec![(0.0, 0.0)];
+        for (i, &(ax, ay)) in corners.iter().enumerate() {
+            let (bx, by) = corners[(i + 1) % corners.len()];
+            offsets.push((ax * 0.9, ay * 0.9));
+            offsets.push(((ax + bx) / 2.0 * 0.9, (ay + by) / 2.0 * 0.9));
+        }
+        for y in -4..12 {
+            for x in -4..12 {
+                let center = grid.tile_center(x, y);
+                for &(dx, dy) in offsets.iter() {
+                    assert_eq!(
+                        grid.pixel_to_tile(center.x + dx, center.y + dy),
+                        (x, y),
+                        "{:?} tile {} {} offset {} {}",
+                        orientation,
+                        x,
+                        y,
+                        dx,
+                        dy
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orthogonal_round_trip() {
+        round_trip(Orientation::Orthogonal, 16.0, 16.0);
+        let grid = TileGrid::new(Orientation::Orthogonal, 16.0, 16.0, 10);
+        assert_eq!(grid.pixel_to_tile(-0.5, 31.9), (-1, 1));
+        // top and left edges belong to the tile, bottom and right to its neighbours
+        assert_eq!(grid.pixel_to_tile(16.0, 16.0), (1, 1));
+        assert_eq!(grid.pixel_to_tile(15.99, 15.99), (0, 0));
+    }
+
+    #[test]
+    fn isometric_round_trip() {
+        round_trip(Orientation::Isometric, 64.0, 32.0);
+        let grid = TileGrid::new(Orientation::Isometric, 64.0, 32.0, 10);
+        // top corner of tile 0, 0 sits at half the map height
+        assert_eq!(grid.tile_to_pixel(0, 0), Point2::new(288.0, 0.0));
+    }
+
+    #[test]
+    fn staggered_round_trip() {
+        for &axis in [StaggerAxis::X, StaggerAxis::Y].iter() {
+            for &index in [StaggerIndex::Odd, StaggerIndex::Even].iter() {
+                round_trip(Orientation::Staggered { axis, index }, 64.0, 32.0);
+            }
+        }
+    }
+
+    #[test]
+    fn hexagonal_round_trip() {
+        for &axis in [StaggerAxis::X, StaggerAxis::Y].iter() {
+            for &index in [StaggerIndex::Odd, StaggerIndex::Even].iter() {
+                round_trip(
+                    Orientation::Hexagonal {
+                        axis,
+                        index,
+                        side_length: 16.0,
+                    },
+                    32.0,
+                    32.0,
+                );
+            }
+        }
+    }
+}
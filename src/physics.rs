@@ -0,0 +1,383 @@
+//! Free movement with box collision.
+//!
+//! Entities with a `Body` component in the map's world move by velocity in
+//! pixels instead of stepping tile by tile. Boxes collide with the blocking
+//! tiles of the entity's level and with the solid bodies of other entities
+//! on it, and slide along whatever they hit. The entity's footprint still
+//! follows the centre of its box so tile based queries keep working. Solid
+//! bodies do not end a move on tiles held by another entity and keep only the
+//! x or y part of the move if that one fits, free bodies pass over them and
+//! keep their old tiles until the ones under them are free.
+use ggez::graphics::{Point2, Rect};
+
+use map::{Map, MoveError};
+
+/// box and velocity of a freely moving entity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Body {
+    /// box relative to the entity's pos
+    pub bounds: Rect,
+    /// pixels per second
+    pub velocity: Point2,
+    /// solid bodies block each other, others only overlap
+    pub solid: bool,
+}
+
+impl Body {
+    /// solid width by height box standing on the entity's pos, like a bottom centre anchor
+    pub fn new(width: f32, height: f32) -> Self {
+        Body {
+            bounds: Rect::new(-width / 2.0, -height, width, height),
+            velocity: Point2::new(0.0, 0.0),
+            solid: true,
+        }
+    }
+
+    /// box in world pixels for an entity at pos
+    pub fn world_rect(&self, pos: Point2) -> Rect {
+        Rect::new(pos.x + self.bounds.x, pos.y + self.bounds.y, self.bounds.w, self.bounds.h)
+    }
+}
+
+/// what stopped a move, see move_and_slide
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Contacts {
+    pub blocked_x: bool,
+    pub blocked_y: bool,
+    /// solid bodies that were hit and entities holding the tile moved to, sorted
+    pub entities: Vec<String>,
+}
+
+// touching edges do not overlap
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.left() < b.right() && b.left() < a.right() && a.top() < b.bottom() && b.top() < a.bottom()
+}
+
+/// world box of the entity with id, None without a Body
+pub fn body_rect(map: &Map, id: &str) -> Option<Rect> {
+    let body = map.world().get::<Body>(id)?;
+    let e = map.entity_by_id(id)?;
+    Some(body.world_rect(e.pos()))
+}
+
+/// true when rect touches a blocking tile of level or leaves the map
+pub fn is_rect_blocked(map: &Map, level: usize, rect: &Rect) -> bool {
+    // shrink a little so boxes resting against a tile edge are not inside it
+    let epsilon = 0.001;
+    let (left, top) = map.point_to_tile_at(rect.left() + epsilon, rect.top() + epsilon);
+    let (right, bottom) = map.point_to_tile_at(rect.right() - epsilon, rect.bottom() - epsilon);
+    let (min_x, min_y, max_x, max_y) = map.tile_bounds();
+    for y in top..bottom + 1 {
+        for x in left..right + 1 {
+            if x < min_x || y < min_y || x >= max_x || y >= max_y || map.is_blocked_at(level, x, y) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// ids of the entities of layer whose body overlaps rect, sorted
+pub fn overlapping(map: &Map, layer: usize, rect: &Rect, except: Option<&str>) -> Vec<String> {
+    let mut found: Vec<String> = map
+        .entities_of_layer(layer)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|e| Some(e.id.as_str()) != except)
+        .filter(|e| match map.world().get::<Body>(&e.id) {
+            Some(body) => overlaps(&body.world_rect(e.pos()), rect),
+            None => false,
+        })
+        .map(|e| e.id.clone())
+        .collect();
+    found.sort();
+    found
+}
+
+/// ids of the entities whose bodies overlap the body of id
+pub fn overlapping_entity(map: &Map, id: &str) -> Vec<String> {
+    match (body_rect(map, id), map.entity_by_id(id)) {
+        (Some(rect), Some(e)) => overlapping(map, e.layer(), &rect, Some(id)),
+        _ => Vec::new(),
+    }
+}
+
+// solid bodies of layer other than id overlapping rect
+fn solid_hits(map: &Map, layer: usize, rect: &Rect, id: &str) -> Vec<String> {
+    overlapping(map, layer, rect, Some(id))
+        .into_iter()
+        .filter(|other| map.world().get::<Body>(other).map_or(false, |b| b.solid))
+        .collect()
+}
+
+/// moves the entity with id by its body's velocity over dt seconds
+/// each axis is resolved on its own so boxes slide along walls, velocity
+/// on a blocked axis is zeroed. None when id has no body.
+pub fn move_and_slide(map: &mut Map, id: &str, dt: f32) -> Option<Contacts> {
+    let body = *map.world().get::<Body>(id)?;
+    let (start, layer) = {
+        let e = map.entity_by_id(id)?;
+        (e.pos(), e.layer())
+    };
+    let mut pos = start;
+
+    // steps no longer than half a tile or half the box so nothing is skipped
+    let tile = map.tile_dimensions();
+    let max_step = (tile.x.min(tile.y).min(body.bounds.w).min(body.bounds.h) / 2.0).max(1.0);
+    let delta = Point2::new(body.velocity.x * dt, body.velocity.y * dt);
+    let steps = (delta.x.abs().max(delta.y.abs()) / max_step).ceil().max(1.0) as usize;
+
+    let mut contacts = Contacts::default();
+    for _ in 0..steps {
+        for axis in 0..2 {
+            let blocked = if axis == 0 { contacts.blocked_x } else { contacts.blocked_y };
+            let amount = if axis == 0 { delta.x } else { delta.y } / steps as f32;
+            if blocked || amount == 0.0 {
+                continue;
+            }
+            let moved = |t: f32| {
+                let mut p = pos;
+                if axis == 0 {
+                    p.x += amount * t;
+                } else {
+                    p.y += amount * t;
+                }
+                p
+            };
+            let hits = |p: Point2| {
+                let rect = body.world_rect(p);
+                let entities = if body.solid {
+                    solid_hits(map, layer, &rect, id)
+                } else {
+                    Vec::new()
+                };
+                (is_rect_blocked(map, layer, &rect), entities)
+            };
+
+            let (tile_hit, entity_hits) = hits(moved(1.0));
+            if !tile_hit && entity_hits.is_empty() {
+                pos = moved(1.0);
+                continue;
+            }
+            // largest free fraction of the step, to rest against what was hit
+            let (mut free, mut stuck) = (0.0, 1.0);
+            for _ in 0..8 {
+                let t = (free + stuck) / 2.0;
+                let (tile_hit, entity_hits) = hits(moved(t));
+                if tile_hit || !entity_hits.is_empty() {
+                    stuck = t;
+                } else {
+                    free = t;
+                }
+            }
+            pos = moved(free);
+            for other in entity_hits {
+                if !contacts.entities.contains(&other) {
+                    contacts.entities.push(other);
+                }
+            }
+            if axis == 0 {
+                contacts.blocked_x = true;
+            } else {
+                contacts.blocked_y = true;
+            }
+        }
+    }
+
+    // keep the tile index under the centre of the box, when the tiles there are
+    // taken or do not fit the footprint try keeping only the x or the y move
+    let tries = [pos, Point2::new(pos.x, start.y), Point2::new(start.x, pos.y)];
+    let mut placed = start;
+    let mut blocker = None;
+    for &to in tries.iter() {
+        match place_body(map, id, &body, to, layer) {
+            Ok(()) => (),
+            Err(MoveError::Occupied { .. }) if !body.solid => (),
+            Err(MoveError::Occupied { by, .. }) => {
+                blocker = blocker.or(Some(by));
+                continue;
+            }
+            Err(_) => continue,
+        }
+        placed = to;
+        break;
+    }
+    if placed != pos {
+        contacts.blocked_x |= placed.x != pos.x;
+        contacts.blocked_y |= placed.y != pos.y;
+        if let Some(by) = blocker {
+            if !contacts.entities.contains(&by) {
+                contacts.entities.push(by);
+            }
+        }
+        pos = placed;
+    }
+    contacts.entities.sort();
+
+    if let Some(body) = map.world_mut().get_mut::<Body>(id) {
+        if contacts.blocked_x {
+            body.velocity.x = 0.0;
+        }
+        if contacts.blocked_y {
+            body.velocity.y = 0.0;
+        }
+    }
+    if let Some(e) = map.entity_by_id_mut(id) {
+        e.set_pos(pos);
+    }
+    Some(contacts)
+}
+
+// tile under the centre of body at pos
+fn centre_tile(map: &Map, body: &Body, pos: Point2) -> (usize, usize) {
+    let rect = body.world_rect(pos);
+    map.point_to_tile(rect.x + rect.w / 2.0, rect.y + rect.h / 2.0)
+}
+
+// moves the tile index of id so its footprint sits under its box at pos the
+// way it does when the entity stands on its tiles
+fn place_body(map: &mut Map, id: &str, body: &Body, pos: Point2, layer: usize) -> Result<(), MoveError> {
+    let (cell_x, cell_y) = {
+        let e = match map.entity_by_id(id) {
+            Some(e) => e,
+            None => return Err(MoveError::UnknownEntity(id.to_string())),
+        };
+        let (width, height) = e.footprint();
+        let standing = centre_tile(map, body, map.footprint_foot((width, height), e.tile_x(), e.tile_y()));
+        // the cell of the footprint under the centre of the box, the bottom
+        // middle one for boxes reaching past the footprint
+        match e.covered_tiles().iter().position(|&tile| tile == standing) {
+            Some(i) => e.footprint_at(0, 0)[i],
+            None => (width / 2, height - 1),
+        }
+    };
+    let (x, y) = centre_tile(map, body, pos);
+    if x < cell_x || y < cell_y {
+        return Err(MoveError::OutOfBounds { x, y });
+    }
+    map.move_entity(id, x - cell_x, y - cell_y, layer)
+}
+
+/// moves every entity with a body, run it from a MapSystems each fixed update
+pub fn move_bodies(map: &mut Map, dt: f32) {
+    let ids = map.world().query().with::<Body>().ids();
+    for id in ids {
+        move_and_slide(map, &id, dt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::Entity;
+    use map::MapSystems;
+    use test_support;
+
+    fn spawn(map: &mut Map, id: &str, x: usize, y: usize, velocity: Point2) {
+        let mut e = Entity::new(id.to_string(), Point2::new(16.0, 16.0));
+        e.tile_x = x;
+        e.tile_y = y;
+        let foot = map.get_tile_foot(x, y);
+        map.add_entity(e).unwrap().warp_pos(foot);
+        let mut body = Body::new(8.0, 8.0);
+        body.velocity = velocity;
+        map.world_mut().insert(id, body);
+    }
+
+    #[test]
+    fn slides_and_overlaps() {
+        let map_def = test_support::test_map(&["1#1#", "111#", "111#"]);
+        let mut map = Map::new(&map_def, 0);
+
+        // moving right and up into the wall at column 3 keeps sliding up
+        spawn(&mut map, "hero", 2, 2, Point2::new(40.0, -16.0));
+        let contacts = move_and_slide(&mut map, "hero", 0.5).unwrap();
+        assert!(contacts.blocked_x && !contacts.blocked_y);
+        let rect = body_rect(&map, "hero").unwrap();
+        assert!((rect.right() - 48.0).abs() < 0.1);
+        assert!((rect.bottom() - 40.0).abs() < 0.01);
+        assert_eq!(map.world().get::<Body>("hero").unwrap().velocity, Point2::new(0.0, -16.0));
+        assert_eq!(map.entity_by_id("hero").unwrap().tile_y(), 2);
+
+        // keeps going up into the next tile
+        move_and_slide(&mut map, "hero", 0.5).unwrap();
+        assert_eq!(map.entity_by_id("hero").unwrap().tile_y(), 1);
+
+        // solid bodies stop each other, overlaps can be queried
+        spawn(&mut map, "slime", 0, 2, Point2::new(0.0, 0.0));
+        spawn(&mut map, "bat", 1, 2, Point2::new(-40.0, 0.0));
+        let contacts = move_and_slide(&mut map, "bat", 0.5).unwrap();
+        assert_eq!(contacts.entities, vec!["slime"]);
+        assert!(overlapping_entity(&map, "bat").is_empty());
+
+        map.world_mut().get_mut::<Body>("bat").unwrap().solid = false;
+        map.world_mut().get_mut::<Body>("bat").unwrap().velocity = Point2::new(-8.0, 0.0);
+        move_bodies(&mut map, 0.5);
+        assert_eq!(overlapping_entity(&map, "bat"), vec!["slime"]);
+        let rect = Rect::new(0.0, 0.0, 48.0, 48.0);
+        assert_eq!(overlapping(&map, 0, &rect, None), vec!["bat", "hero", "slime"]);
+        assert!(is_rect_blocked(&map, 0, &Rect::new(18.0, 2.0, 4.0, 4.0)));
+        assert!(!is_rect_blocked(&map, 0, &Rect::new(0.0, 16.0, 16.0, 16.0)));
+    }
+
+    #[test]
+    fn tiles_held_by_other_entities() {
+        let map_def = test_support::test_map(&["1111", "1111", "1111"]);
+        let mut map = Map::new(&map_def, 0);
+        for &(id, y) in [("sign", 1), ("statue", 2)].iter() {
+            let mut e = Entity::new(id.to_string(), Point2::new(16.0, 16.0));
+            e.tile_x = 1;
+            e.tile_y = y;
+            map.add_entity(e).unwrap();
+        }
+
+        // free bodies pass over the sign and keep their tile until it is free
+        spawn(&mut map, "ghost", 0, 1, Point2::new(32.0, 0.0));
+        map.world_mut().get_mut::<Body>("ghost").unwrap().solid = false;
+        let contacts = move_and_slide(&mut map, "ghost", 0.5).unwrap();
+        assert_eq!(contacts, Contacts::default());
+        let ghost = map.entity_by_id("ghost").unwrap();
+        assert_eq!((ghost.pos().x, ghost.tile_x()), (24.0, 0));
+
+        // solid bodies are stopped by the statue even though it has no body
+        spawn(&mut map, "knight", 0, 2, Point2::new(32.0, 0.0));
+        let contacts = move_and_slide(&mut map, "knight", 0.5).unwrap();
+        assert!(contacts.blocked_x && !contacts.blocked_y);
+        assert_eq!(contacts.entities, vec!["statue"]);
+        assert_eq!(map.entity_by_id("knight").unwrap().pos().x, 8.0);
+        assert_eq!(map.world().get::<Body>("knight").unwrap().velocity.x, 0.0);
+
+        // moving diagonally into the sign keeps the part of the move that fits
+        spawn(&mut map, "squire", 0, 0, Point2::new(32.0, 32.0));
+        let contacts = move_and_slide(&mut map, "squire", 0.5).unwrap();
+        assert!(contacts.blocked_y && !contacts.blocked_x);
+        assert_eq!(contacts.entities, vec!["sign"]);
+        let squire = map.entity_by_id("squire").unwrap();
+        assert_eq!((squire.tile_x(), squire.tile_y(), squire.pos()), (1, 0, Point2::new(24.0, 16.0)));
+
+        // wide footprints keep their top left tile, not the one under the box
+        let mut cart = Entity::new(String::from("cart"), Point2::new(32.0, 16.0)).with_footprint(2, 1);
+        cart.tile_x = 2;
+        cart.tile_y = 1;
+        let foot = map.footprint_foot((2, 1), 2, 1);
+        map.add_entity(cart).unwrap().warp_pos(foot);
+        let mut body = Body::new(24.0, 8.0);
+        body.velocity = Point2::new(0.0, -32.0);
+        map.world_mut().insert("cart", body);
+        move_and_slide(&mut map, "cart", 0.5).unwrap();
+        let cart = map.entity_by_id("cart").unwrap();
+        assert_eq!((cart.tile_x(), cart.tile_y()), (2, 0));
+    }
+
+    #[test]
+    fn bodies_move_in_systems() {
+        let map_def = test_support::test_map(&["111", "111"]);
+        let mut map = Map::new(&map_def, 0);
+        spawn(&mut map, "hero", 0, 0, Point2::new(32.0, 0.0));
+
+        let mut systems = MapSystems::new();
+        systems.add(|map: &mut Map| move_bodies(map, 0.5));
+        map.run_systems(&mut systems);
+        assert_eq!(map.entity_by_id("hero").unwrap().tile_x(), 1);
+    }
+}
@@ -0,0 +1,544 @@
+//! Procedural dungeon generation.
+//!
+//! Generators carve a floor `Layout` from a seed, which is then painted into
+//! a `tiled::Map` with the usual base, decoration and collision layers so it
+//! loads, draws and saves like a map made in Tiled. Stairs are written as
+//! objects in the `triggers` object group.
+use std::collections::{HashMap, VecDeque};
+
+use tiled;
+
+use map::{self, Map};
+
+/// object group holding generated stairs and exits
+pub const TRIGGER_GROUP: &str = "triggers";
+/// object type of the stairs a level is entered from, also its exit
+pub const STAIRS_UP: &str = "stairs_up";
+/// object type of the stairs leading deeper
+pub const STAIRS_DOWN: &str = "stairs_down";
+
+/// small xorshift generator so layouts only depend on the seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let state = seed ^ 0x9E37_79B9_7F4A_7C15;
+        Rng(if state == 0 { 0x2545_F491_4F6C_DD1D } else { state })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32
+    }
+
+    /// value in min..max, min when the range is empty
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min {
+            return min;
+        }
+        min + (self.next_u32() % (max - min) as u32) as i32
+    }
+
+    /// true with probability chance
+    pub fn chance(&mut self, chance: f32) -> bool {
+        (self.next_u32() as f32 / u32::max_value() as f32) < chance
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            None
+        } else {
+            Some(&items[self.range(0, items.len() as i32) as usize])
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Generator {
+    /// rectangular rooms joined by corridors in the order they were placed
+    RoomsAndCorridors {
+        max_rooms: usize,
+        min_size: i32,
+        max_size: i32,
+    },
+    /// random noise smoothed by cellular automata, only the largest cave is kept
+    Caves { fill: f32, iterations: usize },
+    /// a random walk carving floor until coverage of the map is open
+    DrunkardsWalk { coverage: f32 },
+}
+
+/// tile ids local to the dungeon tileset
+#[derive(Debug, Clone)]
+pub struct DungeonTiles {
+    /// floor tiles, picked at random for variety
+    pub floor: Vec<u32>,
+    pub wall: u32,
+    /// decoration scattered over floor tiles
+    pub decorations: Vec<u32>,
+    pub decoration_chance: f32,
+    /// decoration drawn on the stairs
+    pub stairs_up: Option<u32>,
+    pub stairs_down: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DungeonConfig {
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+    pub generator: Generator,
+    pub tiles: DungeonTiles,
+}
+
+/// carved floor plan of a dungeon
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub width: usize,
+    pub height: usize,
+    floor: Vec<bool>,
+    pub stairs_up: (usize, usize),
+    pub stairs_down: (usize, usize),
+}
+
+impl Layout {
+    fn new(width: usize, height: usize) -> Self {
+        Layout {
+            width,
+            height,
+            floor: vec![false; width * height],
+            stairs_up: (0, 0),
+            stairs_down: (0, 0),
+        }
+    }
+
+    pub fn is_floor(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.floor[x + y * self.width]
+    }
+
+    fn set(&mut self, x: usize, y: usize, floor: bool) {
+        // the outer ring is always wall so the dungeon is closed
+        if x > 0 && y > 0 && x + 1 < self.width && y + 1 < self.height {
+            self.floor[x + y * self.width] = floor;
+        }
+    }
+
+    fn wall_neighbours(&self, x: usize, y: usize) -> usize {
+        let mut count = 0;
+        for ny in y as i32 - 1..y as i32 + 2 {
+            for nx in x as i32 - 1..x as i32 + 2 {
+                if (nx, ny) == (x as i32, y as i32) {
+                    continue;
+                }
+                if nx < 0 || ny < 0 || !self.is_floor(nx as usize, ny as usize) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    // walking distance to every floor tile reachable from start
+    fn distances(&self, start: (usize, usize)) -> HashMap<(usize, usize), usize> {
+        let mut distances = HashMap::new();
+        let mut open = VecDeque::new();
+        distances.insert(start, 0);
+        open.push_back(start);
+        while let Some((x, y)) = open.pop_front() {
+            let distance = distances[&(x, y)];
+            let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+            for &next in neighbours.iter() {
+                if self.is_floor(next.0, next.1) && !distances.contains_key(&next) {
+                    distances.insert(next, distance + 1);
+                    open.push_back(next);
+                }
+            }
+        }
+        distances
+    }
+
+    // places the down stairs as far as possible from the up stairs
+    fn place_stairs(&mut self, up: (usize, usize)) {
+        self.stairs_up = up;
+        self.stairs_down = self
+            .distances(up)
+            .into_iter()
+            .max_by_key(|&((x, y), distance)| (distance, y, x))
+            .map(|(tile, _)| tile)
+            .unwrap_or(up);
+    }
+}
+
+/// carves the floor plan for config
+pub fn layout(config: &DungeonConfig) -> Layout {
+    let mut rng = Rng::new(config.seed);
+    let mut layout = Layout::new(config.width.max(3), config.height.max(3));
+    match config.generator {
+        Generator::RoomsAndCorridors {
+            max_rooms,
+            min_size,
+            max_size,
+        } => rooms_and_corridors(&mut layout, &mut rng, max_rooms, min_size, max_size),
+        Generator::Caves { fill, iterations } => caves(&mut layout, &mut rng, fill, iterations),
+        Generator::DrunkardsWalk { coverage } => drunkards_walk(&mut layout, &mut rng, coverage),
+    }
+    layout
+}
+
+fn rooms_and_corridors(layout: &mut Layout, rng: &mut Rng, max_rooms: usize, min_size: i32, max_size: i32) {
+    let (width, height) = (layout.width as i32, layout.height as i32);
+    let mut rooms: Vec<(i32, i32, i32, i32)> = Vec::new();
+    // rejected rooms count as attempts so crowded maps still finish
+    for _ in 0..max_rooms * 4 {
+        if rooms.len() == max_rooms {
+            break;
+        }
+        let w = rng.range(min_size, max_size + 1).min(width - 2);
+        let h = rng.range(min_size, max_size + 1).min(height - 2);
+        let x = rng.range(1, width - w);
+        let y = rng.range(1, height - h);
+        // keep a wall between rooms
+        let overlaps = rooms.iter().any(|&(rx, ry, rw, rh)| {
+            x <= rx + rw && x + w >= rx && y <= ry + rh && y + h >= ry
+        });
+        if overlaps {
+            continue;
+        }
+        for ty in y..y + h {
+            for tx in x..x + w {
+                layout.set(tx as usize, ty as usize, true);
+            }
+        }
+        rooms.push((x, y, w, h));
+    }
+
+    let centre = |&(x, y, w, h): &(i32, i32, i32, i32)| (x + w / 2, y + h / 2);
+    for pair in rooms.windows(2) {
+        let (ax, ay) = centre(&pair[0]);
+        let (bx, by) = centre(&pair[1]);
+        // an L shaped corridor, randomly horizontal or vertical first
+        let corner = if rng.chance(0.5) { (bx, ay) } else { (ax, by) };
+        for &((x0, y0), (x1, y1)) in [((ax, ay), corner), (corner, (bx, by))].iter() {
+            for x in x0.min(x1)..x0.max(x1) + 1 {
+                for y in y0.min(y1)..y0.max(y1) + 1 {
+                    layout.set(x as usize, y as usize, true);
+                }
+            }
+        }
+    }
+
+    match rooms.first() {
+        Some(room) => {
+            let (x, y) = centre(room);
+            layout.place_stairs((x as usize, y as usize));
+        }
+        None => {
+            let centre = (layout.width / 2, layout.height / 2);
+            layout.set(centre.0, centre.1, true);
+            layout.place_stairs(centre);
+        }
+    }
+}
+
+fn caves(layout: &mut Layout, rng: &mut Rng, fill: f32, iterations: usize) {
+    for y in 0..layout.height {
+        for x in 0..layout.width {
+            let floor = !rng.chance(fill);
+            layout.set(x, y, floor);
+        }
+    }
+    for _ in 0..iterations {
+        let walls: Vec<usize> = (0..layout.width * layout.height)
+            .map(|i| layout.wall_neighbours(i % layout.width, i / layout.width))
+            .collect();
+        for (i, count) in walls.into_iter().enumerate() {
+            let (x, y) = (i % layout.width, i / layout.width);
+            if count > 4 {
+                layout.set(x, y, false);
+            } else if count < 4 {
+                layout.set(x, y, true);
+            }
+        }
+    }
+
+    // keep only the largest cave so every floor tile is reachable
+    let mut best: Option<HashMap<(usize, usize), usize>> = None;
+    let mut seen = vec![false; layout.width * layout.height];
+    for y in 0..layout.height {
+        for x in 0..layout.width {
+            if !layout.is_floor(x, y) || seen[x + y * layout.width] {
+                continue;
+            }
+            let cave = layout.distances((x, y));
+            for &(cx, cy) in cave.keys() {
+                seen[cx + cy * layout.width] = true;
+            }
+            if best.as_ref().map(|b| cave.len() > b.len()).unwrap_or(true) {
+                best = Some(cave);
+            }
+        }
+    }
+    let best = best.unwrap_or_default();
+    for y in 0..layout.height {
+        for x in 0..layout.width {
+            if !best.contains_key(&(x, y)) {
+                layout.set(x, y, false);
+            }
+        }
+    }
+
+    // stairs up at the first open tile in reading order
+    let mut start = None;
+    'search: for y in 0..layout.height {
+        for x in 0..layout.width {
+            if layout.is_floor(x, y) {
+                start = Some((x, y));
+                break 'search;
+            }
+        }
+    }
+    let start = start.unwrap_or_else(|| {
+        let centre = (layout.width / 2, layout.height / 2);
+        layout.set(centre.0, centre.1, true);
+        centre
+    });
+    layout.place_stairs(start);
+}
+
+fn drunkards_walk(layout: &mut Layout, rng: &mut Rng, coverage: f32) {
+    let inner = (layout.width - 2) * (layout.height - 2);
+    let target = ((inner as f32 * coverage) as usize).max(1).min(inner);
+    let start = (layout.width / 2, layout.height / 2);
+    let (mut x, mut y) = start;
+    let mut open = 0;
+    // bounded so a tiny coverage rounding error can not walk forever
+    for _ in 0..inner * 100 {
+        if !layout.is_floor(x, y) {
+            layout.set(x, y, true);
+            open += 1;
+            if open >= target {
+                break;
+            }
+        }
+        match rng.range(0, 4) {
+            0 if x > 1 => x -= 1,
+            1 if x + 2 < layout.width => x += 1,
+            2 if y > 1 => y -= 1,
+            3 if y + 2 < layout.height => y += 1,
+            _ => (),
+        }
+    }
+    layout.place_stairs(start);
+}
+
+fn trigger_object(id: u32, kind: &str, tile: (usize, usize), tileset: &tiled::Tileset) -> tiled::Object {
+    let (width, height) = (tileset.tile_width as f32, tileset.tile_height as f32);
+    tiled::Object {
+        id,
+        gid: 0,
+        name: kind.to_string(),
+        obj_type: kind.to_string(),
+        x: tile.0 as f32 * width,
+        y: tile.1 as f32 * height,
+        rotation: 0.0,
+        visible: true,
+        shape: tiled::ObjectShape::Rect { width, height },
+        properties: HashMap::new(),
+    }
+}
+
+/// generates a dungeon drawn with tileset, which becomes the map's first tileset
+pub fn generate(config: &DungeonConfig, tileset: &tiled::Tileset) -> tiled::Map {
+    let layout = layout(config);
+    // separate stream so changing tiles does not change the layout
+    let mut rng = Rng::new(config.seed.wrapping_add(1));
+
+    let mut tileset = tileset.clone();
+    tileset.first_gid = 1;
+    let tile_count = if tileset.images.is_empty() {
+        tileset.tiles.len() as u32
+    } else {
+        map::tileset_uvs(&tileset).len() as u32
+    };
+    let collision = tiled::Tileset {
+        first_gid: tileset.first_gid + tile_count.max(1),
+        name: map::COLLISION_TILESET.to_string(),
+        tile_width: tileset.tile_width,
+        tile_height: tileset.tile_height,
+        spacing: 0,
+        margin: 0,
+        images: Vec::new(),
+        tiles: Vec::new(),
+    };
+    let gid = |id: u32| tileset.first_gid + id;
+
+    let (width, height) = (layout.width, layout.height);
+    let mut base = vec![vec![0; width]; height];
+    let mut decoration = vec![vec![0; width]; height];
+    let mut blocking = vec![vec![0; width]; height];
+    for y in 0..height {
+        for x in 0..width {
+            if layout.is_floor(x, y) {
+                base[y][x] = rng.pick(&config.tiles.floor).map(|id| gid(*id)).unwrap_or(0);
+                if rng.chance(config.tiles.decoration_chance) {
+                    if let Some(id) = rng.pick(&config.tiles.decorations) {
+                        decoration[y][x] = gid(*id);
+                    }
+                }
+            } else {
+                base[y][x] = gid(config.tiles.wall);
+                blocking[y][x] = collision.first_gid;
+            }
+        }
+    }
+    let stairs = [
+        (layout.stairs_up, config.tiles.stairs_up),
+        (layout.stairs_down, config.tiles.stairs_down),
+    ];
+    for &((x, y), id) in stairs.iter() {
+        decoration[y][x] = id.map(&gid).unwrap_or(0);
+    }
+
+    let tile_layer = |name: &str, tiles| tiled::Layer {
+        name: name.to_string(),
+        opacity: 1.0,
+        visible: true,
+        tiles,
+        properties: HashMap::new(),
+    };
+    let triggers = tiled::ObjectGroup {
+        name: TRIGGER_GROUP.to_string(),
+        opacity: 1.0,
+        visible: true,
+        objects: vec![
+            trigger_object(1, STAIRS_UP, layout.stairs_up, &tileset),
+            trigger_object(2, STAIRS_DOWN, layout.stairs_down, &tileset),
+        ],
+        colour: None,
+    };
+
+    let mut properties = HashMap::new();
+    properties.insert(
+        String::from("seed"),
+        tiled::PropertyValue::StringValue(config.seed.to_string()),
+    );
+
+    tiled::Map {
+        version: String::from("1.0"),
+        orientation: tiled::Orientation::Orthogonal,
+        width: width as u32,
+        height: height as u32,
+        tile_width: tileset.tile_width,
+        tile_height: tileset.tile_height,
+        layers: vec![
+            tile_layer("base", base),
+            tile_layer("decoration", decoration),
+            tile_layer("collision", blocking),
+        ],
+        tilesets: vec![tileset.clone(), collision],
+        image_layers: Vec::new(),
+        object_groups: vec![triggers],
+        properties,
+        background_colour: None,
+    }
+}
+
+/// generate, loaded as a Map
+pub fn generate_map(config: &DungeonConfig, tileset: &tiled::Tileset) -> Map {
+    Map::new(&generate(config, tileset), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(seed: u64, generator: Generator) -> DungeonConfig {
+        DungeonConfig {
+            width: 40,
+            height: 30,
+            seed,
+            generator,
+            tiles: DungeonTiles {
+                floor: vec![0, 1],
+                wall: 2,
+                decorations: vec![3],
+                decoration_chance: 0.1,
+                stairs_up: Some(4),
+                stairs_down: Some(5),
+            },
+        }
+    }
+
+    #[test]
+    fn seeded_and_connected() {
+        let generators = vec![
+            Generator::RoomsAndCorridors {
+                max_rooms: 8,
+                min_size: 3,
+                max_size: 7,
+            },
+            Generator::Caves {
+                fill: 0.45,
+                iterations: 4,
+            },
+            Generator::DrunkardsWalk { coverage: 0.3 },
+        ];
+        for generator in generators {
+            let a = layout(&config(7, generator.clone()));
+            assert_eq!(a, layout(&config(7, generator.clone())));
+
+            let (ux, uy) = a.stairs_up;
+            let (dx, dy) = a.stairs_down;
+            assert!(a.is_floor(ux, uy) && a.is_floor(dx, dy));
+            assert!(a.distances(a.stairs_up).contains_key(&a.stairs_down));
+            assert_ne!(a.stairs_up, a.stairs_down);
+        }
+    }
+
+    #[test]
+    fn generated_maps() {
+        let tileset = tiled::Tileset {
+            first_gid: 3,
+            name: String::from("dungeon"),
+            tile_width: 16,
+            tile_height: 16,
+            spacing: 0,
+            margin: 0,
+            images: vec![tiled::Image {
+                source: String::from("dungeon.png"),
+                width: 96,
+                height: 16,
+                transparent_colour: None,
+            }],
+            tiles: Vec::new(),
+        };
+        let config = config(3, Generator::Caves { fill: 0.45, iterations: 4 });
+        let map_def = generate(&config, &tileset);
+
+        // one level of base, decoration and collision layers
+        let names: Vec<&str> = map_def.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["base", "decoration", "collision"]);
+        assert_eq!(map_def.tilesets[0].first_gid, 1);
+        let collision = &map_def.tilesets[1];
+        assert_eq!(collision.name, map::COLLISION_TILESET);
+        assert_eq!(collision.first_gid, 7);
+
+        let layout = layout(&config);
+        let map = generate_map(&config, &tileset);
+        assert_eq!(map.blocking_tile(), Some(7));
+        let triggers = &map_def.object_groups[0];
+        assert_eq!(triggers.name, TRIGGER_GROUP);
+        assert_eq!(triggers.objects.len(), 2);
+        for object in triggers.objects.iter() {
+            assert_eq!(object.shape, tiled::ObjectShape::Rect { width: 16.0, height: 16.0 });
+            let (x, y) = ((object.x / 16.0) as usize, (object.y / 16.0) as usize);
+            let expected = if object.name == STAIRS_UP { layout.stairs_up } else { layout.stairs_down };
+            assert_eq!((x, y), expected);
+            assert!(layout.is_floor(x, y));
+            assert!([1, 2].contains(&map_def.layers[0].tiles[y][x]));
+            assert!(!map.is_blocked(0, x, y));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Named map regions.
+//!
+//! Rectangles and polygons in the `regions` object layer mark areas like a
+//! town or a dungeon floor. Their name, type and properties are free for the
+//! game to use for music, encounter tables or location names. Object
+//! rotation is ignored.
+use ggez::graphics::{Point2, Rect};
+use tiled;
+
+/// object group regions are read from
+pub const REGION_GROUP: &str = "regions";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegionShape {
+    Rect(Rect),
+    /// points in world pixels
+    Polygon(Vec<Point2>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    /// tiled object id, unique within the map
+    pub id: u32,
+    pub name: String,
+    pub region_type: String,
+    pub shape: RegionShape,
+    pub properties: tiled::Properties,
+}
+
+impl Region {
+    /// region for a rectangle or polygon object, None for other shapes
+    pub fn from_object(object: &tiled::Object) -> Option<Region> {
+        let shape = match object.shape {
+            tiled::ObjectShape::Rect { width, height } => {
+                RegionShape::Rect(Rect::new(object.x, object.y, width, height))
+            }
+            tiled::ObjectShape::Polygon { ref points } => RegionShape::Polygon(
+                points
+                    .iter()
+                    .map(|&(x, y)| Point2::new(object.x + x, object.y + y))
+                    .collect(),
+            ),
+            _ => return None,
+        };
+        Some(Region {
+            id: object.id,
+            name: object.name.clone(),
+            region_type: object.obj_type.clone(),
+            shape,
+            properties: object.properties.clone(),
+        })
+    }
+
+    /// regions of every rectangle and polygon in the REGION_GROUP object groups
+    pub fn from_groups(groups: &Vec<tiled::ObjectGroup>) -> Vec<Region> {
+        groups
+            .iter()
+            .filter(|group| group.name == REGION_GROUP)
+            .flat_map(|group| group.objects.iter())
+            .filter_map(Region::from_object)
+            .collect()
+    }
+
+    /// true when the world pixel x, y lies inside the region
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        match self.shape {
+            RegionShape::Rect(ref rect) => {
+                x >= rect.left() && x < rect.right() && y >= rect.top() && y < rect.bottom()
+            }
+            RegionShape::Polygon(ref points) => {
+                // even odd rule, count edges crossed by a ray going right
+                let mut inside = false;
+                let mut j = points.len().wrapping_sub(1);
+                for i in 0..points.len() {
+                    let (a, b) = (points[i], points[j]);
+                    if (a.y > y) != (b.y > y) && x < (b.x - a.x) * (y - a.y) / (b.y - a.y) + a.x {
+                        inside = !inside;
+                    }
+                    j = i;
+                }
+                inside
+            }
+        }
+    }
+}
+
+/// sent when an entity moves into or out of a region, see Map::drain_region_events
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegionEvent {
+    Enter { entity: String, region: u32 },
+    Leave { entity: String, region: u32 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ggez::graphics::Point2;
+
+    use entity::Entity;
+    use map::Map;
+    use test_support;
+    use tiled;
+
+    fn object(id: u32, name: &str, x: f32, shape: tiled::ObjectShape) -> tiled::Object {
+        tiled::Object {
+            id,
+            gid: 0,
+            name: name.to_string(),
+            obj_type: name.to_lowercase(),
+            x,
+            y: 0.0,
+            rotation: 0.0,
+            visible: true,
+            shape,
+            properties: tiled::Properties::new(),
+        }
+    }
+
+    #[test]
+    fn enter_and_leave() {
+        // a town covering the left two columns and a triangular forest on the right
+        let mut map_def = test_support::test_map(&["1111", "1111"]);
+        let town = tiled::ObjectShape::Rect {
+            width: 32.0,
+            height: 32.0,
+        };
+        let forest = tiled::ObjectShape::Polygon {
+            points: vec![(0.0, 0.0), (32.0, 0.0), (32.0, 32.0)],
+        };
+        map_def.object_groups.push(tiled::ObjectGroup {
+            name: REGION_GROUP.to_string(),
+            opacity: 1.0,
+            visible: true,
+            objects: vec![object(1, "Town", 0.0, town), object(2, "Forest", 32.0, forest)],
+            colour: None,
+        });
+        let mut map = Map::new(&map_def, 0);
+
+        let names: Vec<&str> = map.regions_at(8.0, 8.0).iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["Town"]);
+        assert!(map.regions_at(60.0, 4.0).len() == 1);
+        assert!(map.regions_at(36.0, 28.0).is_empty());
+
+        let hero = map.add_entity(Entity::new(String::from("hero"), Point2::new(16.0, 16.0))).unwrap().clone();
+        let enter = |region| RegionEvent::Enter { entity: String::from("hero"), region };
+        let leave = |region| RegionEvent::Leave { entity: String::from("hero"), region };
+        assert_eq!(map.drain_region_events(), vec![enter(1)]);
+
+        map.set_tile_pos(&hero, 1, 1, 0).unwrap();
+        assert!(map.drain_region_events().is_empty());
+
+        map.set_tile_pos(&hero, 3, 0, 0).unwrap();
+        assert_eq!(map.drain_region_events(), vec![leave(1), enter(2)]);
+
+        assert!(map.remove_entity(&hero).is_some());
+        assert_eq!(map.drain_region_events(), vec![leave(2)]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Fixed logical resolution.
+//!
+//! A `VirtualScreen` is drawn to at a fixed size, like 256x224, and then
+//! scaled into the window with bars filling what is left. Integer scaling
+//! keeps pixels square and sharp, fit scaling fills as much of the window as
+//! the aspect ratio allows. Window sizes and positions are in window units,
+//! as resize and mouse events give them, not drawable pixels, which differ
+//! on high dpi displays. Create it after
+//! `graphics::set_default_filter(ctx, FilterMode::Nearest)` for crisp pixels.
+//!
+//! ```ignore
+//! screen.begin(ctx)?;
+//! // draw the map and entities in logical pixels
+//! screen.end(ctx)?;
+//! graphics::present(ctx);
+//! ```
+use ggez::conf::NumSamples;
+use ggez::graphics::{self, Canvas, Color, DrawParam, Point2, Rect};
+use ggez::{Context, GameResult};
+
+use map::Map;
+use util;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleMode {
+    /// largest whole number scale that fits
+    Integer,
+    /// largest scale that fits, keeping the aspect ratio
+    Fit,
+}
+
+/// where a logical screen lands in a window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Letterbox {
+    pub scale: f32,
+    /// window units covered by the logical screen
+    pub viewport: Rect,
+}
+
+impl Letterbox {
+    pub fn new(logical: Point2, window: Point2, mode: ScaleMode) -> Self {
+        let scale = match mode {
+            ScaleMode::Integer => util::integer_scale(window.x, window.y, logical.x, logical.y),
+            ScaleMode::Fit => (window.x / logical.x).min(window.y / logical.y),
+        };
+        let (w, h) = (logical.x * scale, logical.y * scale);
+        // bars on whole pixels so the image is not resampled across them
+        let x = ((window.x - w) / 2.0).floor();
+        let y = ((window.y - h) / 2.0).floor();
+        Letterbox {
+            scale,
+            viewport: Rect::new(x, y, w, h),
+        }
+    }
+
+    /// logical position of a window position, None over the bars
+    pub fn to_logical(&self, x: f32, y: f32) -> Option<Point2> {
+        let v = self.viewport;
+        if x < v.left() || y < v.top() || x >= v.right() || y >= v.bottom() {
+            return None;
+        }
+        Some(Point2::new((x - v.x) / self.scale, (y - v.y) / self.scale))
+    }
+
+    /// window position of a logical position
+    pub fn to_window(&self, p: Point2) -> Point2 {
+        Point2::new(self.viewport.x + p.x * self.scale, self.viewport.y + p.y * self.scale)
+    }
+}
+
+pub struct VirtualScreen {
+    width: u32,
+    height: u32,
+    mode: ScaleMode,
+    window: Point2,
+    letterbox: Letterbox,
+    canvas: Canvas,
+
+    /// what the logical screen is cleared to
+    pub clear_colour: Color,
+    /// colour of the bars around it
+    pub bar_colour: Color,
+}
+
+impl VirtualScreen {
+    /// width by height logical pixels, fitted to the current window
+    pub fn new(ctx: &mut Context, width: u32, height: u32, mode: ScaleMode) -> GameResult<Self> {
+        let canvas = Canvas::new(ctx, width, height, NumSamples::One)?;
+        let (window_width, window_height) = graphics::get_size(ctx);
+        let window = Point2::new(window_width as f32, window_height as f32);
+        Ok(VirtualScreen {
+            width,
+            height,
+            mode,
+            window,
+            letterbox: Letterbox::new(Point2::new(width as f32, height as f32), window, mode),
+            canvas,
+
+            clear_colour: Color::new(0.0, 0.0, 0.0, 1.0),
+            bar_colour: Color::new(0.0, 0.0, 0.0, 1.0),
+        })
+    }
+
+    /// logical width and height
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn mode(&self) -> ScaleMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ScaleMode) {
+        self.mode = mode;
+        self.fit();
+    }
+
+    pub fn letterbox(&self) -> Letterbox {
+        self.letterbox
+    }
+
+    /// call from EventHandler::resize_event with the new window size
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.window = Point2::new(width as f32, height as f32);
+        self.fit();
+    }
+
+    fn fit(&mut self) {
+        let logical = Point2::new(self.width as f32, self.height as f32);
+        self.letterbox = Letterbox::new(logical, self.window, self.mode);
+    }
+
+    /// redirects drawing to the logical screen and clears it
+    pub fn begin(&self, ctx: &mut Context) -> GameResult<()> {
+        graphics::set_canvas(ctx, Some(&self.canvas));
+        graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, self.width as f32, self.height as f32))?;
+        graphics::set_background_color(ctx, self.clear_colour);
+        graphics::clear(ctx);
+        Ok(())
+    }
+
+    /// draws the logical screen scaled into the window, between the bars
+    pub fn end(&self, ctx: &mut Context) -> GameResult<()> {
+        graphics::set_canvas(ctx, None);
+        graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, self.window.x, self.window.y))?;
+        graphics::set_background_color(ctx, self.bar_colour);
+        graphics::clear(ctx);
+        let mut param = DrawParam::default();
+        param.dest = Point2::new(self.letterbox.viewport.x, self.letterbox.viewport.y);
+        param.scale = Point2::new(self.letterbox.scale, self.letterbox.scale);
+        graphics::draw_ex(ctx, &self.canvas, param)
+    }
+
+    /// logical position under a window position, like a mouse event's, None over the bars
+    pub fn to_logical(&self, x: f32, y: f32) -> Option<Point2> {
+        self.letterbox.to_logical(x, y)
+    }
+
+    /// world position under a window position, for a map drawn at its camera
+    pub fn to_world(&self, map: &Map, x: f32, y: f32) -> Option<Point2> {
+        screen_to_world(&self.letterbox, map, x, y)
+    }
+}
+
+fn screen_to_world(letterbox: &Letterbox, map: &Map, x: f32, y: f32) -> Option<Point2> {
+    let p = letterbox.to_logical(x, y)?;
+    let offset = map.camera_offset();
+    Some(Point2::new(p.x + offset.x, p.y + offset.y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support;
+    use util;
+
+    #[test]
+    fn letterboxing_and_mouse() {
+        let logical = Point2::new(256.0, 224.0);
+
+        let integer = Letterbox::new(logical, Point2::new(800.0, 600.0), ScaleMode::Integer);
+        assert_eq!(integer.scale, 2.0);
+        assert_eq!(integer.viewport, Rect::new(144.0, 76.0, 512.0, 448.0));
+
+        let fit = Letterbox::new(logical, Point2::new(800.0, 600.0), ScaleMode::Fit);
+        assert!((fit.scale - 600.0 / 224.0).abs() < 1e-5);
+        assert_eq!(fit.viewport.y, 0.0);
+
+        // windows smaller than the logical screen still draw it whole size
+        let small = Letterbox::new(logical, Point2::new(200.0, 200.0), ScaleMode::Integer);
+        assert_eq!(small.scale, 1.0);
+        assert_eq!(util::integer_scale(800.0, 600.0, 256.0, 224.0), 2.0);
+        assert_eq!(util::integer_scale(200.0, 100.0, 256.0, 224.0), 1.0);
+
+        assert_eq!(integer.to_logical(144.0, 76.0), Some(Point2::new(0.0, 0.0)));
+        assert_eq!(integer.to_logical(400.0, 300.0), Some(Point2::new(128.0, 112.0)));
+        assert!(integer.to_logical(100.0, 300.0).is_none());
+        assert!(integer.to_logical(656.0, 300.0).is_none());
+        assert_eq!(integer.to_window(Point2::new(128.0, 112.0)), Point2::new(400.0, 300.0));
+
+        let map_def = test_support::test_map(&vec!["0".repeat(32); 28]);
+        let mut map = Map::new(&map_def, 0);
+        map.set_camera(Rect::new(32.0, 16.0, 256.0, 224.0));
+        assert_eq!(screen_to_world(&integer, &map, 400.0, 300.0), Some(Point2::new(160.0, 128.0)));
+    }
+}
@@ -0,0 +1,163 @@
+//! Line of sight and range queries.
+//!
+//! Everything works in map tile coordinates like `Entity::tile_x`, with
+//! collision tiles blocking sight the same way they block movement.
+use common::Facing;
+use entity::Entity;
+use map::Map;
+
+/// tiles on the Bresenham line from one tile to another, both ends included
+pub fn line(from: (i32, i32), to: (i32, i32)) -> Vec<(i32, i32)> {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let sx = if x < to.0 { 1 } else { -1 };
+    let sy = if y < to.1 { 1 } else { -1 };
+    let mut err = dx + dy;
+
+    let mut tiles = vec![(x, y)];
+    while (x, y) != to {
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+        tiles.push((x, y));
+    }
+    tiles
+}
+
+/// true when no collision tile of level lies between the two tiles
+/// the end tiles themselves may be blocked, so walls can be seen
+pub fn has_line_of_sight(map: &Map, level: usize, from: (usize, usize), to: (usize, usize)) -> bool {
+    let tiles = line((from.0 as i32, from.1 as i32), (to.0 as i32, to.1 as i32));
+    let between = &tiles[1..tiles.len().saturating_sub(1).max(1)];
+    between
+        .iter()
+        .all(|&(x, y)| !map.is_blocked(level, x as usize, y as usize))
+}
+
+/// true when a and b are on the same layer with nothing blocking the line between them
+pub fn can_see(map: &Map, a: &Entity, b: &Entity) -> bool {
+    a.layer() == b.layer()
+        && has_line_of_sight(map, a.layer(), (a.tile_x(), a.tile_y()), (b.tile_x(), b.tile_y()))
+}
+
+fn distance(a: (usize, usize), b: (usize, usize)) -> f32 {
+    let dx = a.0 as f32 - b.0 as f32;
+    let dy = a.1 as f32 - b.1 as f32;
+    (dx * dx + dy * dy).sqrt()
+}
+
+/// entities of layer within radius tiles of centre, nearest first
+pub fn entities_in_radius<'a>(map: &'a Map, layer: usize, centre: (usize, usize), radius: f32) -> Vec<&'a Entity> {
+    let mut found: Vec<&Entity> = map
+        .entities_of_layer(layer)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|e| distance(centre, (e.tile_x(), e.tile_y())) <= radius)
+        .collect();
+    found.sort_by(|a, b| {
+        let da = distance(centre, (a.tile_x(), a.tile_y()));
+        let db = distance(centre, (b.tile_x(), b.tile_y()));
+        da.partial_cmp(&db).unwrap().then_with(|| a.id.cmp(&b.id))
+    });
+    found
+}
+
+/// other entities within radius tiles of e and half_angle degrees of facing, nearest first
+/// combine with can_see for sight cones that respect walls
+pub fn entities_in_cone<'a>(
+    map: &'a Map,
+    e: &Entity,
+    facing: Facing,
+    radius: f32,
+    half_angle: f32,
+) -> Vec<&'a Entity> {
+    let (fx, fy) = facing.offset();
+    let cos_limit = half_angle.to_radians().cos();
+    let origin = (e.tile_x(), e.tile_y());
+    entities_in_radius(map, e.layer(), origin, radius)
+        .into_iter()
+        .filter(|other| {
+            if other.id == e.id {
+                return false;
+            }
+            let dx = other.tile_x() as f32 - origin.0 as f32;
+            let dy = other.tile_y() as f32 - origin.1 as f32;
+            let length = (dx * dx + dy * dy).sqrt();
+            length > 0.0 && (dx * fx as f32 + dy * fy as f32) / length >= cos_limit
+        })
+        .collect()
+}
+
+/// tile next to e in the direction it faces, None at the edge of the map
+pub fn tile_in_front(map: &Map, e: &Entity, facing: Facing) -> Option<(usize, usize)> {
+    let (dx, dy) = facing.offset();
+    let (x, y) = (e.tile_x() as i32 + dx, e.tile_y() as i32 + dy);
+    let (left, top, right, bottom) = map.tile_bounds();
+    if x < 0 || y < 0 || x >= right - left || y >= bottom - top {
+        return None;
+    }
+    Some((x as usize, y as usize))
+}
+
+/// entity e is facing, what it interacts with
+pub fn entity_in_front<'a>(map: &'a Map, e: &Entity, facing: Facing) -> Option<&'a Entity> {
+    let (x, y) = tile_in_front(map, e, facing)?;
+    map.entity(x, y, e.layer())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ggez::graphics::Point2;
+    use test_support;
+
+    // a collision tile at 2, 1
+
+    fn entity(id: &str, x: usize, y: usize) -> Entity {
+        let mut e = Entity::new(id.to_string(), Point2::new(16.0, 16.0));
+        e.tile_x = x;
+        e.tile_y = y;
+        e
+    }
+
+    #[test]
+    fn sight_and_range() {
+        assert_eq!(line((0, 0), (3, 1)), vec![(0, 0), (1, 0), (2, 1), (3, 1)]);
+
+        let map_def = test_support::test_map(&["11111", "11#11", "11111"]);
+        let mut map = Map::new(&map_def, 0);
+        map.add_entity(entity("guard", 0, 1)).unwrap();
+        map.add_entity(entity("thief", 4, 1)).unwrap();
+        map.add_entity(entity("cat", 1, 0)).unwrap();
+        map.add_entity(entity("dog", 0, 0)).unwrap();
+
+        let guard = map.entity(0, 1, 0).unwrap().clone();
+        let thief = map.entity(4, 1, 0).unwrap().clone();
+        let cat = map.entity(1, 0, 0).unwrap().clone();
+        assert!(!can_see(&map, &guard, &thief));
+        assert!(can_see(&map, &guard, &cat));
+        assert!(has_line_of_sight(&map, 0, (1, 0), (2, 1)));
+
+        let near: Vec<&str> = entities_in_radius(&map, 0, (0, 1), 2.0)
+            .iter()
+            .map(|e| e.id.as_str())
+            .collect();
+        assert_eq!(near, vec!["guard", "dog", "cat"]);
+
+        let ahead = entities_in_cone(&map, &guard, Facing::Right, 5.0, 30.0);
+        assert_eq!(ahead.len(), 1);
+        assert_eq!(ahead[0].id, "thief");
+
+        assert_eq!(tile_in_front(&map, &guard, Facing::Left), None);
+        assert_eq!(tile_in_front(&map, &guard, Facing::Up), Some((0, 0)));
+        assert_eq!(entity_in_front(&map, &guard, Facing::Up).map(|e| e.id.as_str()), Some("dog"));
+        assert!(entity_in_front(&map, &guard, Facing::Right).is_none());
+    }
+}
@@ -1,7 +1,8 @@
 use ggez::{
-    self, graphics::{self, spritebatch::SpriteBatch, Image, Point2, Rect},
+    self, graphics::{self, spritebatch::SpriteBatch, Color, Image, Point2, Rect},
 };
 
+use spritesheet::SpriteSheet;
 use util;
 
 pub struct SpriteDrawContext<'a> {
@@ -15,11 +16,13 @@ impl<'a> graphics::Drawable for SpriteDrawContext<'a> {
         let mut param = param.clone();
         param.dest.x += pos.x;
         param.dest.y += pos.y;
-        self.sprite.sprite_batch.draw_ex(ctx, param)
+        self.sprite.draw_ex(ctx, param)
+    }
+    fn set_blend_mode(&mut self, mode: Option<graphics::BlendMode>) {
+        self.sprite.set_blend_mode(mode);
     }
-    fn set_blend_mode(&mut self, mode: Option<graphics::BlendMode>) {}
     fn get_blend_mode(&self) -> Option<graphics::BlendMode> {
-        None
+        self.sprite.get_blend_mode()
     }
 }
 
@@ -29,19 +32,56 @@ pub struct Sprite {
 
     pub sprite_batch: SpriteBatch,
     pub uvs: Vec<Rect>,
+    // where each frame is drawn inside width by height, for trimmed sheets
+    pub offsets: Vec<Point2>,
     frame: usize,
+
+    // applied to every frame, see set_frame
+    tint: Option<Color>,
+    flip_x: bool,
+    flip_y: bool,
+    rotation: f32,
+    scale: Point2,
+    // point rotation, scaling and flipping happen around, as a fraction of width and height
+    anchor: Point2,
+    blend_mode: Option<graphics::BlendMode>,
 }
 
 impl Sprite {
     pub fn new(image: graphics::Image, width: f32, height: f32) -> Self {
         let uvs = util::generate_uvs(image.width() as f32, image.height() as f32, width, height);
+        Sprite::with_uvs(image, width, height, uvs, Vec::new())
+    }
+
+    /// frames as described by a sprite sheet, sized like its first untrimmed frame
+    pub fn from_sheet(image: graphics::Image, sheet: &SpriteSheet) -> Self {
+        let uvs = sheet.uvs(image.width() as f32, image.height() as f32);
+        let size = sheet
+            .frames
+            .first()
+            .map(|f| f.source_size)
+            .unwrap_or(Point2::new(image.width() as f32, image.height() as f32));
+        let offsets = sheet.frames.iter().map(|f| f.offset).collect();
+        Sprite::with_uvs(image, size.x, size.y, uvs, offsets)
+    }
+
+    fn with_uvs(image: Image, width: f32, height: f32, uvs: Vec<Rect>, offsets: Vec<Point2>) -> Self {
         Sprite {
             width,
             height,
 
             sprite_batch: SpriteBatch::new(image),
             uvs,
+            offsets,
             frame: 0,
+
+            tint: None,
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.0,
+            scale: Point2::new(1.0, 1.0),
+            anchor: Point2::new(0.5, 1.0),
+            blend_mode: None,
         }
     }
 
@@ -52,11 +92,85 @@ impl Sprite {
     pub fn set_frame(&mut self, frame: usize) {
         self.frame = frame;
         self.sprite_batch.clear();
+        if let Some(&src) = self.uvs.get(frame) {
+            let param = self.frame_param(src);
+            self.sprite_batch.add(param);
+        }
+    }
 
-        let mut param = graphics::DrawParam::default();
+    // rebuilds the batch after a change to how frames are drawn
+    fn refresh(&mut self) {
+        let frame = self.frame;
+        self.set_frame(frame);
+    }
+
+    /// draw param of one frame relative to the top left of the sprite
+    pub fn frame_param(&self, src: Rect) -> graphics::DrawParam {
+        let offset = self.offsets.get(self.frame).cloned().unwrap_or(Point2::new(0.0, 0.0));
+        let anchor = Point2::new(self.anchor.x * self.width, self.anchor.y * self.height);
+        let scale = Point2::new(
+            if self.flip_x { -self.scale.x } else { self.scale.x },
+            if self.flip_y { -self.scale.y } else { self.scale.y },
+        );
+        transform_param(src, offset, anchor, scale, self.rotation, self.tint)
+    }
 
-        param.src = self.uvs[frame];
-        self.sprite_batch.add(param);
+    pub fn tint(&self) -> Option<Color> {
+        self.tint
+    }
+
+    /// colour multiplied into every pixel, None draws the image as is
+    pub fn set_tint(&mut self, tint: Option<Color>) {
+        self.tint = tint;
+        self.refresh();
+    }
+
+    /// opacity from 0 to 1, kept in the tint
+    pub fn set_alpha(&mut self, alpha: f32) {
+        let mut tint = self.tint.unwrap_or(Color::new(1.0, 1.0, 1.0, 1.0));
+        tint.a = alpha;
+        self.set_tint(Some(tint));
+    }
+
+    pub fn flip(&self) -> (bool, bool) {
+        (self.flip_x, self.flip_y)
+    }
+
+    /// mirrors frames around the anchor, horizontally and vertically
+    pub fn set_flip(&mut self, flip_x: bool, flip_y: bool) {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+        self.refresh();
+    }
+
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    /// radians clockwise around the anchor
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.rotation = rotation;
+        self.refresh();
+    }
+
+    pub fn scale(&self) -> Point2 {
+        self.scale
+    }
+
+    /// scale around the anchor
+    pub fn set_scale(&mut self, scale: Point2) {
+        self.scale = scale;
+        self.refresh();
+    }
+
+    pub fn anchor(&self) -> Point2 {
+        self.anchor
+    }
+
+    /// point transforms happen around as a fraction of the sprite size, bottom centre by default
+    pub fn set_anchor(&mut self, anchor: Point2) {
+        self.anchor = anchor;
+        self.refresh();
     }
 
     pub fn with_context<'a>(&'a mut self, comp: &'a SpriteComponent) -> SpriteDrawContext<'a> {
@@ -65,13 +179,38 @@ impl Sprite {
     }
 }
 
+/// param drawing a frame whose top left is at offset so that it is scaled and
+/// rotated around anchor, both in pixels from the sprite's top left
+pub fn transform_param(
+    src: Rect,
+    offset: Point2,
+    anchor: Point2,
+    scale: Point2,
+    rotation: f32,
+    tint: Option<Color>,
+) -> graphics::DrawParam {
+    // the frame's top left relative to the anchor, scaled then rotated
+    let (x, y) = ((offset.x - anchor.x) * scale.x, (offset.y - anchor.y) * scale.y);
+    let (sin, cos) = rotation.sin_cos();
+    let mut param = graphics::DrawParam::default();
+    param.src = src;
+    param.dest = Point2::new(anchor.x + x * cos - y * sin, anchor.y + x * sin + y * cos);
+    param.scale = scale;
+    param.rotation = rotation;
+    param.color = tint;
+    param
+}
+
 impl graphics::Drawable for Sprite {
     fn draw_ex(&self, ctx: &mut ggez::Context, param: graphics::DrawParam) -> ggez::GameResult<()> {
         self.sprite_batch.draw_ex(ctx, param)
     }
-    fn set_blend_mode(&mut self, mode: Option<graphics::BlendMode>) {}
+    fn set_blend_mode(&mut self, mode: Option<graphics::BlendMode>) {
+        self.blend_mode = mode;
+        self.sprite_batch.set_blend_mode(mode);
+    }
     fn get_blend_mode(&self) -> Option<graphics::BlendMode> {
-        None
+        self.blend_mode
     }
 }
 
@@ -79,3 +218,38 @@ pub trait SpriteComponent {
     fn setup_sprite(&self, sprite: &mut Sprite);
     fn draw_sprite_at(&self) -> graphics::Point2;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn close(a: Point2, b: Point2) -> bool {
+        (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4
+    }
+
+    #[test]
+    fn transforms_around_the_anchor() {
+        let src = Rect::new(0.0, 0.0, 0.5, 1.0);
+        let zero = Point2::new(0.0, 0.0);
+        let feet = Point2::new(8.0, 24.0);
+        let one = Point2::new(1.0, 1.0);
+
+        let plain = transform_param(src, zero, feet, one, 0.0, None);
+        assert_eq!((plain.dest, plain.scale, plain.src), (zero, one, src));
+
+        // mirrored frames start at the right edge and grow left
+        let flipped = transform_param(src, zero, feet, Point2::new(-1.0, 1.0), 0.0, None);
+        assert!(close(flipped.dest, Point2::new(16.0, 0.0)));
+
+        // trim offsets are scaled with the frame
+        let trimmed = transform_param(src, Point2::new(2.0, 4.0), feet, Point2::new(2.0, 2.0), 0.0, None);
+        assert!(close(trimmed.dest, Point2::new(-4.0, -16.0)));
+
+        // a half turn about the feet puts the top left below and right of them
+        let tint = Color::new(1.0, 0.0, 0.0, 0.5);
+        let turned = transform_param(src, zero, feet, one, PI, Some(tint));
+        assert!(close(turned.dest, Point2::new(16.0, 48.0)));
+        assert_eq!((turned.rotation, turned.color), (PI, Some(tint)));
+    }
+}
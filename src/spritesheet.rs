@@ -0,0 +1,381 @@
+//! Sprite sheet descriptors.
+//!
+//! Reads the JSON written by Aseprite and TexturePacker, in both their array
+//! and hash layouts. Frames keep their name, their rect in the image and the
+//! offset trimming removed, so sprites of different sizes can share a sheet.
+//! Aseprite frame tags become named frame sequences, like `walk_up`.
+//!
+//! Hash layouts have no frame order, frames are sorted by name with numbers
+//! compared by value so `walk 10` comes after `walk 9`. Rotated frames are
+//! not supported, disable rotation when packing.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+use ggez::graphics::{Point2, Rect};
+use ggez::{Context, GameError, GameResult};
+use serde_json;
+
+use anim::Animation;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagDirection {
+    Forward,
+    Reverse,
+    PingPong,
+}
+
+/// named run of frames, from and to included
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameTag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    pub direction: TagDirection,
+}
+
+impl FrameTag {
+    /// frame indices in playing order
+    pub fn frames(&self) -> Vec<usize> {
+        let forward: Vec<usize> = (self.from..self.to + 1).collect();
+        match self.direction {
+            TagDirection::Forward => forward,
+            TagDirection::Reverse => forward.into_iter().rev().collect(),
+            TagDirection::PingPong => {
+                let back: Vec<usize> = forward[1..forward.len().saturating_sub(1).max(1)]
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect();
+                forward.into_iter().chain(back).collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetFrame {
+    pub name: String,
+    /// pixels in the sheet image
+    pub rect: Rect,
+    /// where the trimmed rect sits inside the untrimmed frame
+    pub offset: Point2,
+    /// untrimmed frame size
+    pub source_size: Point2,
+    /// seconds, when the exporter gives one
+    pub duration: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+    /// image file as written in the descriptor
+    pub image: String,
+    /// image size in pixels, zero when not given
+    pub size: Point2,
+    pub frames: Vec<SheetFrame>,
+    pub tags: Vec<FrameTag>,
+    names: HashMap<String, usize>,
+}
+
+impl Default for SpriteSheet {
+    fn default() -> Self {
+        SpriteSheet {
+            image: String::new(),
+            size: Point2::new(0.0, 0.0),
+            frames: Vec::new(),
+            tags: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct JsonRect {
+    #[serde(default)]
+    x: f32,
+    #[serde(default)]
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonFrame {
+    #[serde(default)]
+    filename: String,
+    frame: JsonRect,
+    #[serde(default)]
+    rotated: bool,
+    sprite_source_size: Option<JsonRect>,
+    source_size: Option<JsonRect>,
+    // milliseconds, aseprite only
+    duration: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonFrames {
+    Array(Vec<JsonFrame>),
+    Hash(BTreeMap<String, JsonFrame>),
+}
+
+#[derive(Deserialize)]
+struct JsonTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct JsonMeta {
+    #[serde(default)]
+    image: String,
+    size: Option<JsonRect>,
+    #[serde(default)]
+    frame_tags: Vec<JsonTag>,
+}
+
+#[derive(Deserialize)]
+struct JsonSheet {
+    frames: JsonFrames,
+    #[serde(default)]
+    meta: JsonMeta,
+}
+
+fn json_error(err: serde_json::Error) -> GameError {
+    GameError::from(format!("sprite sheet error: {}", err))
+}
+
+// compares runs of digits by value
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().cloned(), b.peek().cloned()) {
+            (None, None) => return Ordering::Equal,
+            (None, _) => return Ordering::Less,
+            (_, None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let number = |chars: &mut ::std::iter::Peekable<::std::str::Chars>| {
+                    let mut n = 0u64;
+                    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                        n = n.saturating_mul(10).saturating_add(d as u64);
+                        chars.next();
+                    }
+                    n
+                };
+                match number(&mut a).cmp(&number(&mut b)) {
+                    Ordering::Equal => (),
+                    other => return other,
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+// name without its file extension, "walk 0.aseprite" is "walk 0"
+fn stem(name: &str) -> &str {
+    match name.rfind('.') {
+        Some(i) if i > 0 => &name[..i],
+        _ => name,
+    }
+}
+
+impl SpriteSheet {
+    pub fn parse<R: Read>(reader: R) -> GameResult<SpriteSheet> {
+        let sheet: JsonSheet = serde_json::from_reader(reader).map_err(json_error)?;
+        let frames: Vec<(String, JsonFrame)> = match sheet.frames {
+            JsonFrames::Array(frames) => frames.into_iter().map(|f| (f.filename.clone(), f)).collect(),
+            JsonFrames::Hash(frames) => {
+                let mut frames: Vec<(String, JsonFrame)> = frames.into_iter().collect();
+                frames.sort_by(|a, b| natural_cmp(&a.0, &b.0));
+                frames
+            }
+        };
+
+        let mut result = SpriteSheet {
+            image: sheet.meta.image,
+            size: sheet
+                .meta
+                .size
+                .map(|s| Point2::new(s.w, s.h))
+                .unwrap_or(Point2::new(0.0, 0.0)),
+            ..SpriteSheet::default()
+        };
+        for (name, frame) in frames {
+            if frame.rotated {
+                return Err(GameError::from(format!("rotated sprite sheet frame {}", name)));
+            }
+            let rect = Rect::new(frame.frame.x, frame.frame.y, frame.frame.w, frame.frame.h);
+            let offset = frame
+                .sprite_source_size
+                .map(|s| Point2::new(s.x, s.y))
+                .unwrap_or(Point2::new(0.0, 0.0));
+            let source_size = frame
+                .source_size
+                .map(|s| Point2::new(s.w, s.h))
+                .unwrap_or(Point2::new(rect.w, rect.h));
+            result.add_frame(SheetFrame {
+                name,
+                rect,
+                offset,
+                source_size,
+                duration: frame.duration.map(|ms| ms / 1000.0),
+            });
+        }
+
+        for tag in sheet.meta.frame_tags {
+            if tag.from > tag.to || tag.to >= result.frames.len() {
+                return Err(GameError::from(format!("frame tag {} out of range", tag.name)));
+            }
+            let direction = match tag.direction.as_str() {
+                "reverse" => TagDirection::Reverse,
+                "pingpong" => TagDirection::PingPong,
+                _ => TagDirection::Forward,
+            };
+            result.tags.push(FrameTag {
+                name: tag.name,
+                from: tag.from,
+                to: tag.to,
+                direction,
+            });
+        }
+        Ok(result)
+    }
+
+    pub fn load(ctx: &mut Context, path: &str) -> GameResult<SpriteSheet> {
+        let file = ctx.filesystem.open(path)?;
+        SpriteSheet::parse(file)
+    }
+
+    /// adds a frame, reachable by its name with or without extension
+    pub fn add_frame(&mut self, frame: SheetFrame) {
+        let index = self.frames.len();
+        self.names.entry(frame.name.clone()).or_insert(index);
+        self.names.entry(stem(&frame.name).to_string()).or_insert(index);
+        self.frames.push(frame);
+    }
+
+    pub fn frame_index(&self, name: &str) -> Option<usize> {
+        self.names.get(name).cloned()
+    }
+
+    pub fn frame(&self, name: &str) -> Option<&SheetFrame> {
+        self.frame_index(name).map(|i| &self.frames[i])
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&FrameTag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    /// frames of a tag, or of a single frame with that name
+    pub fn frames(&self, name: &str) -> Option<Vec<usize>> {
+        match self.tag(name) {
+            Some(tag) => Some(tag.frames()),
+            None => self.frame_index(name).map(|i| vec![i]),
+        }
+    }
+
+    /// indices of named frames, None if any is missing
+    pub fn frames_named(&self, names: &[&str]) -> Option<Vec<usize>> {
+        names.iter().map(|name| self.frame_index(name)).collect()
+    }
+
+    /// animation playing the frames called name, each for its own duration
+    pub fn animation(&self, name: &str, should_loop: bool) -> Option<Animation> {
+        let frames = self.frames(name)?;
+        let durations = frames.iter().map(|&i| self.frames[i].duration.unwrap_or(0.0)).collect();
+        Some(Animation::new(frames, should_loop, 0.0).with_durations(durations))
+    }
+
+    /// frame rects as fractions of an image of the given size, for sprite batches
+    pub fn uvs(&self, image_width: f32, image_height: f32) -> Vec<Rect> {
+        let (width, height) = if self.size.x > 0.0 && self.size.y > 0.0 {
+            (self.size.x, self.size.y)
+        } else {
+            (image_width, image_height)
+        };
+        self.frames
+            .iter()
+            .map(|f| Rect::new(f.rect.x / width, f.rect.y / height, f.rect.w / width, f.rect.h / height))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASEPRITE: &str = r#"{
+        "frames": [
+            { "filename": "hero 0.aseprite", "frame": { "x": 0, "y": 0, "w": 16, "h": 24 },
+              "rotated": false, "trimmed": false,
+              "spriteSourceSize": { "x": 0, "y": 0, "w": 16, "h": 24 },
+              "sourceSize": { "w": 16, "h": 24 }, "duration": 100 },
+            { "filename": "hero 1.aseprite", "frame": { "x": 16, "y": 0, "w": 12, "h": 20 },
+              "rotated": false, "trimmed": true,
+              "spriteSourceSize": { "x": 2, "y": 4, "w": 12, "h": 20 },
+              "sourceSize": { "w": 16, "h": 24 }, "duration": 150 },
+            { "filename": "hero 2.aseprite", "frame": { "x": 28, "y": 0, "w": 16, "h": 24 },
+              "rotated": false, "trimmed": false,
+              "spriteSourceSize": { "x": 0, "y": 0, "w": 16, "h": 24 },
+              "sourceSize": { "w": 16, "h": 24 }, "duration": 100 }
+        ],
+        "meta": { "image": "hero.png", "size": { "w": 64, "h": 24 },
+            "frameTags": [
+                { "name": "walk_up", "from": 0, "to": 2, "direction": "pingpong" },
+                { "name": "idle", "from": 1, "to": 1, "direction": "forward" }
+            ] }
+    }"#;
+
+    const TEXTURE_PACKER: &str = r#"{
+        "frames": {
+            "door_10.png": { "frame": { "x": 32, "y": 0, "w": 16, "h": 16 }, "rotated": false,
+                             "trimmed": false, "sourceSize": { "w": 16, "h": 16 } },
+            "door_9.png": { "frame": { "x": 16, "y": 0, "w": 16, "h": 16 }, "rotated": false,
+                            "trimmed": false, "sourceSize": { "w": 16, "h": 16 } },
+            "chest.png": { "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "rotated": false,
+                           "trimmed": false, "sourceSize": { "w": 16, "h": 16 } }
+        },
+        "meta": { "image": "objects.png", "size": { "w": 48, "h": 16 } }
+    }"#;
+
+    #[test]
+    fn aseprite_and_texture_packer() {
+        let sheet = SpriteSheet::parse(ASEPRITE.as_bytes()).unwrap();
+        assert_eq!(sheet.image, "hero.png");
+        assert_eq!(sheet.frame_index("hero 1"), Some(1));
+        let trimmed = sheet.frame("hero 1.aseprite").unwrap();
+        assert_eq!(trimmed.offset, Point2::new(2.0, 4.0));
+        assert_eq!(trimmed.source_size, Point2::new(16.0, 24.0));
+        assert_eq!(sheet.frames("walk_up"), Some(vec![0, 1, 2, 1]));
+        assert_eq!(sheet.frames("idle"), Some(vec![1]));
+        assert_eq!(sheet.uvs(0.0, 0.0)[1], Rect::new(0.25, 0.0, 0.1875, 20.0 / 24.0));
+        let mut walk = sheet.animation("walk_up", true).unwrap();
+        assert_eq!(walk.frame(), 0);
+        walk.update(0.1);
+        assert_eq!(walk.frame(), 1);
+        // the trimmed frame is shown for 150ms rather than the first frame's 100
+        walk.update(0.1);
+        assert_eq!(walk.frame(), 1);
+        walk.update(0.05);
+        assert_eq!(walk.frame(), 2);
+
+        let sheet = SpriteSheet::parse(TEXTURE_PACKER.as_bytes()).unwrap();
+        let names: Vec<&str> = sheet.frames.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["chest.png", "door_9.png", "door_10.png"]);
+        assert_eq!(sheet.frames_named(&["door_9", "door_10"]), Some(vec![1, 2]));
+        assert!(sheet.frames_named(&["door_11"]).is_none());
+        assert!(sheet.tags.is_empty());
+    }
+}
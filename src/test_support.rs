@@ -0,0 +1,63 @@
+//! Builders for the maps used by the tests of the other modules.
+use tiled;
+
+use map::COLLISION_TILESET;
+
+/// gid of the collision tile in `test_map`
+pub(crate) const TEST_WALL: u32 = 5;
+
+/// orthogonal map of 16x16 tiles for tests with a base, decoration and
+/// collision layer, one string per row of tiles
+///
+/// digits are base gids from the four tiles of tiles.png, 0 leaves the tile
+/// empty and '#' is tile 1 with a collision tile, `TEST_WALL`, over it
+pub(crate) fn test_map<S: AsRef<str>>(rows: &[S]) -> tiled::Map {
+    let layer = |name: &str, tile: fn(char) -> u32| tiled::Layer {
+        name: name.to_string(),
+        opacity: 1.0,
+        visible: true,
+        tiles: rows.iter().map(|row| row.as_ref().chars().map(tile).collect()).collect(),
+        properties: tiled::Properties::new(),
+    };
+    let tileset = |first_gid: u32, name: &str, source: &str, size: i32| tiled::Tileset {
+        first_gid,
+        name: name.to_string(),
+        tile_width: 16,
+        tile_height: 16,
+        spacing: 0,
+        margin: 0,
+        images: vec![tiled::Image {
+            source: source.to_string(),
+            width: size,
+            height: size,
+            transparent_colour: None,
+        }],
+        tiles: Vec::new(),
+    };
+    let layers = vec![
+        layer("base", |c| match c {
+            '#' => 1,
+            c => c.to_digit(10).expect("test map rows are digits or #"),
+        }),
+        layer("decoration", |_| 0),
+        layer("collision", |c| if c == '#' { TEST_WALL } else { 0 }),
+    ];
+
+    tiled::Map {
+        version: String::from("1.2"),
+        orientation: tiled::Orientation::Orthogonal,
+        width: rows.first().map(|row| row.as_ref().chars().count()).unwrap_or(0) as u32,
+        height: rows.len() as u32,
+        tile_width: 16,
+        tile_height: 16,
+        tilesets: vec![
+            tileset(1, "tiles", "tiles.png", 32),
+            tileset(TEST_WALL, COLLISION_TILESET, "collision.png", 16),
+        ],
+        layers,
+        image_layers: Vec::new(),
+        object_groups: Vec::new(),
+        properties: tiled::Properties::new(),
+        background_colour: None,
+    }
+}
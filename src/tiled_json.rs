@@ -0,0 +1,970 @@
+//! Loader for maps exported in Tiled's JSON format.
+//!
+//! The result is a `tiled::Map`, the same structure the TMX loader produces,
+//! so everything built on top of it does not care which format was used.
+//!
+//! `tiled::Map` has no notion of infinite maps, so each of their chunks is
+//! packed into one row of its layer, see `chunk::chunk_row`, and the map size
+//! covers the used area. The map is flagged with the `INFINITE_PROPERTY`
+//! property and the tiled coordinate of its top left tile is stored in
+//! `ORIGIN_X_PROPERTY` and `ORIGIN_Y_PROPERTY`. Tile animations
+//! are kept in `ANIMATION_PROPERTY`, read them with `tile_animation`.
+use std::collections::HashMap;
+use std::io::Read;
+
+use ggez::{GameError, GameResult};
+use serde_json::{self, Value};
+use tiled;
+
+use chunk;
+use tiled_tmx;
+
+pub const INFINITE_PROPERTY: &str = "infinite";
+pub const ORIGIN_X_PROPERTY: &str = "infinite_origin_x";
+pub const ORIGIN_Y_PROPERTY: &str = "infinite_origin_y";
+
+// stagger settings of staggered and hexagonal maps, also kept as properties
+pub const STAGGER_AXIS_PROPERTY: &str = "staggeraxis";
+pub const STAGGER_INDEX_PROPERTY: &str = "staggerindex";
+pub const HEX_SIDE_LENGTH_PROPERTY: &str = "hexsidelength";
+
+// image layer scroll rate relative to the camera, 1.0 moves with the map
+pub const PARALLAX_X_PROPERTY: &str = "parallaxx";
+pub const PARALLAX_Y_PROPERTY: &str = "parallaxy";
+// image layers drawn over the tile levels, set for image layers above a tile layer
+pub const FOREGROUND_PROPERTY: &str = "foreground";
+
+// tile animation frames as "tileid:duration,..." since tiled::Frame can not be built outside tiled
+pub const ANIMATION_PROPERTY: &str = "animation_frames";
+
+/// one frame of an animated tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileFrame {
+    /// tile id local to the tileset
+    pub tile_id: u32,
+    /// milliseconds
+    pub duration: u32,
+}
+
+pub(crate) fn animation_string(frames: &[TileFrame]) -> String {
+    frames
+        .iter()
+        .map(|f| format!("{}:{}", f.tile_id, f.duration))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_frames(frames: &str) -> Option<Vec<TileFrame>> {
+    frames
+        .split(',')
+        .map(|frame| {
+            let mut parts = frame.trim().split(':');
+            let tile_id = parts.next()?.parse().ok()?;
+            let duration = parts.next()?.parse().ok()?;
+            Some(TileFrame { tile_id, duration })
+        })
+        .collect()
+}
+
+/// animation frames of a tile from either loader, None if it is not animated
+pub fn tile_animation(tile: &tiled::Tile) -> Option<Vec<TileFrame>> {
+    match tile.properties.get(ANIMATION_PROPERTY) {
+        Some(&tiled::PropertyValue::StringValue(ref frames)) => parse_frames(frames),
+        _ => None,
+    }
+}
+
+// left, top, right, bottom in tiles, right and bottom exclusive
+type Bounds = (i32, i32, i32, i32);
+
+#[derive(Deserialize)]
+struct JsonMap {
+    #[serde(default)]
+    version: Value,
+    #[serde(default = "default_orientation")]
+    orientation: String,
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    staggeraxis: Option<String>,
+    #[serde(default)]
+    staggerindex: Option<String>,
+    #[serde(default)]
+    hexsidelength: Option<i32>,
+    #[serde(default)]
+    backgroundcolor: Option<String>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+    #[serde(default)]
+    tilesets: Vec<JsonTileset>,
+    #[serde(default)]
+    properties: Value,
+    #[serde(default)]
+    propertytypes: Value,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    layer_type: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32,
+    #[serde(default = "default_parallax")]
+    parallaxx: f32,
+    #[serde(default = "default_parallax")]
+    parallaxy: f32,
+    #[serde(default)]
+    properties: Value,
+    #[serde(default)]
+    propertytypes: Value,
+
+    // tile layers
+    #[serde(default)]
+    data: Value,
+    #[serde(default)]
+    encoding: Option<String>,
+    // infinite maps store tiles in chunks instead of data
+    #[serde(default)]
+    chunks: Vec<JsonChunk>,
+
+    // object layers
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    color: Option<String>,
+
+    // image layers
+    #[serde(default)]
+    image: Option<String>,
+
+    // group layers
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonChunk {
+    data: Value,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    id: u32,
+    #[serde(default)]
+    gid: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    obj_type: String,
+    #[serde(default)]
+    x: f32,
+    #[serde(default)]
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    polygon: Option<Vec<JsonPoint>>,
+    #[serde(default)]
+    polyline: Option<Vec<JsonPoint>>,
+    #[serde(default)]
+    properties: Value,
+    #[serde(default)]
+    propertytypes: Value,
+}
+
+#[derive(Deserialize)]
+struct JsonPoint {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    #[serde(default)]
+    firstgid: u32,
+    // set when the tileset lives in its own file
+    #[serde(default)]
+    source: Option<String>,
+
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    tilewidth: u32,
+    #[serde(default)]
+    tileheight: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: i32,
+    #[serde(default)]
+    imageheight: i32,
+    #[serde(default)]
+    transparentcolor: Option<String>,
+    // array of tiles, or an object keyed by tile id in older exports
+    #[serde(default)]
+    tiles: Value,
+    #[serde(default)]
+    tileproperties: Value,
+    #[serde(default)]
+    tilepropertytypes: Value,
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+    #[serde(default)]
+    id: u32,
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: i32,
+    #[serde(default)]
+    imageheight: i32,
+    #[serde(default)]
+    animation: Option<Vec<JsonFrame>>,
+    #[serde(default)]
+    objectgroup: Option<JsonLayer>,
+    #[serde(default)]
+    properties: Value,
+    #[serde(default)]
+    propertytypes: Value,
+}
+
+#[derive(Deserialize)]
+struct JsonFrame {
+    tileid: u32,
+    duration: u32,
+}
+
+fn default_orientation() -> String {
+    String::from("orthogonal")
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn default_parallax() -> f32 {
+    1.0
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn json_error(err: serde_json::Error) -> GameError {
+    GameError::from(format!("tiled json error: {}", err))
+}
+
+/// Parses a Tiled JSON map.
+///
+/// External tilesets are read through `load_source`, which is given the
+/// `source` attribute exactly as written in the map and returns the file
+/// contents. Both JSON (.json/.tsj) and TMX (.tsx) tilesets are supported.
+pub fn parse<R, F>(reader: R, mut load_source: F) -> GameResult<tiled::Map>
+where
+    R: Read,
+    F: FnMut(&str) -> GameResult<Vec<u8>>,
+{
+    let map: JsonMap = serde_json::from_reader(reader).map_err(json_error)?;
+
+    let orientation = match map.orientation.as_str() {
+        "orthogonal" => tiled::Orientation::Orthogonal,
+        "isometric" => tiled::Orientation::Isometric,
+        "staggered" => tiled::Orientation::Staggered,
+        "hexagonal" => tiled::Orientation::Hexagonal,
+        other => {
+            return Err(GameError::from(format!(
+                "unknown map orientation {}",
+                other
+            )))
+        }
+    };
+
+    let mut tilesets = Vec::new();
+    for tileset in map.tilesets.into_iter() {
+        let tileset = match tileset.source.clone() {
+            Some(source) => load_external_tileset(&source, tileset.firstgid, &mut load_source)?,
+            None => convert_tileset(tileset)?,
+        };
+        tilesets.push(tileset);
+    }
+
+    let mut properties = convert_properties(&map.properties, &map.propertytypes);
+    if let Some(ref axis) = map.staggeraxis {
+        properties.insert(
+            STAGGER_AXIS_PROPERTY.to_string(),
+            tiled::PropertyValue::StringValue(axis.clone()),
+        );
+    }
+    if let Some(ref index) = map.staggerindex {
+        properties.insert(
+            STAGGER_INDEX_PROPERTY.to_string(),
+            tiled::PropertyValue::StringValue(index.clone()),
+        );
+    }
+    if let Some(side_length) = map.hexsidelength {
+        properties.insert(
+            HEX_SIDE_LENGTH_PROPERTY.to_string(),
+            tiled::PropertyValue::IntValue(side_length),
+        );
+    }
+    let (mut width, mut height) = (map.width, map.height);
+    if map.infinite {
+        let mut bounds = None;
+        chunk_bounds(&map.layers, &mut bounds);
+        let bounds = bounds.unwrap_or((0, 0, 0, 0));
+        width = (bounds.2 - bounds.0) as u32;
+        height = (bounds.3 - bounds.1) as u32;
+        properties.insert(
+            INFINITE_PROPERTY.to_string(),
+            tiled::PropertyValue::BoolValue(true),
+        );
+        properties.insert(
+            ORIGIN_X_PROPERTY.to_string(),
+            tiled::PropertyValue::IntValue(bounds.0),
+        );
+        properties.insert(
+            ORIGIN_Y_PROPERTY.to_string(),
+            tiled::PropertyValue::IntValue(bounds.1),
+        );
+    }
+
+    let mut layers = Vec::new();
+    let mut image_layers = Vec::new();
+    let mut object_groups = Vec::new();
+    collect_layers(
+        map.layers,
+        map.infinite,
+        (1.0, 1.0),
+        &mut layers,
+        &mut image_layers,
+        &mut object_groups,
+    )?;
+
+    let version = match map.version {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+
+    Ok(tiled::Map {
+        version,
+        orientation,
+        width,
+        height,
+        tile_width: map.tilewidth,
+        tile_height: map.tileheight,
+        tilesets,
+        layers,
+        image_layers,
+        object_groups,
+        properties,
+        background_colour: map.backgroundcolor.as_ref().and_then(|c| parse_colour(c)),
+    })
+}
+
+/// Parses a standalone Tiled JSON tileset (.tsj)
+pub fn parse_tileset<R: Read>(reader: R, first_gid: u32) -> GameResult<tiled::Tileset> {
+    let mut tileset: JsonTileset = serde_json::from_reader(reader).map_err(json_error)?;
+    tileset.firstgid = first_gid;
+    convert_tileset(tileset)
+}
+
+fn load_external_tileset<F>(source: &str, first_gid: u32, load_source: &mut F) -> GameResult<tiled::Tileset>
+where
+    F: FnMut(&str) -> GameResult<Vec<u8>>,
+{
+    let bytes = load_source(source)?;
+    if source.ends_with(".tsx") {
+        tiled_tmx::parse_tileset(&bytes[..], first_gid)
+    } else {
+        parse_tileset(&bytes[..], first_gid)
+    }
+}
+
+// tiles covered by the chunks of every tile layer
+fn chunk_bounds(layers: &Vec<JsonLayer>, bounds: &mut Option<Bounds>) {
+    for layer in layers.iter() {
+        for chunk in layer.chunks.iter() {
+            let (l, t) = (chunk.x, chunk.y);
+            let (r, b) = (l + chunk.width as i32, t + chunk.height as i32);
+            *bounds = Some(match *bounds {
+                None => (l, t, r, b),
+                Some((bl, bt, br, bb)) => (bl.min(l), bt.min(t), br.max(r), bb.max(b)),
+            });
+        }
+        chunk_bounds(&layer.layers, bounds);
+    }
+}
+
+// flattens group layers into the lists tiled::Map keeps
+// parallax is the product of the parent groups
+fn collect_layers(
+    json_layers: Vec<JsonLayer>,
+    infinite: bool,
+    parallax: (f32, f32),
+    layers: &mut Vec<tiled::Layer>,
+    image_layers: &mut Vec<tiled::ImageLayer>,
+    object_groups: &mut Vec<tiled::ObjectGroup>,
+) -> GameResult<()> {
+    for layer in json_layers.into_iter() {
+        match layer.layer_type.as_str() {
+            "tilelayer" => layers.push(convert_tile_layer(layer, infinite)?),
+            "objectgroup" => object_groups.push(convert_object_group(layer)),
+            "imagelayer" => {
+                // layers are listed bottom up, so any tile layer so far is below this one
+                let foreground = !layers.is_empty();
+                image_layers.push(convert_image_layer(layer, parallax, foreground))
+            }
+            "group" => {
+                let parallax = (parallax.0 * layer.parallaxx, parallax.1 * layer.parallaxy);
+                collect_layers(layer.layers, infinite, parallax, layers, image_layers, object_groups)?
+            }
+            other => {
+                return Err(GameError::from(format!("unknown layer type {}", other)));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn convert_tile_layer(layer: JsonLayer, infinite: bool) -> GameResult<tiled::Layer> {
+    if let Some(ref encoding) = layer.encoding {
+        if encoding != "csv" {
+            return Err(GameError::from(format!(
+                "layer {} uses {} encoding, only csv is supported",
+                layer.name, encoding
+            )));
+        }
+    }
+    let tiles = if infinite {
+        // one row per chunk so far apart chunks do not fill in everything between them
+        let mut rows = Vec::new();
+        for c in layer.chunks.iter() {
+            let data = tile_data(&c.data, c.width, c.height, &layer.name)?;
+            rows.push(chunk::chunk_row(c.x, c.y, c.width, c.height, &data));
+        }
+        rows
+    } else {
+        if layer.width == 0 {
+            return Err(GameError::from(format!("layer {} has no width", layer.name)));
+        }
+        let data = tile_data(&layer.data, layer.width, layer.height, &layer.name)?;
+        data.chunks(layer.width as usize)
+            .map(|row| row.to_vec())
+            .collect()
+    };
+
+    Ok(tiled::Layer {
+        name: layer.name,
+        opacity: layer.opacity,
+        visible: layer.visible,
+        tiles,
+        properties: convert_properties(&layer.properties, &layer.propertytypes),
+    })
+}
+
+fn tile_data(data: &Value, width: u32, height: u32, layer: &str) -> GameResult<Vec<u32>> {
+    let expected = width
+        .checked_mul(height)
+        .ok_or_else(|| GameError::from(format!("layer {} is too large", layer)))?;
+    let gids: Vec<u32> = match *data {
+        Value::Array(ref gids) => gids
+            .iter()
+            .map(|gid| {
+                gid.as_u64()
+                    .map(|gid| gid as u32)
+                    .ok_or_else(|| GameError::from(format!("invalid gid {}", gid)))
+            })
+            .collect::<GameResult<Vec<u32>>>()?,
+        _ => return Err(GameError::from(format!("layer {} has no data", layer))),
+    };
+    if gids.len() != expected as usize {
+        return Err(GameError::from(format!(
+            "layer {} has {} tiles, expected {}",
+            layer,
+            gids.len(),
+            expected
+        )));
+    }
+    Ok(gids)
+}
+
+fn convert_object_group(layer: JsonLayer) -> tiled::ObjectGroup {
+    let objects = layer.objects.into_iter().map(convert_object).collect();
+    tiled::ObjectGroup {
+        name: layer.name,
+        opacity: layer.opacity,
+        visible: layer.visible,
+        objects,
+        colour: layer.color.as_ref().and_then(|c| parse_colour(c)),
+    }
+}
+
+fn convert_object(object: JsonObject) -> tiled::Object {
+    let points = |points: &Vec<JsonPoint>| points.iter().map(|p| (p.x, p.y)).collect();
+    let shape = if let Some(ref polygon) = object.polygon {
+        tiled::ObjectShape::Polygon {
+            points: points(polygon),
+        }
+    } else if let Some(ref polyline) = object.polyline {
+        tiled::ObjectShape::Polyline {
+            points: points(polyline),
+        }
+    } else if object.ellipse {
+        tiled::ObjectShape::Ellipse {
+            width: object.width,
+            height: object.height,
+        }
+    } else {
+        tiled::ObjectShape::Rect {
+            width: object.width,
+            height: object.height,
+        }
+    };
+
+    tiled::Object {
+        id: object.id,
+        gid: object.gid,
+        properties: convert_properties(&object.properties, &object.propertytypes),
+        name: object.name,
+        obj_type: object.obj_type,
+        x: object.x,
+        y: object.y,
+        rotation: object.rotation,
+        visible: object.visible,
+        shape,
+    }
+}
+
+fn convert_image_layer(layer: JsonLayer, parallax: (f32, f32), foreground: bool) -> tiled::ImageLayer {
+    let image = layer.image.as_ref().filter(|s| !s.is_empty()).map(|source| tiled::Image {
+        source: source.clone(),
+        width: 0,
+        height: 0,
+        transparent_colour: None,
+    });
+    let mut properties = convert_properties(&layer.properties, &layer.propertytypes);
+    let parallax = (parallax.0 * layer.parallaxx, parallax.1 * layer.parallaxy);
+    if parallax.0 != 1.0 {
+        properties.insert(
+            PARALLAX_X_PROPERTY.to_string(),
+            tiled::PropertyValue::FloatValue(parallax.0),
+        );
+    }
+    if parallax.1 != 1.0 {
+        properties.insert(
+            PARALLAX_Y_PROPERTY.to_string(),
+            tiled::PropertyValue::FloatValue(parallax.1),
+        );
+    }
+    properties
+        .entry(FOREGROUND_PROPERTY.to_string())
+        .or_insert(tiled::PropertyValue::BoolValue(foreground));
+    tiled::ImageLayer {
+        properties,
+        name: layer.name,
+        opacity: layer.opacity,
+        visible: layer.visible,
+        offset_x: layer.offsetx,
+        offset_y: layer.offsety,
+        image,
+    }
+}
+
+fn convert_tileset(tileset: JsonTileset) -> GameResult<tiled::Tileset> {
+    let mut images = Vec::new();
+    if let Some(ref source) = tileset.image {
+        images.push(tiled::Image {
+            source: source.clone(),
+            width: tileset.imagewidth,
+            height: tileset.imageheight,
+            transparent_colour: tileset.transparentcolor.as_ref().and_then(|c| parse_colour(c)),
+        });
+    }
+
+    // older exports key tiles by id and keep properties in a separate object
+    let mut json_tiles: Vec<JsonTile> = match tileset.tiles {
+        Value::Array(_) => serde_json::from_value(tileset.tiles.clone()).map_err(json_error)?,
+        Value::Object(ref by_id) => {
+            let mut tiles = Vec::new();
+            for (id, tile) in by_id.iter() {
+                let mut tile: JsonTile = serde_json::from_value(tile.clone()).map_err(json_error)?;
+                tile.id = id.parse().unwrap_or(0);
+                tiles.push(tile);
+            }
+            tiles
+        }
+        _ => Vec::new(),
+    };
+    if let Value::Object(ref by_id) = tileset.tileproperties {
+        for (id, props) in by_id.iter() {
+            let id: u32 = id.parse().unwrap_or(0);
+            if !json_tiles.iter().any(|t| t.id == id) {
+                json_tiles.push(JsonTile {
+                    id,
+                    image: None,
+                    imagewidth: 0,
+                    imageheight: 0,
+                    animation: None,
+                    objectgroup: None,
+                    properties: Value::Null,
+                    propertytypes: Value::Null,
+                });
+            }
+            let tile = json_tiles.iter_mut().find(|t| t.id == id).unwrap();
+            tile.properties = props.clone();
+            tile.propertytypes = tileset.tilepropertytypes[id.to_string()].clone();
+        }
+    }
+    json_tiles.sort_by_key(|t| t.id);
+
+    let tiles = json_tiles.into_iter().map(convert_tile).collect();
+
+    Ok(tiled::Tileset {
+        first_gid: tileset.firstgid,
+        name: tileset.name,
+        tile_width: tileset.tilewidth,
+        tile_height: tileset.tileheight,
+        spacing: tileset.spacing,
+        margin: tileset.margin,
+        images,
+        tiles,
+    })
+}
+
+fn convert_tile(tile: JsonTile) -> tiled::Tile {
+    let mut properties = convert_properties(&tile.properties, &tile.propertytypes);
+    if let Some(ref frames) = tile.animation {
+        let frames: Vec<TileFrame> = frames
+            .iter()
+            .map(|f| TileFrame {
+                tile_id: f.tileid,
+                duration: f.duration,
+            })
+            .collect();
+        properties.insert(
+            ANIMATION_PROPERTY.to_string(),
+            tiled::PropertyValue::StringValue(animation_string(&frames)),
+        );
+    }
+    let images = tile
+        .image
+        .as_ref()
+        .map(|source| {
+            vec![tiled::Image {
+                source: source.clone(),
+                width: tile.imagewidth,
+                height: tile.imageheight,
+                transparent_colour: None,
+            }]
+        })
+        .unwrap_or_default();
+
+    tiled::Tile {
+        id: tile.id,
+        flip_h: false,
+        flip_v: false,
+        images,
+        properties,
+        objectgroup: tile.objectgroup.map(convert_object_group),
+        // tiled::Frame can only be built by the tiled crate, see ANIMATION_PROPERTY
+        animation: None,
+    }
+}
+
+// properties are a list of {name, type, value}, or an object plus a
+// matching propertytypes object in older exports
+fn convert_properties(properties: &Value, types: &Value) -> tiled::Properties {
+    let mut result = HashMap::new();
+    match *properties {
+        Value::Array(ref list) => {
+            for property in list.iter() {
+                let name = match property["name"].as_str() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let kind = property["type"].as_str().unwrap_or("string");
+                if let Some(value) = property_value(kind, &property["value"]) {
+                    result.insert(name.to_string(), value);
+                }
+            }
+        }
+        Value::Object(ref by_name) => {
+            for (name, value) in by_name.iter() {
+                let kind = types[name.as_str()].as_str().unwrap_or_else(|| match *value {
+                    Value::Bool(_) => "bool",
+                    Value::Number(ref n) if n.is_f64() => "float",
+                    Value::Number(_) => "int",
+                    _ => "string",
+                });
+                if let Some(value) = property_value(kind, value) {
+                    result.insert(name.clone(), value);
+                }
+            }
+        }
+        _ => (),
+    }
+    result
+}
+
+fn property_value(kind: &str, value: &Value) -> Option<tiled::PropertyValue> {
+    match kind {
+        "bool" => value.as_bool().map(tiled::PropertyValue::BoolValue),
+        "float" => value
+            .as_f64()
+            .map(|v| tiled::PropertyValue::FloatValue(v as f32)),
+        "int" => value
+            .as_i64()
+            .map(|v| tiled::PropertyValue::IntValue(v as i32)),
+        "color" => value
+            .as_str()
+            .and_then(parse_argb)
+            .map(tiled::PropertyValue::ColorValue),
+        _ => match *value {
+            Value::String(ref s) => Some(tiled::PropertyValue::StringValue(s.clone())),
+            Value::Null => None,
+            ref other => Some(tiled::PropertyValue::StringValue(other.to_string())),
+        },
+    }
+}
+
+// #AARRGGBB or #RRGGBB as a packed argb value
+fn parse_argb(colour: &str) -> Option<u32> {
+    let hex = colour.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        6 => Some(0xFF00_0000 | value),
+        8 => Some(value),
+        _ => None,
+    }
+}
+
+fn parse_colour(colour: &str) -> Option<tiled::Colour> {
+    parse_argb(colour).map(|argb| tiled::Colour {
+        red: (argb >> 16) as u8,
+        green: (argb >> 8) as u8,
+        blue: argb as u8,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::path::Path;
+    use util;
+
+    #[test]
+    fn matches_the_tmx_loader() {
+        let file = File::open("resources/tiled/example_map.json").unwrap();
+        let json = parse(file, |_| unreachable!()).unwrap();
+        let tmx = tiled::parse_file(Path::new("resources/tiled/example_map.tmx")).unwrap();
+
+        assert_eq!(json.orientation, tmx.orientation);
+        assert_eq!((json.width, json.height), (tmx.width, tmx.height));
+        assert_eq!((json.tile_width, json.tile_height), (tmx.tile_width, tmx.tile_height));
+        assert_eq!(json.layers.len(), tmx.layers.len());
+        assert_eq!(json.layers[0].name, tmx.layers[0].name);
+        assert_eq!(json.layers[0].tiles, tmx.layers[0].tiles);
+
+        let (a, b) = (&json.tilesets[0], &tmx.tilesets[0]);
+        assert_eq!((a.first_gid, &a.name), (b.first_gid, &b.name));
+        assert_eq!((a.tile_width, a.tile_height), (b.tile_width, b.tile_height));
+        assert_eq!(
+            (a.images[0].width, a.images[0].height),
+            (b.images[0].width, b.images[0].height)
+        );
+    }
+
+    const EXTERNAL_MAP: &str = r#"{
+        "type": "map", "orientation": "orthogonal",
+        "width": 1, "height": 1, "tilewidth": 16, "tileheight": 16,
+        "tilesets": [
+            { "firstgid": 1, "source": "../tilesets/town.tsj" },
+            { "firstgid": 5, "source": "./water.tsx" }
+        ],
+        "layers": [
+            { "type": "tilelayer", "name": "base", "width": 1, "height": 1, "data": [5] }
+        ]
+    }"#;
+
+    const TOWN_TSJ: &str = r#"{
+        "name": "town", "tilewidth": 16, "tileheight": 16, "spacing": 0, "margin": 0,
+        "image": "town.png", "imagewidth": 32, "imageheight": 32,
+        "tiles": [
+            { "id": 1, "properties": [{ "name": "door", "type": "bool", "value": true }],
+              "animation": [{ "tileid": 1, "duration": 100 }, { "tileid": 2, "duration": 150 }] }
+        ]
+    }"#;
+
+    const WATER_TSX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset name="water" tilewidth="16" tileheight="16">
+ <image source="water.png" width="32" height="16"/>
+ <tile id="0">
+  <animation>
+   <frame tileid="0" duration="200"/>
+   <frame tileid="1" duration="250"/>
+  </animation>
+ </tile>
+</tileset>
+"#;
+
+    #[test]
+    fn external_tilesets() {
+        let mut requested = Vec::new();
+        let map = parse(EXTERNAL_MAP.as_bytes(), |source| {
+            let path = util::resolve_path("/maps/town.json", source);
+            requested.push(path.clone());
+            match path.as_str() {
+                "/tilesets/town.tsj" => Ok(TOWN_TSJ.as_bytes().to_vec()),
+                "/maps/water.tsx" => Ok(WATER_TSX.as_bytes().to_vec()),
+                _ => Err(GameError::from(format!("no file {}", path))),
+            }
+        })
+        .unwrap();
+        assert_eq!(requested, vec!["/tilesets/town.tsj", "/maps/water.tsx"]);
+
+        let town = &map.tilesets[0];
+        assert_eq!((town.first_gid, town.name.as_str()), (1, "town"));
+        assert_eq!(town.images[0].source, "town.png");
+        assert_eq!(
+            town.tiles[0].properties.get("door"),
+            Some(&tiled::PropertyValue::BoolValue(true))
+        );
+        assert_eq!(
+            tile_animation(&town.tiles[0]),
+            Some(vec![
+                TileFrame { tile_id: 1, duration: 100 },
+                TileFrame { tile_id: 2, duration: 150 },
+            ])
+        );
+
+        // frames of tsx tilesets are read from their xml
+        let water = &map.tilesets[1];
+        assert_eq!((water.first_gid, water.name.as_str()), (5, "water"));
+        assert_eq!(
+            tile_animation(&water.tiles[0]),
+            Some(vec![
+                TileFrame { tile_id: 0, duration: 200 },
+                TileFrame { tile_id: 1, duration: 250 },
+            ])
+        );
+        assert_eq!(map.get_tileset_by_gid(map.layers[0].tiles[0][0]).unwrap().name, "water");
+    }
+
+    const OBJECTS_MAP: &str = r##"{
+        "type": "map", "orientation": "orthogonal",
+        "width": 4, "height": 4, "tilewidth": 16, "tileheight": 16,
+        "properties": [
+            { "name": "dark", "type": "bool", "value": true },
+            { "name": "level", "type": "int", "value": 3 },
+            { "name": "speed", "type": "float", "value": 1.5 },
+            { "name": "tint", "type": "color", "value": "#80ff0000" },
+            { "name": "music", "type": "file", "value": "town.ogg" },
+            { "name": "title", "type": "string", "value": "Town" }
+        ],
+        "tilesets": [],
+        "layers": [
+            { "type": "objectgroup", "name": "things", "color": "#00ff00", "objects": [
+                { "id": 1, "name": "door", "x": 16, "y": 32, "width": 16, "height": 8 },
+                { "id": 2, "name": "pond", "x": 0, "y": 0, "width": 32, "height": 16, "ellipse": true },
+                { "id": 3, "name": "hill", "x": 8, "y": 8,
+                  "polygon": [{ "x": 0, "y": 0 }, { "x": 16, "y": 0 }, { "x": 8, "y": 8 }] },
+                { "id": 4, "name": "road", "x": 0, "y": 0, "rotation": 90, "visible": false,
+                  "polyline": [{ "x": 0, "y": 0 }, { "x": 32, "y": 16 }],
+                  "properties": { "paved": false, "lanes": 2 }, "propertytypes": { "lanes": "float" } }
+            ] }
+        ]
+    }"##;
+
+    const EMPTY_LAYER: &str = r#"{
+        "type": "map", "orientation": "orthogonal",
+        "width": 1, "height": 1, "tilewidth": 16, "tileheight": 16, "tilesets": [],
+        "layers": [
+            { "type": "tilelayer", "name": "base", "height": 1, "width": 0, "data": [] }
+        ]
+    }"#;
+
+    #[test]
+    fn bad_tile_layers() {
+        assert!(parse(EMPTY_LAYER.as_bytes(), |_| unreachable!()).is_err());
+        let short = EMPTY_LAYER.replace(r#""width": 0, "data": []"#, r#""width": 2, "data": [1]"#);
+        assert!(parse(short.as_bytes(), |_| unreachable!()).is_err());
+    }
+
+    #[test]
+    fn object_shapes_and_property_types() {
+        let map = parse(OBJECTS_MAP.as_bytes(), |_| unreachable!()).unwrap();
+        let property = |name: &str| map.properties.get(name).cloned();
+        assert_eq!(property("dark"), Some(tiled::PropertyValue::BoolValue(true)));
+        assert_eq!(property("level"), Some(tiled::PropertyValue::IntValue(3)));
+        assert_eq!(property("speed"), Some(tiled::PropertyValue::FloatValue(1.5)));
+        assert_eq!(property("tint"), Some(tiled::PropertyValue::ColorValue(0x80ff_0000)));
+        assert_eq!(
+            property("music"),
+            Some(tiled::PropertyValue::StringValue(String::from("town.ogg")))
+        );
+        assert_eq!(
+            property("title"),
+            Some(tiled::PropertyValue::StringValue(String::from("Town")))
+        );
+
+        let group = &map.object_groups[0];
+        assert_eq!(
+            group.colour,
+            Some(tiled::Colour { red: 0, green: 255, blue: 0 })
+        );
+        let objects = &group.objects;
+        assert_eq!(objects[0].shape, tiled::ObjectShape::Rect { width: 16.0, height: 8.0 });
+        assert_eq!((objects[0].x, objects[0].y), (16.0, 32.0));
+        assert_eq!(objects[1].shape, tiled::ObjectShape::Ellipse { width: 32.0, height: 16.0 });
+        assert_eq!(
+            objects[2].shape,
+            tiled::ObjectShape::Polygon { points: vec![(0.0, 0.0), (16.0, 0.0), (8.0, 8.0)] }
+        );
+        assert_eq!(
+            objects[3].shape,
+            tiled::ObjectShape::Polyline { points: vec![(0.0, 0.0), (32.0, 16.0)] }
+        );
+        assert_eq!((objects[3].rotation, objects[3].visible), (90.0, false));
+
+        // older exports keep properties in an object, typed by propertytypes
+        let road = &objects[3].properties;
+        assert_eq!(road.get("paved"), Some(&tiled::PropertyValue::BoolValue(false)));
+        assert_eq!(road.get("lanes"), Some(&tiled::PropertyValue::FloatValue(2.0)));
+    }
+}
@@ -0,0 +1,186 @@
+//! Loader for maps in Tiled's TMX format.
+//!
+//! The map is parsed by the tiled crate, which drops the stagger settings of
+//! staggered and hexagonal maps, the parallax of image layers and where image
+//! layers sit between the tile layers, and keeps tile animations where they
+//! can not be read. They are read in a second pass over the XML and kept in
+//! the same properties the JSON loader uses, so the rest of the crate sees
+//! them whichever format was loaded.
+use std::io::Read;
+
+use ggez::{GameError, GameResult};
+use tiled;
+use xml::reader::{EventReader, XmlEvent};
+
+use tiled_json::{self, TileFrame};
+
+pub fn parse<R: Read>(mut reader: R) -> GameResult<tiled::Map> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut map = tiled::parse(&bytes[..]).map_err(|e| GameError::from(format!("tiled error: {:?}", e)))?;
+
+    let (mut tile_layers, mut image_layers) = (0, 0);
+    for event in EventReader::new(&bytes[..]) {
+        let (name, attributes) = match event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => (name, attributes),
+            Ok(_) => continue,
+            Err(e) => return Err(GameError::from(format!("tmx error: {}", e))),
+        };
+        match name.local_name.as_str() {
+            "map" => for attribute in attributes.iter() {
+                read_map_attribute(&mut map, &attribute.name.local_name, &attribute.value)?;
+            },
+            "layer" => tile_layers += 1,
+            "imagelayer" => {
+                if let Some(layer) = map.image_layers.get_mut(image_layers) {
+                    for attribute in attributes.iter() {
+                        read_parallax(layer, &attribute.name.local_name, &attribute.value)?;
+                    }
+                    // as in the JSON loader, image layers above a tile layer are drawn over the map
+                    layer
+                        .properties
+                        .entry(tiled_json::FOREGROUND_PROPERTY.to_string())
+                        .or_insert(tiled::PropertyValue::BoolValue(tile_layers > 0));
+                }
+                image_layers += 1;
+            }
+            _ => (),
+        }
+    }
+    read_animations(&bytes, &mut map.tilesets)?;
+    Ok(map)
+}
+
+/// Parses a standalone TMX tileset (.tsx)
+pub fn parse_tileset<R: Read>(mut reader: R, first_gid: u32) -> GameResult<tiled::Tileset> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let tileset = tiled::parse_tileset(&bytes[..], first_gid)
+        .map_err(|e| GameError::from(format!("tiled error: {:?}", e)))?;
+    let mut tilesets = vec![tileset];
+    read_animations(&bytes, &mut tilesets)?;
+    Ok(tilesets.remove(0))
+}
+
+// frames of animated tiles, the tiled crate keeps them in tiled::Frame whose
+// fields are private, so they go in ANIMATION_PROPERTY as the JSON loader does
+fn read_animations(bytes: &[u8], tilesets: &mut [tiled::Tileset]) -> GameResult<()> {
+    let (mut tileset, mut tile) = (None, None);
+    let mut frames = Vec::new();
+    for event in EventReader::new(bytes) {
+        match event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                let number = |key: &str| -> Option<u32> {
+                    let attribute = attributes.iter().find(|a| a.name.local_name == key)?;
+                    attribute.value.parse().ok()
+                };
+                match name.local_name.as_str() {
+                    "tileset" => tileset = Some(tileset.map_or(0, |i| i + 1)),
+                    // tiles of xml encoded layer data have a gid rather than an id
+                    "tile" => tile = number("id"),
+                    "frame" => match (number("tileid"), number("duration")) {
+                        (Some(tile_id), Some(duration)) => frames.push(TileFrame { tile_id, duration }),
+                        _ => return Err(GameError::from(String::from("tmx frame without tileid or duration"))),
+                    },
+                    _ => (),
+                }
+            }
+            Ok(XmlEvent::EndElement { ref name }) if name.local_name == "animation" => {
+                let found = match (tileset, tile) {
+                    (Some(ts), Some(id)) => tilesets
+                        .get_mut(ts)
+                        .and_then(|ts| ts.tiles.iter_mut().find(|t| t.id == id)),
+                    _ => None,
+                };
+                if let Some(found) = found {
+                    found.properties.insert(
+                        tiled_json::ANIMATION_PROPERTY.to_string(),
+                        tiled::PropertyValue::StringValue(tiled_json::animation_string(&frames)),
+                    );
+                }
+                frames.clear();
+            }
+            Ok(_) => (),
+            Err(e) => return Err(GameError::from(format!("tmx error: {}", e))),
+        }
+    }
+    Ok(())
+}
+
+fn read_parallax(layer: &mut tiled::ImageLayer, name: &str, value: &str) -> GameResult<()> {
+    let key = match name {
+        "parallaxx" => tiled_json::PARALLAX_X_PROPERTY,
+        "parallaxy" => tiled_json::PARALLAX_Y_PROPERTY,
+        _ => return Ok(()),
+    };
+    let parallax: f32 = value
+        .parse()
+        .map_err(|_| GameError::from(format!("bad parallax {}", value)))?;
+    if parallax != 1.0 {
+        layer
+            .properties
+            .insert(key.to_string(), tiled::PropertyValue::FloatValue(parallax));
+    }
+    Ok(())
+}
+
+fn read_map_attribute(map: &mut tiled::Map, name: &str, value: &str) -> GameResult<()> {
+    let property = match name {
+        "staggeraxis" => match value {
+            "x" | "y" => tiled::PropertyValue::StringValue(value.to_string()),
+            _ => return Err(GameError::from(format!("unknown stagger axis {}", value))),
+        },
+        "staggerindex" => match value {
+            "odd" | "even" => tiled::PropertyValue::StringValue(value.to_string()),
+            _ => return Err(GameError::from(format!("unknown stagger index {}", value))),
+        },
+        "hexsidelength" => match value.parse() {
+            Ok(side) => tiled::PropertyValue::IntValue(side),
+            Err(_) => return Err(GameError::from(format!("bad hex side length {}", value))),
+        },
+        _ => return Ok(()),
+    };
+    let key = match name {
+        "staggeraxis" => tiled_json::STAGGER_AXIS_PROPERTY,
+        "staggerindex" => tiled_json::STAGGER_INDEX_PROPERTY,
+        _ => tiled_json::HEX_SIDE_LENGTH_PROPERTY,
+    };
+    map.properties.insert(key.to_string(), property);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orientation::{Orientation, StaggerAxis, StaggerIndex};
+    use tiled_writer;
+
+    const HEX_MAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="hexagonal" renderorder="right-down" width="2" height="2" tilewidth="32" tileheight="28" hexsidelength="14" staggeraxis="x" staggerindex="even" nextobjectid="1">
+ <layer name="base" width="2" height="2">
+  <data encoding="csv">
+0,0,
+0,0
+</data>
+ </layer>
+</map>
+"#;
+
+    #[test]
+    fn stagger_attributes() {
+        let map = parse(HEX_MAP.as_bytes()).unwrap();
+        let hexagonal = Orientation::Hexagonal {
+            axis: StaggerAxis::X,
+            index: StaggerIndex::Even,
+            side_length: 14.0,
+        };
+        assert_eq!(Orientation::from_tiled(&map), hexagonal);
+
+        let mut saved = Vec::new();
+        tiled_writer::write_tmx(&map, &mut saved).unwrap();
+        assert_eq!(Orientation::from_tiled(&parse(&saved[..]).unwrap()), hexagonal);
+
+        let bad = HEX_MAP.replace("staggeraxis=\"x\"", "staggeraxis=\"z\"");
+        assert!(parse(bad.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,798 @@
+//! Writers for Tiled's TMX and JSON formats.
+//!
+//! Both take a `tiled::Map`, usually from `Map::to_tiled`, so edited and
+//! generated maps can be opened in Tiled again. Tilesets are embedded in the
+//! map and tile data is written as csv. `tiled::Map` does not keep the
+//! original layer order, so background image layers are written first, then
+//! tile layers, foreground image layers and object layers.
+use std::io::Write;
+
+use ggez::GameResult;
+use serde_json::{self, Value};
+use tiled;
+
+use chunk;
+use map::{image_layer_is_foreground, image_layer_parallax};
+use tiled_json;
+
+// properties the loaders add to carry settings tiled::Map has no field for
+const INTERNAL_PROPERTIES: [&str; 7] = [
+    tiled_json::INFINITE_PROPERTY,
+    tiled_json::ORIGIN_X_PROPERTY,
+    tiled_json::ORIGIN_Y_PROPERTY,
+    tiled_json::STAGGER_AXIS_PROPERTY,
+    tiled_json::STAGGER_INDEX_PROPERTY,
+    tiled_json::HEX_SIDE_LENGTH_PROPERTY,
+    tiled_json::ANIMATION_PROPERTY,
+];
+
+// written when the map does not say which format version it came from
+const FORMAT_VERSION: &str = "1.2";
+
+fn version(map: &tiled::Map) -> &str {
+    if map.version.is_empty() {
+        FORMAT_VERSION
+    } else {
+        &map.version
+    }
+}
+
+// width and height of rect and ellipse objects, the other shapes have none
+fn object_size(object: &tiled::Object) -> Option<(f32, f32)> {
+    match object.shape {
+        tiled::ObjectShape::Rect { width, height } | tiled::ObjectShape::Ellipse { width, height } => {
+            Some((width, height))
+        }
+        _ => None,
+    }
+}
+
+fn orientation_name(orientation: tiled::Orientation) -> &'static str {
+    match orientation {
+        tiled::Orientation::Orthogonal => "orthogonal",
+        tiled::Orientation::Isometric => "isometric",
+        tiled::Orientation::Staggered => "staggered",
+        tiled::Orientation::Hexagonal => "hexagonal",
+    }
+}
+
+fn string_property(map: &tiled::Map, name: &str) -> Option<String> {
+    match map.properties.get(name) {
+        Some(&tiled::PropertyValue::StringValue(ref s)) => Some(s.clone()),
+        Some(&tiled::PropertyValue::IntValue(v)) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+// tiled coordinate of the top left tile when the map is infinite
+fn infinite_origin(map: &tiled::Map) -> Option<(i32, i32)> {
+    match map.properties.get(tiled_json::INFINITE_PROPERTY) {
+        Some(&tiled::PropertyValue::BoolValue(true)) => (),
+        _ => return None,
+    }
+    let origin = |name| match map.properties.get(name) {
+        Some(&tiled::PropertyValue::IntValue(v)) => v,
+        _ => 0,
+    };
+    Some((
+        origin(tiled_json::ORIGIN_X_PROPERTY),
+        origin(tiled_json::ORIGIN_Y_PROPERTY),
+    ))
+}
+
+fn argb_string(argb: u32) -> String {
+    format!("#{:08x}", argb)
+}
+
+fn colour_string(colour: &tiled::Colour) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour.red, colour.green, colour.blue)
+}
+
+fn property_type(value: &tiled::PropertyValue) -> (&'static str, String) {
+    match *value {
+        tiled::PropertyValue::BoolValue(v) => ("bool", v.to_string()),
+        tiled::PropertyValue::FloatValue(v) => ("float", v.to_string()),
+        tiled::PropertyValue::IntValue(v) => ("int", v.to_string()),
+        tiled::PropertyValue::ColorValue(v) => ("color", argb_string(v)),
+        tiled::PropertyValue::StringValue(ref v) => ("string", v.clone()),
+    }
+}
+
+// properties sorted by name so saved files diff cleanly
+fn sorted_properties<'a>(
+    properties: &'a tiled::Properties,
+    skip_internal: bool,
+) -> Vec<(&'a String, &'a tiled::PropertyValue)> {
+    let mut sorted: Vec<_> = properties
+        .iter()
+        .filter(|&(name, _)| !skip_internal || !INTERNAL_PROPERTIES.contains(&name.as_str()))
+        .collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+    sorted
+}
+
+// tmx
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn csv(tiles: &[u32], width: usize) -> String {
+    tiles
+        .chunks(width)
+        .map(|row| {
+            row.iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>()
+        .join(",\n")
+}
+
+fn write_tmx_properties<W: Write>(
+    w: &mut W,
+    properties: &tiled::Properties,
+    indent: &str,
+    skip_internal: bool,
+) -> GameResult<()> {
+    let sorted = sorted_properties(properties, skip_internal);
+    if sorted.is_empty() {
+        return Ok(());
+    }
+    writeln!(w, "{}<properties>", indent)?;
+    for (name, value) in sorted {
+        let (kind, value) = property_type(value);
+        writeln!(
+            w,
+            "{} <property name=\"{}\" type=\"{}\" value=\"{}\"/>",
+            indent,
+            escape(name),
+            kind,
+            escape(&value)
+        )?;
+    }
+    writeln!(w, "{}</properties>", indent)?;
+    Ok(())
+}
+
+fn write_tmx_image<W: Write>(w: &mut W, image: &tiled::Image, indent: &str) -> GameResult<()> {
+    let trans = match image.transparent_colour {
+        Some(ref c) => format!(" trans=\"{}\"", &colour_string(c)[1..]),
+        None => String::new(),
+    };
+    writeln!(
+        w,
+        "{}<image source=\"{}\"{} width=\"{}\" height=\"{}\"/>",
+        indent,
+        escape(&image.source),
+        trans,
+        image.width,
+        image.height
+    )?;
+    Ok(())
+}
+
+fn write_tmx_objects<W: Write>(w: &mut W, group: &tiled::ObjectGroup, indent: &str) -> GameResult<()> {
+    let colour = match group.colour {
+        Some(ref c) => format!(" color=\"{}\"", colour_string(c)),
+        None => String::new(),
+    };
+    writeln!(
+        w,
+        "{}<objectgroup name=\"{}\"{} opacity=\"{}\" visible=\"{}\">",
+        indent,
+        escape(&group.name),
+        colour,
+        group.opacity,
+        group.visible as u8
+    )?;
+    for object in group.objects.iter() {
+        let gid = if object.gid != 0 {
+            format!(" gid=\"{}\"", object.gid)
+        } else {
+            String::new()
+        };
+        let size = match object_size(object) {
+            Some((width, height)) => format!(" width=\"{}\" height=\"{}\"", width, height),
+            None => String::new(),
+        };
+        writeln!(
+            w,
+            "{} <object id=\"{}\"{} name=\"{}\" type=\"{}\" x=\"{}\" y=\"{}\"{} rotation=\"{}\" visible=\"{}\">",
+            indent,
+            object.id,
+            gid,
+            escape(&object.name),
+            escape(&object.obj_type),
+            object.x,
+            object.y,
+            size,
+            object.rotation,
+            object.visible as u8
+        )?;
+        let points = |points: &Vec<(f32, f32)>| {
+            points
+                .iter()
+                .map(|&(x, y)| format!("{},{}", x, y))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        match object.shape {
+            tiled::ObjectShape::Rect { .. } => (),
+            tiled::ObjectShape::Ellipse { .. } => writeln!(w, "{}  <ellipse/>", indent)?,
+            tiled::ObjectShape::Polygon { points: ref p } => {
+                writeln!(w, "{}  <polygon points=\"{}\"/>", indent, points(p))?
+            }
+            tiled::ObjectShape::Polyline { points: ref p } => {
+                writeln!(w, "{}  <polyline points=\"{}\"/>", indent, points(p))?
+            }
+        }
+        write_tmx_properties(w, &object.properties, &format!("{}  ", indent), false)?;
+        writeln!(w, "{} </object>", indent)?;
+    }
+    writeln!(w, "{}</objectgroup>", indent)?;
+    Ok(())
+}
+
+fn write_tmx_image_layer<W: Write>(w: &mut W, layer: &tiled::ImageLayer) -> GameResult<()> {
+    let parallax = image_layer_parallax(layer);
+    writeln!(
+        w,
+        " <imagelayer name=\"{}\" offsetx=\"{}\" offsety=\"{}\" parallaxx=\"{}\" parallaxy=\"{}\" opacity=\"{}\" visible=\"{}\">",
+        escape(&layer.name),
+        layer.offset_x,
+        layer.offset_y,
+        parallax.x,
+        parallax.y,
+        layer.opacity,
+        layer.visible as u8
+    )?;
+    write_tmx_properties(w, &image_layer_properties(layer), "  ", false)?;
+    if let Some(ref image) = layer.image {
+        write_tmx_image(w, image, "  ")?;
+    }
+    writeln!(w, " </imagelayer>")?;
+    Ok(())
+}
+
+/// Writes a map in Tiled's TMX format
+pub fn write_tmx<W: Write>(map: &tiled::Map, mut w: W) -> GameResult<()> {
+    let w = &mut w;
+    let origin = infinite_origin(map);
+
+    let mut stagger = String::new();
+    if let Some(axis) = string_property(map, tiled_json::STAGGER_AXIS_PROPERTY) {
+        stagger.push_str(&format!(" staggeraxis=\"{}\"", axis));
+    }
+    if let Some(index) = string_property(map, tiled_json::STAGGER_INDEX_PROPERTY) {
+        stagger.push_str(&format!(" staggerindex=\"{}\"", index));
+    }
+    if let Some(side) = string_property(map, tiled_json::HEX_SIDE_LENGTH_PROPERTY) {
+        stagger.push_str(&format!(" hexsidelength=\"{}\"", side));
+    }
+    let background = match map.background_colour {
+        Some(ref c) => format!(" backgroundcolor=\"{}\"", colour_string(c)),
+        None => String::new(),
+    };
+
+    writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        w,
+        "<map version=\"{}\" orientation=\"{}\" renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" infinite=\"{}\"{}{}>",
+        version(map),
+        orientation_name(map.orientation),
+        map.width,
+        map.height,
+        map.tile_width,
+        map.tile_height,
+        origin.is_some() as u8,
+        stagger,
+        background
+    )?;
+    write_tmx_properties(w, &map.properties, " ", true)?;
+
+    for tileset in map.tilesets.iter() {
+        writeln!(
+            w,
+            " <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" spacing=\"{}\" margin=\"{}\">",
+            tileset.first_gid,
+            escape(&tileset.name),
+            tileset.tile_width,
+            tileset.tile_height,
+            tileset.spacing,
+            tileset.margin
+        )?;
+        for image in tileset.images.iter() {
+            write_tmx_image(w, image, "  ")?;
+        }
+        for tile in tileset.tiles.iter() {
+            writeln!(w, "  <tile id=\"{}\">", tile.id)?;
+            write_tmx_properties(w, &tile.properties, "   ", true)?;
+            for image in tile.images.iter() {
+                write_tmx_image(w, image, "   ")?;
+            }
+            if let Some(ref group) = tile.objectgroup {
+                write_tmx_objects(w, group, "   ")?;
+            }
+            if let Some(frames) = tiled_json::tile_animation(tile) {
+                writeln!(w, "   <animation>")?;
+                for frame in frames.iter() {
+                    writeln!(
+                        w,
+                        "    <frame tileid=\"{}\" duration=\"{}\"/>",
+                        frame.tile_id, frame.duration
+                    )?;
+                }
+                writeln!(w, "   </animation>")?;
+            }
+            writeln!(w, "  </tile>")?;
+        }
+        writeln!(w, " </tileset>")?;
+    }
+
+    for layer in map.image_layers.iter().filter(|l| !image_layer_is_foreground(l)) {
+        write_tmx_image_layer(w, layer)?;
+    }
+
+    for layer in map.layers.iter() {
+        writeln!(
+            w,
+            " <layer name=\"{}\" width=\"{}\" height=\"{}\" opacity=\"{}\" visible=\"{}\">",
+            escape(&layer.name),
+            map.width,
+            map.height,
+            layer.opacity,
+            layer.visible as u8
+        )?;
+        write_tmx_properties(w, &layer.properties, "  ", false)?;
+        writeln!(w, "  <data encoding=\"csv\">")?;
+        match origin {
+            Some(_) => {
+                for (x, y, width, height, tiles) in chunk::chunk_rows(layer) {
+                    writeln!(
+                        w,
+                        "   <chunk x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\">",
+                        x, y, width, height
+                    )?;
+                    writeln!(w, "{}", csv(tiles, width as usize))?;
+                    writeln!(w, "   </chunk>")?;
+                }
+            }
+            None => {
+                let tiles: Vec<u32> = layer.tiles.iter().flat_map(|row| row.iter().cloned()).collect();
+                writeln!(w, "{}", csv(&tiles, map.width as usize))?;
+            }
+        }
+        writeln!(w, "  </data>")?;
+        writeln!(w, " </layer>")?;
+    }
+
+    for layer in map.image_layers.iter().filter(|l| image_layer_is_foreground(l)) {
+        write_tmx_image_layer(w, layer)?;
+    }
+
+    for group in map.object_groups.iter() {
+        write_tmx_objects(w, group, " ")?;
+    }
+
+    writeln!(w, "</map>")?;
+    Ok(())
+}
+
+// json
+
+fn json_properties(properties: &tiled::Properties, skip_internal: bool) -> Value {
+    let list = sorted_properties(properties, skip_internal)
+        .into_iter()
+        .map(|(name, value)| {
+            let (kind, _) = property_type(value);
+            let value = match *value {
+                tiled::PropertyValue::BoolValue(v) => json!(v),
+                tiled::PropertyValue::FloatValue(v) => json!(v),
+                tiled::PropertyValue::IntValue(v) => json!(v),
+                tiled::PropertyValue::ColorValue(v) => json!(argb_string(v)),
+                tiled::PropertyValue::StringValue(ref v) => json!(v),
+            };
+            json!({ "name": name, "type": kind, "value": value })
+        })
+        .collect();
+    Value::Array(list)
+}
+
+fn json_objects(group: &tiled::ObjectGroup) -> Value {
+    let objects: Vec<Value> = group
+        .objects
+        .iter()
+        .map(|object| {
+            let mut value = json!({
+                "id": object.id,
+                "name": object.name,
+                "type": object.obj_type,
+                "x": object.x,
+                "y": object.y,
+                "rotation": object.rotation,
+                "visible": object.visible,
+                "properties": json_properties(&object.properties, false),
+            });
+            if object.gid != 0 {
+                value["gid"] = json!(object.gid);
+            }
+            if let Some((width, height)) = object_size(object) {
+                value["width"] = json!(width);
+                value["height"] = json!(height);
+            }
+            let points = |points: &Vec<(f32, f32)>| {
+                Value::Array(points.iter().map(|&(x, y)| json!({ "x": x, "y": y })).collect())
+            };
+            match object.shape {
+                tiled::ObjectShape::Rect { .. } => (),
+                tiled::ObjectShape::Ellipse { .. } => value["ellipse"] = json!(true),
+                tiled::ObjectShape::Polygon { points: ref p } => value["polygon"] = points(p),
+                tiled::ObjectShape::Polyline { points: ref p } => value["polyline"] = points(p),
+            }
+            value
+        })
+        .collect();
+
+    let mut value = json!({
+        "type": "objectgroup",
+        "name": group.name,
+        "opacity": group.opacity,
+        "visible": group.visible,
+        "x": 0,
+        "y": 0,
+        "draworder": "topdown",
+        "objects": objects,
+    });
+    if let Some(ref c) = group.colour {
+        value["color"] = json!(colour_string(c));
+    }
+    value
+}
+
+fn json_tileset(tileset: &tiled::Tileset) -> Value {
+    let mut value = json!({
+        "firstgid": tileset.first_gid,
+        "name": tileset.name,
+        "tilewidth": tileset.tile_width,
+        "tileheight": tileset.tile_height,
+        "spacing": tileset.spacing,
+        "margin": tileset.margin,
+    });
+    if let Some(image) = tileset.images.first() {
+        value["image"] = json!(image.source);
+        value["imagewidth"] = json!(image.width);
+        value["imageheight"] = json!(image.height);
+        if tileset.tile_width > 0 && tileset.tile_height > 0 {
+            let columns = image.width as u32 / tileset.tile_width;
+            let rows = image.height as u32 / tileset.tile_height;
+            value["columns"] = json!(columns);
+            value["tilecount"] = json!(columns * rows);
+        }
+        if let Some(ref c) = image.transparent_colour {
+            value["transparentcolor"] = json!(colour_string(c));
+        }
+    }
+    let tiles: Vec<Value> = tileset
+        .tiles
+        .iter()
+        .map(|tile| {
+            let mut value = json!({
+                "id": tile.id,
+                "properties": json_properties(&tile.properties, true),
+            });
+            if let Some(image) = tile.images.first() {
+                value["image"] = json!(image.source);
+                value["imagewidth"] = json!(image.width);
+                value["imageheight"] = json!(image.height);
+            }
+            if let Some(ref group) = tile.objectgroup {
+                value["objectgroup"] = json_objects(group);
+            }
+            if let Some(frames) = tiled_json::tile_animation(tile) {
+                value["animation"] = Value::Array(
+                    frames
+                        .iter()
+                        .map(|f| json!({ "tileid": f.tile_id, "duration": f.duration }))
+                        .collect(),
+                );
+            }
+            value
+        })
+        .collect();
+    if !tiles.is_empty() {
+        value["tiles"] = Value::Array(tiles);
+    }
+    value
+}
+
+// properties of an image layer without the ones written as attributes
+// or, for foreground, by where the layer is placed
+fn image_layer_properties(layer: &tiled::ImageLayer) -> tiled::Properties {
+    let mut properties = layer.properties.clone();
+    properties.remove(tiled_json::PARALLAX_X_PROPERTY);
+    properties.remove(tiled_json::PARALLAX_Y_PROPERTY);
+    properties.remove(tiled_json::FOREGROUND_PROPERTY);
+    properties
+}
+
+fn json_image_layer(layer: &tiled::ImageLayer) -> Value {
+    let image = layer
+        .image
+        .as_ref()
+        .map(|image| image.source.clone())
+        .unwrap_or_default();
+    let parallax = image_layer_parallax(layer);
+    let properties = image_layer_properties(layer);
+    json!({
+        "type": "imagelayer",
+        "name": layer.name,
+        "image": image,
+        "offsetx": layer.offset_x,
+        "offsety": layer.offset_y,
+        "parallaxx": parallax.x,
+        "parallaxy": parallax.y,
+        "opacity": layer.opacity,
+        "visible": layer.visible,
+        "properties": json_properties(&properties, false),
+        "x": 0,
+        "y": 0,
+    })
+}
+
+/// Writes a map in Tiled's JSON format
+pub fn write_json<W: Write>(map: &tiled::Map, w: W) -> GameResult<()> {
+    let origin = infinite_origin(map);
+
+    let mut layers: Vec<Value> = map
+        .image_layers
+        .iter()
+        .filter(|l| !image_layer_is_foreground(l))
+        .map(json_image_layer)
+        .collect();
+    for layer in map.layers.iter() {
+        let mut value = json!({
+            "type": "tilelayer",
+            "name": layer.name,
+            "opacity": layer.opacity,
+            "visible": layer.visible,
+            "properties": json_properties(&layer.properties, false),
+            "x": 0,
+            "y": 0,
+        });
+        match origin {
+            Some(origin) => {
+                let chunks: Vec<Value> = chunk::chunk_rows(layer)
+                    .into_iter()
+                    .map(|(x, y, width, height, tiles)| {
+                        json!({ "x": x, "y": y, "width": width, "height": height, "data": tiles })
+                    })
+                    .collect();
+                value["chunks"] = Value::Array(chunks);
+                value["startx"] = json!(origin.0);
+                value["starty"] = json!(origin.1);
+            }
+            None => {
+                let tiles: Vec<u32> = layer.tiles.iter().flat_map(|row| row.iter().cloned()).collect();
+                value["data"] = json!(tiles);
+            }
+        }
+        value["width"] = json!(map.width);
+        value["height"] = json!(map.height);
+        layers.push(value);
+    }
+    for layer in map.image_layers.iter().filter(|l| image_layer_is_foreground(l)) {
+        layers.push(json_image_layer(layer));
+    }
+    for group in map.object_groups.iter() {
+        layers.push(json_objects(group));
+    }
+
+    let next_object_id = map
+        .object_groups
+        .iter()
+        .flat_map(|g| g.objects.iter())
+        .map(|o| o.id + 1)
+        .max()
+        .unwrap_or(1);
+
+    let mut value = json!({
+        "type": "map",
+        "version": version(map),
+        "orientation": orientation_name(map.orientation),
+        "renderorder": "right-down",
+        "width": map.width,
+        "height": map.height,
+        "tilewidth": map.tile_width,
+        "tileheight": map.tile_height,
+        "infinite": origin.is_some(),
+        "nextobjectid": next_object_id,
+        "properties": json_properties(&map.properties, true),
+        "tilesets": map.tilesets.iter().map(json_tileset).collect::<Vec<_>>(),
+        "layers": layers,
+    });
+    if let Some(axis) = string_property(map, tiled_json::STAGGER_AXIS_PROPERTY) {
+        value["staggeraxis"] = json!(axis);
+    }
+    if let Some(index) = string_property(map, tiled_json::STAGGER_INDEX_PROPERTY) {
+        value["staggerindex"] = json!(index);
+    }
+    if let Some(&tiled::PropertyValue::IntValue(side)) =
+        map.properties.get(tiled_json::HEX_SIDE_LENGTH_PROPERTY)
+    {
+        value["hexsidelength"] = json!(side);
+    }
+    if let Some(ref c) = map.background_colour {
+        value["backgroundcolor"] = json!(colour_string(c));
+    }
+
+    serde_json::to_writer_pretty(w, &value)
+        .map_err(|e| ::ggez::GameError::from(format!("tiled json error: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support;
+    use tiled_tmx;
+
+    // the shared test map with what the writers carry beyond tiles:
+    // map and tile properties, an animated tile, image layers above and
+    // below the tiles and objects of every shape
+    fn test_map() -> tiled::Map {
+        let mut map = test_support::test_map(&["12", "04"]);
+        map.properties.insert(
+            String::from("music"),
+            tiled::PropertyValue::StringValue(String::from("town.ogg")),
+        );
+
+        let mut properties = tiled::Properties::new();
+        properties.insert(String::from("water"), tiled::PropertyValue::BoolValue(true));
+        properties.insert(
+            tiled_json::ANIMATION_PROPERTY.to_string(),
+            tiled::PropertyValue::StringValue(String::from("1:100,3:100")),
+        );
+        map.tilesets[0].tiles.push(tiled::Tile {
+            id: 1,
+            flip_h: false,
+            flip_v: false,
+            images: Vec::new(),
+            properties,
+            objectgroup: None,
+            animation: None,
+        });
+
+        let image_layer = |name: &str, parallax: (&str, f32), foreground: bool| {
+            let mut properties = tiled::Properties::new();
+            properties.insert(parallax.0.to_string(), tiled::PropertyValue::FloatValue(parallax.1));
+            properties.insert(
+                tiled_json::FOREGROUND_PROPERTY.to_string(),
+                tiled::PropertyValue::BoolValue(foreground),
+            );
+            tiled::ImageLayer {
+                name: name.to_string(),
+                opacity: 1.0,
+                visible: true,
+                offset_x: 0.0,
+                offset_y: 0.0,
+                image: Some(tiled::Image {
+                    source: format!("{}.png", name),
+                    width: 0,
+                    height: 0,
+                    transparent_colour: None,
+                }),
+                properties,
+            }
+        };
+        map.image_layers = vec![
+            image_layer("sky", (tiled_json::PARALLAX_X_PROPERTY, 0.5), false),
+            image_layer("clouds", (tiled_json::PARALLAX_Y_PROPERTY, 2.0), true),
+        ];
+
+        let object = |id: u32, name: &str, obj_type: &str, x: f32, y: f32, shape: tiled::ObjectShape| {
+            tiled::Object {
+                id,
+                gid: 0,
+                name: name.to_string(),
+                obj_type: obj_type.to_string(),
+                x,
+                y,
+                rotation: 0.0,
+                visible: true,
+                shape,
+                properties: tiled::Properties::new(),
+            }
+        };
+        let rect = tiled::ObjectShape::Rect {
+            width: 16.0,
+            height: 16.0,
+        };
+        let ellipse = tiled::ObjectShape::Ellipse {
+            width: 8.0,
+            height: 4.0,
+        };
+        let polyline = tiled::ObjectShape::Polyline {
+            points: vec![(0.0, 0.0), (8.0, 8.0)],
+        };
+        map.object_groups.push(tiled::ObjectGroup {
+            name: String::from("triggers"),
+            opacity: 1.0,
+            visible: true,
+            objects: vec![
+                object(1, "door", "exit", 16.0, 0.0, rect),
+                object(2, "pond", "", 0.0, 16.0, ellipse),
+                object(3, "path", "", 0.0, 0.0, polyline),
+            ],
+            colour: None,
+        });
+        map
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let map = test_map();
+        let mut saved = Vec::new();
+        write_json(&map, &mut saved).unwrap();
+        let loaded = tiled_json::parse(&saved[..], |_| unreachable!()).unwrap();
+
+        assert_eq!(loaded.layers[0].tiles, vec![vec![1, 2], vec![0, 4]]);
+        assert_eq!(loaded.properties, map.properties);
+        assert_eq!(loaded.tilesets[0].images[0].source, "tiles.png");
+        assert_eq!(loaded.object_groups[0].objects[0].name, "door");
+
+        let sky = &loaded.image_layers[0];
+        assert_eq!(image_layer_parallax(sky).x, 0.5);
+        assert_eq!(image_layer_parallax(sky).y, 1.0);
+        assert!(!image_layer_is_foreground(sky));
+        assert!(image_layer_is_foreground(&loaded.image_layers[1]));
+        assert_eq!(loaded.image_layers[1].properties, map.image_layers[1].properties);
+        // foreground is carried by where the layer is written, not as a property
+        assert!(!String::from_utf8(saved).unwrap().contains(tiled_json::FOREGROUND_PROPERTY));
+    }
+
+    #[test]
+    fn tmx_round_trip() {
+        let map = test_map();
+        let mut saved = Vec::new();
+        write_tmx(&map, &mut saved).unwrap();
+        let loaded = tiled_tmx::parse(&saved[..]).unwrap();
+
+        assert_eq!(loaded.version, "1.2");
+        assert_eq!((loaded.width, loaded.height), (2, 2));
+        assert_eq!(loaded.layers[0].tiles, vec![vec![1, 2], vec![0, 4]]);
+        assert_eq!(loaded.properties, map.properties);
+
+        let tileset = &loaded.tilesets[0];
+        assert_eq!(tileset.images[0].source, "tiles.png");
+        assert_eq!(tileset.tiles[0].properties, map.tilesets[0].tiles[0].properties);
+        assert_eq!(
+            tiled_json::tile_animation(&tileset.tiles[0]),
+            tiled_json::tile_animation(&map.tilesets[0].tiles[0])
+        );
+        assert!(tiled_json::tile_animation(&tileset.tiles[0]).is_some());
+
+        let objects = &loaded.object_groups[0].objects;
+        assert_eq!(objects, &map.object_groups[0].objects);
+        assert_eq!(objects[1].shape, tiled::ObjectShape::Ellipse { width: 8.0, height: 4.0 });
+
+        // parallax and foreground are written as tiled does and read back into properties
+        assert_eq!(loaded.image_layers[0].properties, map.image_layers[0].properties);
+        assert_eq!(loaded.image_layers[1].properties, map.image_layers[1].properties);
+        assert_eq!(image_layer_parallax(&loaded.image_layers[0]).x, 0.5);
+        assert!(image_layer_is_foreground(&loaded.image_layers[1]));
+        let text = String::from_utf8(saved).unwrap();
+        assert!(text.contains("parallaxy=\"2\""));
+        assert!(!text.contains(tiled_json::FOREGROUND_PROPERTY));
+
+        // maps that do not say which version they are get the current one
+        let mut unversioned = map.clone();
+        unversioned.version = String::new();
+        let mut saved = Vec::new();
+        write_tmx(&unversioned, &mut saved).unwrap();
+        assert_eq!(tiled::parse(&saved[..]).unwrap().version, FORMAT_VERSION);
+    }
+}
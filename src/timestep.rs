@@ -0,0 +1,103 @@
+//! Fixed timestep with render interpolation.
+//!
+//! Game logic runs at a fixed rate while drawing happens as often as the
+//! display allows. Each frame the elapsed time is added, `tick` is called
+//! until it returns false, and what is left over is exposed as `alpha` so
+//! drawing can lerp between the last two logic states:
+//!
+//! ```ignore
+//! timestep.advance(timer::duration_to_f64(timer::get_delta(ctx)) as f32);
+//! while timestep.tick() {
+//!     map.store_previous_positions();
+//!     // fixed update
+//! }
+//! graphics::draw(ctx, &map.entity_layer(layer, timestep.alpha()), origin, 0.0)?;
+//! ```
+use std::time::Duration;
+
+// frames of backlog kept before time is dropped, so a stall does not spiral
+const MAX_STEPS: u32 = 5;
+
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    step: f32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    /// steps of 1 / updates_per_second seconds
+    pub fn new(updates_per_second: u32) -> Self {
+        FixedTimestep {
+            step: 1.0 / updates_per_second.max(1) as f32,
+            accumulator: 0.0,
+        }
+    }
+
+    /// seconds per step, the dt of every fixed update
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    /// adds dt seconds of real time
+    pub fn advance(&mut self, dt: f32) {
+        let limit = self.step * MAX_STEPS as f32;
+        self.accumulator = (self.accumulator + dt.max(0.0)).min(limit);
+    }
+
+    pub fn advance_duration(&mut self, duration: Duration) {
+        let dt = duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9;
+        self.advance(dt);
+    }
+
+    /// consumes a step if enough time has built up, true when an update should run
+    pub fn tick(&mut self) -> bool {
+        if self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// how far the current time is into the next step, 0 to 1
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::Entity;
+    use ggez::graphics::Point2;
+
+    #[test]
+    fn steps_and_interpolation() {
+        let mut timestep = FixedTimestep::new(10);
+        let mut e = Entity::new(String::from("hero"), Point2::new(16.0, 16.0));
+        e.warp_pos(Point2::new(0.0, 0.0));
+
+        timestep.advance(0.25);
+        let mut steps = 0;
+        while timestep.tick() {
+            e.store_previous_pos();
+            let pos = e.pos();
+            e.set_pos(Point2::new(pos.x + 4.0, pos.y));
+            steps += 1;
+        }
+        assert_eq!(steps, 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-4);
+        assert_eq!(e.previous_pos(), Point2::new(4.0, 0.0));
+        let drawn = e.interpolated_pos(timestep.alpha());
+        assert!((drawn.x - 6.0).abs() < 1e-3);
+        assert!((e.interpolated_top_left(1.0).x - 0.0).abs() < 1e-3);
+
+        // a long stall only runs a bounded number of updates
+        timestep.advance(10.0);
+        let mut steps = 0;
+        while timestep.tick() {
+            steps += 1;
+        }
+        assert!(steps > 0 && steps <= MAX_STEPS);
+    }
+}
@@ -1,8 +1,13 @@
 use ggez::{
-    graphics::{Point2, Rect}, Context, GameError, GameResult,
+    graphics::{Point2, Rect}, Context, GameResult,
 };
+use std::io::Read;
 use tiled;
 
+use tiled_json;
+use tiled_tmx;
+use tiled_writer;
+
 /// Math functions
 
 pub fn add_points(args: &[&Point2]) -> Point2 {
@@ -13,6 +18,21 @@ pub fn add_points(args: &[&Point2]) -> Point2 {
     })
 }
 
+/// point rounded to whole pixels so sprites and tiles do not shimmer
+pub fn snap(p: Point2) -> Point2 {
+    Point2::new(p.x.round(), p.y.round())
+}
+
+/// largest whole number scale showing a virtual_width by virtual_height screen in the window, at least 1
+pub fn integer_scale(window_width: f32, window_height: f32, virtual_width: f32, virtual_height: f32) -> f32 {
+    let scale = (window_width / virtual_width).min(window_height / virtual_height).floor();
+    if scale >= 1.0 {
+        scale
+    } else {
+        1.0
+    }
+}
+
 #[inline]
 pub fn clamp<T: PartialOrd>(input: T, min: T, max: T) -> T {
     debug_assert!(min <= max, "min must be less than or equal to max");
@@ -25,12 +45,63 @@ pub fn clamp<T: PartialOrd>(input: T, min: T, max: T) -> T {
     }
 }
 
+/// Loads a tiled map, maps ending in .json or .tmj are read as Tiled JSON
+/// everything else as TMX
 pub fn load_tile_map(ctx: &mut Context, tilemap_src: &str) -> GameResult<tiled::Map> {
     let tilemap_file = ctx.filesystem.open(tilemap_src)?;
-    match tiled::parse(tilemap_file) {
-        Ok(map) => Ok(map),
-        Err(_) => Err(GameError::from(String::from("tiled error"))),
+    if tilemap_src.ends_with(".json") || tilemap_src.ends_with(".tmj") {
+        return tiled_json::parse(tilemap_file, |source| {
+            let mut bytes = Vec::new();
+            let mut file = ctx.filesystem.open(resolve_path(tilemap_src, source))?;
+            file.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        });
     }
+    tiled_tmx::parse(tilemap_file)
+}
+
+/// Saves a tiled map, using the same extensions as load_tile_map to pick the format
+pub fn save_tile_map(ctx: &mut Context, map: &tiled::Map, dest: &str) -> GameResult<()> {
+    let file = ctx.filesystem.create(dest)?;
+    if dest.ends_with(".json") || dest.ends_with(".tmj") {
+        tiled_writer::write_json(map, file)
+    } else {
+        tiled_writer::write_tmx(map, file)
+    }
+}
+
+/// Resolves a path relative to the file at base, as tiled writes them
+/// resolve_path("/maps/town.json", "../tiles/town.tsj") == "/tiles/town.tsj"
+pub fn resolve_path(base: &str, relative: &str) -> String {
+    let mut parts: Vec<&str> = base.split('/').collect();
+    // drop the file name
+    parts.pop();
+    for part in relative.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                if parts.len() > 1 {
+                    parts.pop();
+                }
+            }
+            _ => parts.push(part),
+        }
+    }
+    let path = parts.join("/");
+    if path.starts_with('/') {
+        path
+    } else {
+        format!("/{}", path)
+    }
+}
+
+/// uvs shrunk by texels on every side, for an i_width by i_height image
+/// keeps sampling from reaching into neighbouring tiles, draw them scaled back to the tile size
+pub fn inset_uvs(uvs: &[Rect], i_width: f32, i_height: f32, texels: f32) -> Vec<Rect> {
+    let (dx, dy) = (texels / i_width, texels / i_height);
+    uvs.iter()
+        .map(|uv| Rect::new(uv.x + dx, uv.y + dy, uv.w - dx * 2.0, uv.h - dy * 2.0))
+        .collect()
 }
 
 pub fn generate_uvs(i_width: f32, i_height: f32, t_width: f32, t_height: f32) -> Vec<Rect> {